@@ -0,0 +1,80 @@
+use crate::rayhit::Ray;
+use crate::vec3::Point3;
+
+/// Axis aligned bounding box.
+#[derive(Copy, Clone, Debug)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    pub fn new(min: Point3, max: Point3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    /// The smallest box containing both boxes.
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point3::new(
+                f32::min(self.min.x, other.min.x),
+                f32::min(self.min.y, other.min.y),
+                f32::min(self.min.z, other.min.z),
+            ),
+            max: Point3::new(
+                f32::max(self.max.x, other.max.x),
+                f32::max(self.max.y, other.max.y),
+                f32::max(self.max.z, other.max.z),
+            ),
+        }
+    }
+
+    /// The box both boxes overlap in, empty if they don't.
+    pub fn intersection(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point3::new(
+                f32::max(self.min.x, other.min.x),
+                f32::max(self.min.y, other.min.y),
+                f32::max(self.min.z, other.min.z),
+            ),
+            max: Point3::new(
+                f32::min(self.max.x, other.max.x),
+                f32::min(self.max.y, other.max.y),
+                f32::min(self.max.z, other.max.z),
+            ),
+        }
+    }
+
+    pub fn center(&self) -> Point3 {
+        (self.min + self.max) / 2.0
+    }
+
+    /// Slab test. Returns the parametric interval of the ray that lies
+    /// inside the box, clipped to [t_min, t_max].
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+
+        let axes = [
+            (ray.origin.x, ray.direction.x, self.min.x, self.max.x),
+            (ray.origin.y, ray.direction.y, self.min.y, self.max.y),
+            (ray.origin.z, ray.direction.z, self.min.z, self.max.z),
+        ];
+        for (origin, direction, min, max) in axes {
+            let inv_d = 1.0 / direction;
+            let mut t_near = (min - origin) * inv_d;
+            let mut t_far = (max - origin) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t_near, &mut t_far);
+            }
+
+            t0 = f32::max(t0, t_near);
+            t1 = f32::min(t1, t_far);
+            if t1 <= t0 {
+                return None;
+            }
+        }
+
+        Some((t0, t1))
+    }
+}
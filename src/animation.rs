@@ -0,0 +1,269 @@
+use std::ops::{Add, Mul, Sub};
+
+use crate::vec3::Point3;
+
+/// Anything a track can blend between, like numbers, points and colors.
+pub trait Animatable:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f32, Output = Self>
+{
+}
+
+impl<T> Animatable for T where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T> {}
+
+/// How a track gets from one key to the next.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Interpolation {
+    /// Straight from one key to the next, changing speed sharply at each
+    /// key.
+    #[default]
+    Linear,
+    /// A smooth curve through the keys, heading at each key toward where the
+    /// keys either side of it are.
+    CatmullRom,
+    /// A cubic Bezier curve between keys, shaped by each key's handles. Keys
+    /// without handles get ones that make the curve a Catmull-Rom spline.
+    Bezier,
+}
+
+/// A value at a point in time.
+#[derive(Copy, Clone, Debug)]
+pub struct Key<T> {
+    /// In seconds.
+    pub time: f32,
+    pub value: T,
+    /// Where the curve's control points are for the Bezier interpolation,
+    /// before and after the key, as offsets from its value.
+    pub handles: Option<(T, T)>,
+}
+
+/// A value changing over time, given by keys. Before the first key and
+/// after the last it holds still.
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    keys: Vec<Key<T>>,
+    pub interpolation: Interpolation,
+}
+
+impl<T: Animatable> Track<T> {
+    pub fn new(interpolation: Interpolation) -> Track<T> {
+        Track {
+            keys: Vec::new(),
+            interpolation,
+        }
+    }
+
+    /// A track from (time, value) keys.
+    pub fn from_keys(interpolation: Interpolation, keys: &[(f32, T)]) -> Track<T> {
+        let mut track = Track::new(interpolation);
+        for &(time, value) in keys {
+            track.add(time, value);
+        }
+        track
+    }
+
+    /// A value that never changes.
+    pub fn constant(value: T) -> Track<T> {
+        Track::from_keys(Interpolation::Linear, &[(0.0, value)])
+    }
+
+    /// Add a key, replacing any already at the same time.
+    pub fn add(&mut self, time: f32, value: T) {
+        self.insert(Key {
+            time,
+            value,
+            handles: None,
+        });
+    }
+
+    /// Add a key with Bezier handles, the offsets from `value` of the
+    /// control points before and after it.
+    pub fn add_with_handles(&mut self, time: f32, value: T, before: T, after: T) {
+        self.insert(Key {
+            time,
+            value,
+            handles: Some((before, after)),
+        });
+    }
+
+    fn insert(&mut self, key: Key<T>) {
+        let i = self.keys.partition_point(|k| k.time < key.time);
+        if i < self.keys.len() && self.keys[i].time == key.time {
+            self.keys[i] = key;
+        } else {
+            self.keys.insert(i, key);
+        }
+    }
+
+    /// How fast the Catmull-Rom spline changes at key `i`, per second.
+    fn tangent(&self, i: usize) -> T {
+        let before = &self.keys[i.saturating_sub(1)];
+        let after = &self.keys[usize::min(i + 1, self.keys.len() - 1)];
+        if after.time == before.time {
+            return before.value * 0.0;
+        }
+        (after.value - before.value) * (1.0 / (after.time - before.time))
+    }
+
+    /// The value at `time` in seconds.
+    pub fn at(&self, time: f32) -> T {
+        assert!(!self.keys.is_empty(), "a track needs at least one key");
+        let last = self.keys.len() - 1;
+        if time <= self.keys[0].time {
+            return self.keys[0].value;
+        }
+        if time >= self.keys[last].time {
+            return self.keys[last].value;
+        }
+
+        // the key starting the segment `time` is in
+        let i = self.keys.partition_point(|k| k.time <= time) - 1;
+        let (k0, k1) = (&self.keys[i], &self.keys[i + 1]);
+        let dt = k1.time - k0.time;
+        let s = (time - k0.time) / dt;
+
+        match self.interpolation {
+            Interpolation::Linear => k0.value + (k1.value - k0.value) * s,
+            Interpolation::CatmullRom => {
+                // cubic Hermite with the spline's tangents
+                let (s2, s3) = (s * s, s * s * s);
+                k0.value * (2.0 * s3 - 3.0 * s2 + 1.0)
+                    + self.tangent(i) * ((s3 - 2.0 * s2 + s) * dt)
+                    + k1.value * (-2.0 * s3 + 3.0 * s2)
+                    + self.tangent(i + 1) * ((s3 - s2) * dt)
+            }
+            Interpolation::Bezier => {
+                let after = match k0.handles {
+                    Some((_, after)) => after,
+                    None => self.tangent(i) * (dt / 3.0),
+                };
+                let before = match k1.handles {
+                    Some((before, _)) => before,
+                    None => self.tangent(i + 1) * (-dt / 3.0),
+                };
+                let p1 = k0.value + after;
+                let p2 = k1.value + before;
+
+                let r = 1.0 - s;
+                k0.value * (r * r * r)
+                    + p1 * (3.0 * r * r * s)
+                    + p2 * (3.0 * r * s * s)
+                    + k1.value * (s * s * s)
+            }
+        }
+    }
+}
+
+/// Where a camera is and what it's doing at one moment.
+#[derive(Copy, Clone, Debug)]
+pub struct CameraPose {
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    /// Vertical field of view in degrees, for the cameras that have one.
+    pub vfov: f32,
+    /// None leaves it up to the camera.
+    pub focus_distance: Option<f32>,
+}
+
+/// A camera moving through the scene.
+#[derive(Clone, Debug)]
+pub struct CameraAnimation {
+    pub lookfrom: Track<Point3>,
+    pub lookat: Track<Point3>,
+    pub vfov: Track<f32>,
+    /// Left up to the camera if this isn't set.
+    pub focus_distance: Option<Track<f32>>,
+}
+
+impl CameraAnimation {
+    /// A camera that stays put.
+    pub fn still(lookfrom: Point3, lookat: Point3, vfov: f32) -> CameraAnimation {
+        CameraAnimation {
+            lookfrom: Track::constant(lookfrom),
+            lookat: Track::constant(lookat),
+            vfov: Track::constant(vfov),
+            focus_distance: None,
+        }
+    }
+
+    pub fn at(&self, time: f32) -> CameraPose {
+        CameraPose {
+            lookfrom: self.lookfrom.at(time),
+            lookat: self.lookat.at(time),
+            vfov: self.vfov.at(time),
+            focus_distance: self.focus_distance.as_ref().map(|track| track.at(time)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        f32::abs(a - b) < 1e-4
+    }
+
+    #[test]
+    fn test_linear_track() {
+        let track = Track::from_keys(Interpolation::Linear, &[(2.0, 10.0), (0.0, 0.0)]);
+        assert_eq!(track.at(-1.0), 0.0);
+        assert!(close(track.at(0.5), 2.5));
+        assert!(close(track.at(1.5), 7.5));
+        assert_eq!(track.at(3.0), 10.0);
+
+        // a key at the same time replaces the old one
+        let mut track = track;
+        track.add(2.0, 4.0);
+        assert!(close(track.at(1.0), 2.0));
+    }
+
+    #[test]
+    fn test_smooth_tracks() {
+        let keys = [(0.0, 0.0), (1.0, 1.0), (2.0, 4.0), (3.0, 9.0)];
+        let catmull_rom = Track::from_keys(Interpolation::CatmullRom, &keys);
+        let bezier = Track::from_keys(Interpolation::Bezier, &keys);
+
+        for i in 0..=30 {
+            let time = i as f32 / 10.0;
+            // both go through the keys, and without handles they're the
+            // same curve
+            if i % 10 == 0 {
+                assert!(close(catmull_rom.at(time), time * time));
+            }
+            assert!(close(catmull_rom.at(time), bezier.at(time)));
+        }
+        // and they bend with the keys rather than cutting across
+        assert!(catmull_rom.at(1.5) < 2.5);
+
+        // flat handles ease in and out, so it starts and ends slowly
+        let mut eased = Track::new(Interpolation::Bezier);
+        eased.add_with_handles(0.0, 0.0, 0.0, 0.0);
+        eased.add_with_handles(1.0, 1.0, 0.0, 0.0);
+        assert!(close(eased.at(0.5), 0.5));
+        assert!(eased.at(0.1) < 0.1 && eased.at(0.9) > 0.9);
+    }
+
+    #[test]
+    fn test_camera_animation() {
+        let mut animation = CameraAnimation::still(
+            Point3::new(0.0, 0.0, 10.0),
+            Point3::new(0.0, 0.0, 0.0),
+            20.0,
+        );
+        animation.lookfrom = Track::from_keys(
+            Interpolation::Linear,
+            &[
+                (0.0, Point3::new(0.0, 0.0, 10.0)),
+                (1.0, Point3::new(0.0, 0.0, 4.0)),
+            ],
+        );
+
+        let pose = animation.at(0.5);
+        assert_eq!(pose.lookfrom, Point3::new(0.0, 0.0, 7.0));
+        assert_eq!(pose.vfov, 20.0);
+        assert_eq!(pose.focus_distance, None);
+
+        animation.focus_distance = Some(Track::constant(3.0));
+        assert_eq!(animation.at(0.5).focus_distance, Some(3.0));
+    }
+}
@@ -0,0 +1,125 @@
+use std::f32::consts::PI;
+use std::io::Error;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::distribution::Distribution2D;
+use crate::texture::ImageTexture;
+
+/// The shape of a lens's opening, which out of focus highlights take on.
+#[derive(Clone, Debug)]
+pub enum Aperture {
+    Circle,
+    /// A regular polygon from straight diaphragm blades, with its corners
+    /// on the circle. The rotation is in degrees.
+    Polygon {
+        blades: u32,
+        rotation: f32,
+    },
+    /// A mask from a square image, letting through light in proportion to
+    /// its brightness. Other shapes are stretched to fill the square.
+    Image(Rc<Distribution2D>),
+}
+
+impl Aperture {
+    /// Load a mask from a PNG.
+    pub fn load(path: &Path) -> Result<Aperture, Error> {
+        let image = ImageTexture::load(path, false)?;
+        Ok(Aperture::from_image(&image))
+    }
+
+    pub fn from_image(image: &ImageTexture) -> Aperture {
+        let mut func = Vec::with_capacity(image.width() * image.height());
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                let c = image.texel(x as i64, y as i64);
+                func.push((c.x + c.y + c.z) / 3.0);
+            }
+        }
+
+        Aperture::Image(Rc::new(Distribution2D::new(&func, image.width())))
+    }
+
+    /// Pick a point on the opening, within the unit circle for circles and
+    /// polygons or the square [-1, 1]^2 for images, with u1 and u2 uniform
+    /// in [0, 1).
+    pub fn sample(&self, u1: f32, u2: f32) -> (f32, f32) {
+        match self {
+            Aperture::Circle => {
+                let r = f32::sqrt(u1);
+                let phi = 2.0 * PI * u2;
+                (r * f32::cos(phi), r * f32::sin(phi))
+            }
+            Aperture::Polygon { blades, rotation } => {
+                // a triangle from the center to one of the sides, they're
+                // all the same size
+                let n = u32::max(*blades, 3);
+                let k = u32::min((u1 * n as f32) as u32, n - 1);
+                let u1 = u1 * n as f32 - k as f32;
+                let corner = |i: u32| {
+                    let angle = rotation.to_radians() + 2.0 * PI * i as f32 / n as f32;
+                    (f32::cos(angle), f32::sin(angle))
+                };
+                let (a, b) = (corner(k), corner(k + 1));
+
+                let r = f32::sqrt(u1);
+                (
+                    r * ((1.0 - u2) * a.0 + u2 * b.0),
+                    r * ((1.0 - u2) * a.1 + u2 * b.1),
+                )
+            }
+            Aperture::Image(distribution) => {
+                // rows run from the top
+                let (x, y, _) = distribution.sample(u1, u2);
+                (2.0 * x - 1.0, 1.0 - 2.0 * y)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Color;
+
+    fn samples(aperture: &Aperture) -> Vec<(f32, f32)> {
+        let n = 20;
+        let mut points = Vec::new();
+        for i in 0..n {
+            for j in 0..n {
+                let (u1, u2) = ((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+                points.push(aperture.sample(u1, u2));
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn test_polygon_aperture() {
+        // a square with its corners on the axes, so |x| + |y| <= 1
+        let square = Aperture::Polygon {
+            blades: 4,
+            rotation: 0.0,
+        };
+        for (x, y) in samples(&square) {
+            assert!(f32::abs(x) + f32::abs(y) <= 1.0 + 1e-5);
+        }
+
+        for (x, y) in samples(&Aperture::Circle) {
+            assert!(x * x + y * y <= 1.0 + 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_image_aperture() {
+        // only the top right quarter lets light through
+        let black = Color::new(0.0, 0.0, 0.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let image = ImageTexture::new(2, 2, vec![black, white, black, black]);
+        let aperture = Aperture::from_image(&image);
+
+        for (x, y) in samples(&aperture) {
+            assert!((0.0..=1.0).contains(&x) && (0.0..=1.0).contains(&y));
+        }
+    }
+}
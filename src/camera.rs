@@ -1,23 +1,78 @@
 #![deny(clippy::all)]
 #![forbid(unsafe_code)]
 
+use std::f32::consts::PI;
+
+use rand::{Rng, RngCore};
+
+use crate::aperture::Aperture;
 // This seems incorrect.
 use crate::vec3::{Point3, Vec3};
 use crate::rayhit::Ray;
 
+/// Turns points on the image into rays into the scene. s runs from the
+/// left of the image to the right and t from the bottom to the top, both
+/// in [0, 1].
+pub trait Camera {
+    /// The ray through (s, t), or None if that part of the image doesn't
+    /// see anything, like the corners outside a fisheye's circle.
+    fn get_ray(&self, s: f32, t: f32, rng: &mut dyn RngCore) -> Option<Ray>;
+
+    /// The ray through (s, t) and how much of the light along it reaches
+    /// the image, for cameras that darken toward the edges.
+    fn get_weighted_ray(&self, s: f32, t: f32, rng: &mut dyn RngCore) -> Option<(Ray, f32)> {
+        self.get_ray(s, t, rng).map(|ray| (ray, 1.0))
+    }
+
+    /// What the light reaching the image is multiplied by.
+    fn exposure(&self) -> f32 {
+        1.0
+    }
+}
+
+/// Which way a camera at `lookfrom` faces: u to the right, v up and w
+/// backward, away from `lookat`.
+#[derive(Copy, Clone, Debug, Default)]
+struct Frame {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Frame {
+    fn new(lookfrom: Point3, lookat: Point3, vup: Vec3) -> Frame {
+        let w = (lookfrom - lookat).unit_vector();
+        let u = vup.cross(w).unit_vector();
+        let v = w.cross(u);
+        Frame {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+        }
+    }
+
+    /// A direction given as right, up and forward parts.
+    fn direction(&self, right: f32, up: f32, forward: f32) -> Vec3 {
+        self.u * right + self.v * up - self.w * forward
+    }
+}
+
+/// A pinhole camera, or a thin lens one with depth of field when the
+/// aperture isn't zero.
 #[derive(Copy, Clone, Debug, Default)]
-pub struct Camera {
+pub struct PerspectiveCamera {
     origin: Point3,
     lower_left_corner: Point3,
     horizontal: Vec3,
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
-    w: Vec3,
     lens_radius: f32,
 }
 
-impl Camera {
+impl PerspectiveCamera {
     pub fn new(
         lookfrom: Point3,
         lookat: Point3,
@@ -26,7 +81,7 @@ impl Camera {
         aspect_ratio: f32,
         aperture: f32,
         focus_dist: f32,
-    ) -> Camera {
+    ) -> PerspectiveCamera {
         let theta = vfov.to_radians();
         let h = (theta / 2.0).tan();
         let viewport_height = 2.0 * h;
@@ -42,26 +97,645 @@ impl Camera {
         let vertical = v * focus_dist * viewport_height;
         let lower_left_corner = origin - (horizontal / 2.0) - (vertical / 2.0) - (w * focus_dist);
 
-        Camera {
+        PerspectiveCamera {
             origin,
             lower_left_corner,
             horizontal,
             vertical,
             u,
             v,
-            w,
             lens_radius: (aperture / 2.0),
         }
     }
+}
 
-    pub fn get_ray(self, s: f32, t: f32) -> Ray {
-        let rd = Vec3::random_in_unit_disk() * self.lens_radius;
+impl Camera for PerspectiveCamera {
+    fn get_ray(&self, s: f32, t: f32, rng: &mut dyn RngCore) -> Option<Ray> {
+        let rd = Vec3::random_in_unit_disk(rng) * self.lens_radius;
         let offset = self.u * rd.x + self.v * rd.y;
-        Ray {
+        Some(Ray {
             origin: self.origin + offset,
             direction: self.lower_left_corner + (self.horizontal * s) + (self.vertical * t)
                 - self.origin
                 - offset,
+        })
+    }
+}
+
+/// Parallel rays from a rectangle, so things don't shrink with distance.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct OrthographicCamera {
+    frame: Frame,
+    width: f32,
+    height: f32,
+}
+
+impl OrthographicCamera {
+    /// `view_height` is how much of the scene fits in the height of the
+    /// image.
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        view_height: f32,
+        aspect_ratio: f32,
+    ) -> OrthographicCamera {
+        OrthographicCamera {
+            frame: Frame::new(lookfrom, lookat, vup),
+            width: view_height * aspect_ratio,
+            height: view_height,
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, s: f32, t: f32, _rng: &mut dyn RngCore) -> Option<Ray> {
+        let frame = &self.frame;
+        Some(Ray {
+            origin: frame.origin
+                + frame.u * ((s - 0.5) * self.width)
+                + frame.v * ((t - 0.5) * self.height),
+            direction: -frame.w,
+        })
+    }
+}
+
+/// How a fisheye lens spreads angles from the axis over its image circle.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FisheyeProjection {
+    /// Distance from the center goes up evenly with the angle.
+    Equidistant,
+    /// Equal solid angles cover equal areas of the image.
+    Equisolid,
+}
+
+/// A fisheye lens with its image circle fitting the height of the image.
+#[derive(Copy, Clone, Debug)]
+pub struct FisheyeCamera {
+    frame: Frame,
+    aspect_ratio: f32,
+    /// Half the field of view across the circle, in radians.
+    theta_max: f32,
+    projection: FisheyeProjection,
+}
+
+impl FisheyeCamera {
+    /// `fov` is the angle across the image circle in degrees, up to 360.
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        fov: f32,
+        aspect_ratio: f32,
+        projection: FisheyeProjection,
+    ) -> FisheyeCamera {
+        FisheyeCamera {
+            frame: Frame::new(lookfrom, lookat, vup),
+            aspect_ratio,
+            theta_max: (fov.clamp(0.0, 360.0) / 2.0).to_radians(),
+            projection,
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, s: f32, t: f32, _rng: &mut dyn RngCore) -> Option<Ray> {
+        // position on the image circle, which has radius one
+        let x = (2.0 * s - 1.0) * self.aspect_ratio;
+        let y = 2.0 * t - 1.0;
+        let r = f32::sqrt(x * x + y * y);
+        if r > 1.0 {
+            return None;
+        }
+
+        let theta = match self.projection {
+            FisheyeProjection::Equidistant => r * self.theta_max,
+            FisheyeProjection::Equisolid => 2.0 * f32::asin(r * f32::sin(self.theta_max / 2.0)),
+        };
+        let phi = f32::atan2(y, x);
+
+        Some(Ray {
+            origin: self.frame.origin,
+            direction: self.frame.direction(
+                f32::sin(theta) * f32::cos(phi),
+                f32::sin(theta) * f32::sin(phi),
+                f32::cos(theta),
+            ),
+        })
+    }
+}
+
+/// A 360 degree panorama, with longitude across the image and latitude up
+/// it. The middle of the image looks at `lookat`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct EquirectangularCamera {
+    frame: Frame,
+}
+
+impl EquirectangularCamera {
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3) -> EquirectangularCamera {
+        EquirectangularCamera {
+            frame: Frame::new(lookfrom, lookat, vup),
+        }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, s: f32, t: f32, _rng: &mut dyn RngCore) -> Option<Ray> {
+        let longitude = (s - 0.5) * 2.0 * PI;
+        let latitude = (t - 0.5) * PI;
+
+        Some(Ray {
+            origin: self.frame.origin,
+            direction: self.frame.direction(
+                f32::cos(latitude) * f32::sin(longitude),
+                f32::sin(latitude),
+                f32::cos(latitude) * f32::cos(longitude),
+            ),
+        })
+    }
+}
+
+/// The settings of a real camera. Lengths on the camera are in
+/// millimeters, and the scene is taken to be in meters.
+#[derive(Clone, Debug)]
+pub struct PhysicalSettings {
+    /// The focal length over the diameter of the opening.
+    pub f_number: f32,
+    pub focal_length: f32,
+    /// The height of the sensor follows from the aspect ratio of the image.
+    pub sensor_width: f32,
+    /// How long the shutter is open, in seconds.
+    pub shutter: f32,
+    /// The sensitivity of the sensor.
+    pub iso: f32,
+    pub aperture: Aperture,
+}
+
+/// A full frame camera with a short telephoto lens, exposed for the
+/// renderer's usual brightness.
+impl Default for PhysicalSettings {
+    fn default() -> PhysicalSettings {
+        PhysicalSettings {
+            f_number: 4.0,
+            focal_length: 70.0,
+            sensor_width: 36.0,
+            shutter: 1.0 / 1600.0,
+            iso: 100.0,
+            aperture: Aperture::Circle,
+        }
+    }
+}
+
+impl PhysicalSettings {
+    /// Scene radiance is taken to be in units where a sunlit scene is
+    /// around one. The "sunny 16" rule says that's exposed by f/16 with a
+    /// shutter of one over the ISO, which is set to give an exposure of
+    /// one.
+    pub fn exposure(&self) -> f32 {
+        self.shutter * self.iso * 256.0 / (self.f_number * self.f_number)
+    }
+}
+
+/// A thin lens camera set up like a real one. The focal length and sensor
+/// size give the field of view, the f-number gives the depth of field, and
+/// with the shutter and ISO it also gives the exposure.
+#[derive(Clone, Debug)]
+pub struct PhysicalCamera {
+    frame: Frame,
+    /// Half the size of the image where it's in focus.
+    half_width: f32,
+    half_height: f32,
+    focus_distance: f32,
+    lens_radius: f32,
+    aperture: Aperture,
+    exposure: f32,
+}
+
+impl PhysicalCamera {
+    /// A camera focused at `focus_distance` meters.
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        aspect_ratio: f32,
+        focus_distance: f32,
+        settings: &PhysicalSettings,
+    ) -> PhysicalCamera {
+        let focal_length = settings.focal_length / 1000.0;
+        let focus_distance = f32::max(focus_distance, focal_length * 1.001);
+        // the sensor sits further back than the focal length to focus
+        // closer than infinity
+        let image_distance = focal_length * focus_distance / (focus_distance - focal_length);
+        let magnification = focus_distance / image_distance;
+        let sensor_width = settings.sensor_width / 1000.0;
+        let sensor_height = sensor_width / aspect_ratio;
+
+        PhysicalCamera {
+            frame: Frame::new(lookfrom, lookat, vup),
+            half_width: sensor_width / 2.0 * magnification,
+            half_height: sensor_height / 2.0 * magnification,
+            focus_distance,
+            lens_radius: focal_length / settings.f_number / 2.0,
+            aperture: settings.aperture.clone(),
+            exposure: settings.exposure(),
+        }
+    }
+}
+
+impl Camera for PhysicalCamera {
+    fn get_ray(&self, s: f32, t: f32, rng: &mut dyn RngCore) -> Option<Ray> {
+        let (x, y) = self.aperture.sample(rng.gen(), rng.gen());
+        let frame = &self.frame;
+        let origin =
+            frame.origin + frame.u * (x * self.lens_radius) + frame.v * (y * self.lens_radius);
+        let focus = frame.origin
+            + frame.direction(
+                (2.0 * s - 1.0) * self.half_width,
+                (2.0 * t - 1.0) * self.half_height,
+                self.focus_distance,
+            );
+
+        Some(Ray {
+            origin,
+            direction: focus - origin,
+        })
+    }
+
+    fn exposure(&self) -> f32 {
+        self.exposure
+    }
+}
+
+/// How the two eyes' images share the picture.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum StereoLayout {
+    /// Left eye on the left half.
+    #[default]
+    SideBySide,
+    /// Left eye on the top half.
+    TopBottom,
+}
+
+/// How the eyes of a stereo camera are set up.
+#[derive(Copy, Clone, Debug)]
+pub struct StereoSettings {
+    pub layout: StereoLayout,
+    /// The interpupillary distance, between the eyes.
+    pub ipd: f32,
+    /// How far away the eyes' views line up, the distance to `lookat` if
+    /// not given.
+    pub convergence: Option<f32>,
+}
+
+/// An average adult's eyes, in meters.
+impl Default for StereoSettings {
+    fn default() -> StereoSettings {
+        StereoSettings {
+            layout: StereoLayout::SideBySide,
+            ipd: 0.064,
+            convergence: None,
+        }
+    }
+}
+
+/// What each eye of a stereo camera sees.
+#[derive(Copy, Clone, Debug)]
+pub enum StereoProjection {
+    /// A flat image, with the vertical field of view in degrees.
+    Perspective { vfov: f32 },
+    /// A 360 degree panorama with omni-directional stereo: every direction
+    /// is seen from eyes turned to face it, so the depth looks right
+    /// whichever way the viewer looks.
+    Equirectangular,
+}
+
+/// Two cameras side by side for viewing in stereo, from eyes `ipd` apart.
+/// Both eyes' views line up at the convergence distance, nearer things
+/// seem to come out of the screen and further ones go into it.
+#[derive(Copy, Clone, Debug)]
+pub struct StereoCamera {
+    frame: Frame,
+    projection: StereoProjection,
+    layout: StereoLayout,
+    /// Half the size of each eye's image at the convergence distance, for
+    /// perspective.
+    half_width: f32,
+    half_height: f32,
+    ipd: f32,
+    convergence: f32,
+}
+
+impl StereoCamera {
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        aspect_ratio: f32,
+        projection: StereoProjection,
+        settings: &StereoSettings,
+    ) -> StereoCamera {
+        let layout = settings.layout;
+        let convergence = settings
+            .convergence
+            .unwrap_or_else(|| (lookat - lookfrom).length());
+        // each eye gets half the image
+        let eye_aspect_ratio = match layout {
+            StereoLayout::SideBySide => aspect_ratio / 2.0,
+            StereoLayout::TopBottom => aspect_ratio * 2.0,
+        };
+        let half_height = match projection {
+            StereoProjection::Perspective { vfov } => {
+                f32::tan(vfov.to_radians() / 2.0) * convergence
+            }
+            StereoProjection::Equirectangular => 0.0,
+        };
+
+        StereoCamera {
+            frame: Frame::new(lookfrom, lookat, vup),
+            projection,
+            layout,
+            half_width: half_height * eye_aspect_ratio,
+            half_height,
+            ipd: settings.ipd,
+            convergence,
+        }
+    }
+
+    /// Which eye sees (s, t), -1 for the left and 1 for the right, and
+    /// where it lands on that eye's image.
+    fn eye(&self, s: f32, t: f32) -> (f32, f32, f32) {
+        match self.layout {
+            StereoLayout::SideBySide if s < 0.5 => (-1.0, s * 2.0, t),
+            StereoLayout::SideBySide => (1.0, s * 2.0 - 1.0, t),
+            StereoLayout::TopBottom if t >= 0.5 => (-1.0, s, t * 2.0 - 1.0),
+            StereoLayout::TopBottom => (1.0, s, t * 2.0),
+        }
+    }
+}
+
+impl Camera for StereoCamera {
+    fn get_ray(&self, s: f32, t: f32, _rng: &mut dyn RngCore) -> Option<Ray> {
+        let (eye, s, t) = self.eye(s, t);
+        let frame = &self.frame;
+
+        // Where the middle of the head sees the point, and a sideways
+        // direction for moving to the eye.
+        let (view, sideways) = match self.projection {
+            StereoProjection::Perspective { .. } => (
+                frame.direction(
+                    (2.0 * s - 1.0) * self.half_width,
+                    (2.0 * t - 1.0) * self.half_height,
+                    self.convergence,
+                ),
+                frame.u,
+            ),
+            StereoProjection::Equirectangular => {
+                let longitude = (s - 0.5) * 2.0 * PI;
+                let latitude = (t - 0.5) * PI;
+                let direction = frame.direction(
+                    f32::cos(latitude) * f32::sin(longitude),
+                    f32::sin(latitude),
+                    f32::cos(latitude) * f32::cos(longitude),
+                );
+                // The eyes come together toward the poles, where there's no
+                // telling which way is sideways.
+                let sideways = frame.direction(f32::cos(longitude), 0.0, -f32::sin(longitude))
+                    * f32::cos(latitude);
+                (direction * self.convergence, sideways)
+            }
+        };
+
+        let offset = sideways * (eye * self.ipd / 2.0);
+        Some(Ray {
+            origin: frame.origin + offset,
+            direction: view - offset,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a.unit_vector() - b.unit_vector()).length() < 1e-5
+    }
+
+    // all looking down -z from the origin, with y up
+    const LOOKFROM: Point3 = Point3 {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+    const LOOKAT: Point3 = Point3 {
+        x: 0.0,
+        y: 0.0,
+        z: -1.0,
+    };
+    const VUP: Vec3 = Vec3 {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+    };
+
+    #[test]
+    fn test_perspective_camera() {
+        let mut rng = rand::thread_rng();
+        let camera = PerspectiveCamera::new(LOOKFROM, LOOKAT, VUP, 90.0, 2.0, 0.0, 1.0);
+        let center = camera.get_ray(0.5, 0.5, &mut rng).unwrap();
+        assert!(close(center.direction, Vec3::new(0.0, 0.0, -1.0)));
+
+        // 45 degrees up at the top edge, twice as wide as high
+        let corner = camera.get_ray(1.0, 1.0, &mut rng).unwrap();
+        assert!(close(corner.direction, Vec3::new(2.0, 1.0, -1.0)));
+    }
+
+    #[test]
+    fn test_orthographic_camera() {
+        let mut rng = rand::thread_rng();
+        let camera = OrthographicCamera::new(LOOKFROM, LOOKAT, VUP, 4.0, 1.5);
+        let corner = camera.get_ray(0.0, 1.0, &mut rng).unwrap();
+        assert_eq!(corner.origin, Point3::new(-3.0, 2.0, 0.0));
+        assert_eq!(corner.direction, Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(
+            camera.get_ray(0.5, 0.5, &mut rng).unwrap().origin,
+            Point3::new(0.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_fisheye_camera() {
+        let mut rng = rand::thread_rng();
+        for projection in [FisheyeProjection::Equidistant, FisheyeProjection::Equisolid] {
+            let camera = FisheyeCamera::new(LOOKFROM, LOOKAT, VUP, 180.0, 1.0, projection);
+            let center = camera.get_ray(0.5, 0.5, &mut rng).unwrap();
+            assert!(close(center.direction, Vec3::new(0.0, 0.0, -1.0)));
+
+            // the edge of the circle is 90 degrees off the axis
+            let right = camera.get_ray(1.0, 0.5, &mut rng).unwrap();
+            assert!(close(right.direction, Vec3::new(1.0, 0.0, 0.0)));
+            let bottom = camera.get_ray(0.5, 0.0, &mut rng).unwrap();
+            assert!(close(bottom.direction, Vec3::new(0.0, -1.0, 0.0)));
+
+            // nothing in the corners
+            assert!(camera.get_ray(0.0, 0.0, &mut rng).is_none());
+        }
+
+        // halfway out is 45 degrees for equidistant, less for equisolid
+        let equidistant = FisheyeCamera::new(
+            LOOKFROM,
+            LOOKAT,
+            VUP,
+            180.0,
+            1.0,
+            FisheyeProjection::Equidistant,
+        );
+        let half = equidistant.get_ray(0.75, 0.5, &mut rng).unwrap();
+        assert!(close(half.direction, Vec3::new(1.0, 0.0, -1.0)));
+
+        let equisolid = FisheyeCamera::new(
+            LOOKFROM,
+            LOOKAT,
+            VUP,
+            180.0,
+            1.0,
+            FisheyeProjection::Equisolid,
+        );
+        let half = equisolid
+            .get_ray(0.75, 0.5, &mut rng)
+            .unwrap()
+            .direction
+            .unit_vector();
+        let theta = 2.0 * f32::asin(0.5 * f32::sin(PI / 4.0));
+        assert!(f32::abs(half.x - f32::sin(theta)) < 1e-5);
+    }
+
+    #[test]
+    fn test_physical_camera() {
+        let mut rng = rand::thread_rng();
+        let settings = PhysicalSettings {
+            f_number: 16.0,
+            shutter: 1.0 / 100.0,
+            aperture: Aperture::Polygon {
+                blades: 6,
+                rotation: 0.0,
+            },
+            ..PhysicalSettings::default()
+        };
+        assert!(f32::abs(settings.exposure() - 1.0) < 1e-6);
+
+        let camera = PhysicalCamera::new(LOOKFROM, LOOKAT, VUP, 1.5, 5.0, &settings);
+        // every ray through a pixel meets on the focus plane, within the
+        // sensor's view of it
+        let focus = Point3::new(0.0, 0.0, -5.0);
+        for _ in 0..10 {
+            let ray = camera.get_ray(0.5, 0.5, &mut rng).unwrap();
+            assert!((ray.origin - LOOKFROM).length() <= 70.0 / 16.0 / 2.0 / 1000.0 + 1e-6);
+            let t = -5.0 / ray.direction.z;
+            assert!((ray.at(t) - focus).length() < 1e-4);
+        }
+
+        // a 70mm lens on a 36mm sensor sees about 29 degrees across when
+        // focused far away
+        let far = PhysicalCamera::new(LOOKFROM, LOOKAT, VUP, 1.5, 1e6, &settings);
+        let right = far.get_ray(1.0, 0.5, &mut rng).unwrap().direction;
+        let angle = f32::atan2(right.x, -right.z).to_degrees();
+        assert!(f32::abs(2.0 * angle - 28.8) < 0.1);
+    }
+
+    #[test]
+    fn test_stereo_camera() {
+        let mut rng = rand::thread_rng();
+        let perspective = StereoProjection::Perspective { vfov: 90.0 };
+        let camera = StereoCamera::new(
+            LOOKFROM,
+            LOOKAT,
+            VUP,
+            2.0,
+            perspective,
+            &StereoSettings {
+                convergence: Some(2.0),
+                ..StereoSettings::default()
+            },
+        );
+
+        // the middle of each half looks at the point the eyes converge on
+        let converge = Point3::new(0.0, 0.0, -2.0);
+        let left = camera.get_ray(0.25, 0.5, &mut rng).unwrap();
+        let right = camera.get_ray(0.75, 0.5, &mut rng).unwrap();
+        assert_eq!(left.origin, Point3::new(-0.032, 0.0, 0.0));
+        assert_eq!(right.origin, Point3::new(0.032, 0.0, 0.0));
+        assert!((left.at(1.0) - converge).length() < 1e-6);
+        assert!((right.at(1.0) - converge).length() < 1e-6);
+
+        // and each eye's image is square
+        let corner = camera.get_ray(0.5 - 1e-7, 1.0, &mut rng).unwrap();
+        assert!(close(corner.at(1.0) - LOOKFROM, Vec3::new(2.0, 2.0, -2.0)));
+
+        let camera = StereoCamera::new(
+            LOOKFROM,
+            LOOKAT,
+            VUP,
+            2.0,
+            perspective,
+            &StereoSettings {
+                layout: StereoLayout::TopBottom,
+                convergence: Some(2.0),
+                ..StereoSettings::default()
+            },
+        );
+        assert_eq!(
+            camera.get_ray(0.5, 0.75, &mut rng).unwrap().origin.x,
+            -0.032
+        );
+        assert_eq!(camera.get_ray(0.5, 0.25, &mut rng).unwrap().origin.x, 0.032);
+    }
+
+    #[test]
+    fn test_stereo_panorama() {
+        let mut rng = rand::thread_rng();
+        let camera = StereoCamera::new(
+            LOOKFROM,
+            LOOKAT,
+            VUP,
+            1.0,
+            StereoProjection::Equirectangular,
+            &StereoSettings {
+                layout: StereoLayout::TopBottom,
+                convergence: Some(1e6),
+                ..StereoSettings::default()
+            },
+        );
+
+        // looking right, the left eye is in front and the right behind
+        let left = camera.get_ray(0.75, 0.75, &mut rng).unwrap();
+        let right = camera.get_ray(0.75, 0.25, &mut rng).unwrap();
+        assert!(close(left.direction, Vec3::new(1.0, 0.0, 0.0)));
+        assert!(close(right.direction, Vec3::new(1.0, 0.0, 0.0)));
+        assert!((left.origin - Point3::new(0.0, 0.0, -0.032)).length() < 1e-6);
+        assert!((right.origin - Point3::new(0.0, 0.0, 0.032)).length() < 1e-6);
+
+        // straight up both eyes are in the middle
+        let up = camera.get_ray(0.3, 1.0, &mut rng).unwrap();
+        assert!((up.origin - LOOKFROM).length() < 1e-6);
+    }
+
+    #[test]
+    fn test_equirectangular_camera() {
+        let mut rng = rand::thread_rng();
+        let camera = EquirectangularCamera::new(LOOKFROM, LOOKAT, VUP);
+        let mapping = [
+            ((0.5, 0.5), Vec3::new(0.0, 0.0, -1.0)),
+            ((0.75, 0.5), Vec3::new(1.0, 0.0, 0.0)),
+            ((0.25, 0.5), Vec3::new(-1.0, 0.0, 0.0)),
+            ((0.0, 0.5), Vec3::new(0.0, 0.0, 1.0)),
+            ((0.3, 1.0), Vec3::new(0.0, 1.0, 0.0)),
+            ((0.5, 0.75), Vec3::new(0.0, 1.0, -1.0)),
+        ];
+        for ((s, t), expected) in mapping {
+            let ray = camera.get_ray(s, t, &mut rng).unwrap();
+            assert!(close(ray.direction, expected), "{} {}", s, t);
         }
     }
 }
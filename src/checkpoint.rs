@@ -0,0 +1,205 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+use crate::vec3::Color;
+
+/// What a checkpoint file starts with, changed whenever the layout does.
+const MAGIC: &[u8; 8] = b"RTCKPT02";
+
+/// Longest settings string a checkpoint is read with, well past any
+/// command line.
+const MAX_SETTINGS_LEN: u32 = 1 << 16;
+
+/// A render partway through, saved so that it can be carried on with. The
+/// image is rendered in passes of one sample for every pixel, with all of a
+/// pass's random numbers drawn from a generator seeded by `seed` and the
+/// pass's index, so carrying on from a checkpoint gives exactly the same
+/// image as never having stopped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    pub width: u32,
+    pub height: u32,
+    /// How many passes the finished render has.
+    pub samples_per_pixel: u32,
+    /// How many passes are in `sums`.
+    pub passes: u32,
+    /// Where the render's random numbers start from.
+    pub seed: u64,
+    /// The arguments the render was started with, so that a different
+    /// render isn't carried on from it.
+    pub settings: String,
+    /// Radiance added up over the passes, row by row from the top.
+    pub sums: Vec<Color>,
+}
+
+impl Checkpoint {
+    pub fn new(
+        width: u32,
+        height: u32,
+        samples_per_pixel: u32,
+        seed: u64,
+        settings: String,
+    ) -> Checkpoint {
+        Checkpoint {
+            width,
+            height,
+            samples_per_pixel,
+            passes: 0,
+            seed,
+            settings,
+            sums: vec![Color::new(0.0, 0.0, 0.0); (width * height) as usize],
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.passes >= self.samples_per_pixel
+    }
+
+    /// Whether this can be carried on with for a render with the given
+    /// settings. The size is checked when it's read.
+    pub fn matches(&self, samples_per_pixel: u32, seed: u64, settings: &str) -> bool {
+        self.samples_per_pixel == samples_per_pixel
+            && self.seed == seed
+            && self.settings == settings
+    }
+
+    /// Load a checkpoint for an image `width` by `height`.
+    pub fn load(path: &Path, width: u32, height: u32) -> Result<Checkpoint, Error> {
+        Checkpoint::read_from(&mut BufReader::new(File::open(path)?), width, height)
+    }
+
+    /// Save to `path`, by way of a file beside it so that being killed
+    /// partway through doesn't lose the last checkpoint.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let partial = path.with_extension("partial");
+        {
+            let mut w = BufWriter::new(File::create(&partial)?);
+            self.write_to(&mut w)?;
+            w.flush()?;
+        }
+        fs::rename(partial, path)
+    }
+
+    pub fn write_to(&self, w: &mut impl Write) -> Result<(), Error> {
+        w.write_all(MAGIC)?;
+        for n in [
+            self.width,
+            self.height,
+            self.samples_per_pixel,
+            self.passes,
+            self.settings.len() as u32,
+        ] {
+            w.write_all(&n.to_le_bytes())?;
+        }
+        w.write_all(&self.seed.to_le_bytes())?;
+        w.write_all(self.settings.as_bytes())?;
+        for c in &self.sums {
+            for v in [c.x, c.y, c.z] {
+                w.write_all(&v.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a checkpoint for an image `width` by `height`, which is checked
+    /// before anything the size of the image is allocated.
+    pub fn read_from(r: &mut impl Read, width: u32, height: u32) -> Result<Checkpoint, Error> {
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a render checkpoint"));
+        }
+
+        let mut read_u32 = || -> Result<u32, Error> {
+            let mut bytes = [0u8; 4];
+            r.read_exact(&mut bytes)?;
+            Ok(u32::from_le_bytes(bytes))
+        };
+        let (file_width, file_height) = (read_u32()?, read_u32()?);
+        let samples_per_pixel = read_u32()?;
+        let passes = read_u32()?;
+        let settings_len = read_u32()?;
+        if (file_width, file_height) != (width, height) {
+            return Err(invalid(&format!(
+                "checkpoint is for a {}x{} image, not {}x{}",
+                file_width, file_height, width, height
+            )));
+        }
+        if settings_len > MAX_SETTINGS_LEN {
+            return Err(invalid("bad settings in checkpoint"));
+        }
+
+        let mut seed = [0u8; 8];
+        r.read_exact(&mut seed)?;
+        let seed = u64::from_le_bytes(seed);
+
+        let mut settings = vec![0u8; settings_len as usize];
+        r.read_exact(&mut settings)?;
+        let settings =
+            String::from_utf8(settings).map_err(|_| invalid("bad settings in checkpoint"))?;
+
+        let mut bytes = vec![0u8; width as usize * height as usize * 12];
+        r.read_exact(&mut bytes)?;
+        let value = |i: usize| f32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
+        let sums = (0..width as usize * height as usize)
+            .map(|i| Color::new(value(i * 3), value(i * 3 + 1), value(i * 3 + 2)))
+            .collect();
+
+        Ok(Checkpoint {
+            width,
+            height,
+            samples_per_pixel,
+            passes,
+            seed,
+            settings,
+            sums,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let mut checkpoint = Checkpoint::new(3, 2, 16, 9, "--scene glass".to_string());
+        checkpoint.passes = 5;
+        checkpoint.sums[4] = Color::new(1.5, 0.25, 1e-7);
+
+        let mut bytes = Vec::new();
+        checkpoint.write_to(&mut bytes).unwrap();
+        let loaded = Checkpoint::read_from(&mut bytes.as_slice(), 3, 2).unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert!(loaded.matches(16, 9, "--scene glass"));
+        assert!(!loaded.matches(16, 9, "--scene prism"));
+        assert!(!loaded.matches(16, 10, "--scene glass"));
+        assert!(!loaded.is_finished());
+
+        // the wrong size, cut short, or not a checkpoint at all
+        assert!(Checkpoint::read_from(&mut bytes.as_slice(), 2, 3).is_err());
+        assert!(Checkpoint::read_from(&mut &bytes[..bytes.len() - 1], 3, 2).is_err());
+        assert!(Checkpoint::read_from(&mut &b"P6 3 2 255"[..], 3, 2).is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_header_checked_first() {
+        // a header claiming a huge image or settings is turned down before
+        // anything that size is allocated
+        let header = |width: u32, height: u32, settings_len: u32| {
+            let mut bytes = MAGIC.to_vec();
+            for n in [width, height, 16, 0, settings_len] {
+                bytes.extend_from_slice(&n.to_le_bytes());
+            }
+            bytes
+        };
+        let huge = header(u32::MAX, u32::MAX, 0);
+        assert!(Checkpoint::read_from(&mut huge.as_slice(), 900, 600).is_err());
+        let long = header(900, 600, u32::MAX);
+        let err = Checkpoint::read_from(&mut long.as_slice(), 900, 600).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
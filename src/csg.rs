@@ -0,0 +1,324 @@
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::hittable::Hittable;
+use crate::rayhit::{HitRecord, Ray};
+
+/// Merge the intersections of both operands along the ray, tracking whether
+/// we're inside each one, and keep those where the combined inside/outside
+/// state changes. Each node builds its list from its operands' lists, so
+/// nested CSG only walks each primitive once per ray.
+///
+/// `inside` combines the inside states of a and b.
+fn combine(
+    hits_a: Vec<HitRecord>,
+    hits_b: Vec<HitRecord>,
+    inside: fn(bool, bool) -> bool,
+) -> Vec<HitRecord> {
+    // If the first surface we see is an exit, the ray started inside.
+    let mut in_a = hits_a.first().is_some_and(|rec| !rec.front_face);
+    let mut in_b = hits_b.first().is_some_and(|rec| !rec.front_face);
+
+    let mut hits_a = hits_a.into_iter().peekable();
+    let mut hits_b = hits_b.into_iter().peekable();
+    let mut hits = Vec::new();
+    loop {
+        let from_a = match (hits_a.peek(), hits_b.peek()) {
+            (Some(a), Some(b)) => a.t <= b.t,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => break,
+        };
+        let next = if from_a { hits_a.next() } else { hits_b.next() };
+        let Some(mut rec) = next else { break };
+
+        let was_inside = inside(in_a, in_b);
+        if from_a {
+            in_a = rec.front_face;
+        } else {
+            in_b = rec.front_face;
+        }
+        let is_inside = inside(in_a, in_b);
+
+        if was_inside != is_inside {
+            // The front face is whichever side enters the result. For a
+            // subtracted surface that's the inside of the operand, which
+            // flips its outward normal. The normal already faces against the
+            // ray so it doesn't need to change.
+            rec.front_face = is_inside;
+            hits.push(rec);
+        }
+    }
+
+    hits
+}
+
+/// Both operands' intersections combined, or none without looking at them
+/// if the ray misses the node's bounds.
+fn combined_intersections(
+    node: &dyn Hittable,
+    a: &dyn Hittable,
+    b: &dyn Hittable,
+    ray: Ray,
+    inside: fn(bool, bool) -> bool,
+    rng: &mut dyn RngCore,
+) -> Vec<HitRecord> {
+    if misses(node, ray, f32::NEG_INFINITY, f32::INFINITY) {
+        return Vec::new();
+    }
+
+    combine(a.intersections(ray, rng), b.intersections(ray, rng), inside)
+}
+
+/// Whether the ray certainly misses `node` between t_min and t_max.
+fn misses(node: &dyn Hittable, ray: Ray, t_min: f32, t_max: f32) -> bool {
+    node.bounding_box()
+        .is_some_and(|bounds| bounds.hit(&ray, t_min, t_max).is_none())
+}
+
+/// The first of the node's intersections in [t_min, t_max].
+fn first_hit(
+    node: &dyn Hittable,
+    ray: Ray,
+    t_min: f32,
+    t_max: f32,
+    rng: &mut dyn RngCore,
+) -> Option<HitRecord> {
+    if misses(node, ray, t_min, t_max) {
+        return None;
+    }
+
+    node.intersections(ray, rng)
+        .into_iter()
+        .find(|rec| rec.t >= t_min)
+        .filter(|rec| rec.t <= t_max)
+}
+
+/// Everything inside either operand.
+pub struct Union {
+    a: Box<dyn Hittable>,
+    b: Box<dyn Hittable>,
+}
+
+impl Union {
+    pub fn new(a: impl Hittable + 'static, b: impl Hittable + 'static) -> Union {
+        Union {
+            a: Box::new(a),
+            b: Box::new(b),
+        }
+    }
+}
+
+impl Hittable for Union {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        first_hit(self, ray, t_min, t_max, rng)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.a.bounding_box()?.union(&self.b.bounding_box()?))
+    }
+
+    fn intersections(&self, ray: Ray, rng: &mut dyn RngCore) -> Vec<HitRecord> {
+        combined_intersections(self, &*self.a, &*self.b, ray, |a, b| a || b, rng)
+    }
+}
+
+/// Everything inside both operands, e.g. a lens from two spheres.
+pub struct Intersection {
+    a: Box<dyn Hittable>,
+    b: Box<dyn Hittable>,
+}
+
+impl Intersection {
+    pub fn new(a: impl Hittable + 'static, b: impl Hittable + 'static) -> Intersection {
+        Intersection {
+            a: Box::new(a),
+            b: Box::new(b),
+        }
+    }
+}
+
+impl Hittable for Intersection {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        first_hit(self, ray, t_min, t_max, rng)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        match (self.a.bounding_box(), self.b.bounding_box()) {
+            (Some(a), Some(b)) => Some(a.intersection(&b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn intersections(&self, ray: Ray, rng: &mut dyn RngCore) -> Vec<HitRecord> {
+        combined_intersections(self, &*self.a, &*self.b, ray, |a, b| a && b, rng)
+    }
+}
+
+/// Everything inside `a` but not inside `b`.
+pub struct Difference {
+    a: Box<dyn Hittable>,
+    b: Box<dyn Hittable>,
+}
+
+impl Difference {
+    pub fn new(a: impl Hittable + 'static, b: impl Hittable + 'static) -> Difference {
+        Difference {
+            a: Box::new(a),
+            b: Box::new(b),
+        }
+    }
+}
+
+impl Hittable for Difference {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        first_hit(self, ray, t_min, t_max, rng)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.a.bounding_box()
+    }
+
+    fn intersections(&self, ray: Ray, rng: &mut dyn RngCore) -> Vec<HitRecord> {
+        combined_intersections(self, &*self.a, &*self.b, ray, |a, b| a && !b, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::vec3::{Color, Point3, Vec3};
+
+    fn sphere(x: f32, radius: f32) -> Sphere {
+        let mat = Rc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        });
+        Sphere::new(Point3::new(x, 0.0, 0.0), radius, mat)
+    }
+
+    fn ray_along_x() -> Ray {
+        Ray {
+            origin: Point3::new(-10.0, 0.0, 0.0),
+            direction: Vec3::new(1.0, 0.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn test_intersection_lens() {
+        let mut rng = rand::thread_rng();
+        // overlap of spheres at x = -0.5 and x = 0.5 spans [-0.5, 0.5]
+        let lens = Intersection::new(sphere(-0.5, 1.0), sphere(0.5, 1.0));
+
+        let rec = lens
+            .hit(ray_along_x(), 0.0, f32::INFINITY, &mut rng)
+            .unwrap();
+        assert!(f32::abs(rec.p.x - -0.5) < 1e-4);
+        assert!(rec.front_face);
+
+        let rec = lens
+            .hit(ray_along_x(), rec.t + 0.001, f32::INFINITY, &mut rng)
+            .unwrap();
+        assert!(f32::abs(rec.p.x - 0.5) < 1e-4);
+        assert!(!rec.front_face);
+    }
+
+    #[test]
+    fn test_union_skips_interior_surfaces() {
+        let mut rng = rand::thread_rng();
+        let union = Union::new(sphere(-0.5, 1.0), sphere(0.5, 1.0));
+
+        let rec = union
+            .hit(ray_along_x(), 0.0, f32::INFINITY, &mut rng)
+            .unwrap();
+        assert!(f32::abs(rec.p.x - -1.5) < 1e-4);
+
+        let rec = union
+            .hit(ray_along_x(), rec.t + 0.001, f32::INFINITY, &mut rng)
+            .unwrap();
+        assert!(f32::abs(rec.p.x - 1.5) < 1e-4);
+        assert!(!rec.front_face);
+    }
+
+    #[test]
+    fn test_difference_flips_subtracted_surface() {
+        let mut rng = rand::thread_rng();
+        // a sphere with a bite taken out of its far side
+        let shape = Difference::new(sphere(0.0, 1.0), sphere(1.0, 0.5));
+
+        let rec = shape
+            .hit(ray_along_x(), 0.0, f32::INFINITY, &mut rng)
+            .unwrap();
+        assert!(f32::abs(rec.p.x - -1.0) < 1e-4);
+
+        // leaving the result through the inside of the subtracted sphere
+        let rec = shape
+            .hit(ray_along_x(), rec.t + 0.001, f32::INFINITY, &mut rng)
+            .unwrap();
+        assert!(f32::abs(rec.p.x - 0.5) < 1e-4);
+        assert!(!rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(-1.0, 0.0, 0.0));
+
+        // a ray starting inside the bite sees the subtracted surface first
+        let ray = Ray {
+            origin: Point3::new(0.75, 0.0, 0.0),
+            direction: Vec3::new(-1.0, 0.0, 0.0),
+        };
+        let rec = shape.hit(ray, 0.0, f32::INFINITY, &mut rng).unwrap();
+        assert!(f32::abs(rec.p.x - 0.5) < 1e-4);
+        assert!(rec.front_face);
+    }
+
+    #[test]
+    fn test_nested_intersections() {
+        let mut rng = rand::thread_rng();
+        // a row of three balls, two levels deep
+        let row = Union::new(
+            Union::new(sphere(-3.0, 1.0), sphere(0.0, 1.0)),
+            sphere(3.0, 1.0),
+        );
+
+        let hits = row.intersections(ray_along_x(), &mut rng);
+        let xs: Vec<f32> = hits.iter().map(|rec| rec.p.x).collect();
+        let expected = [-4.0, -2.0, -1.0, 1.0, 2.0, 4.0];
+        assert_eq!(xs.len(), expected.len(), "{:?}", xs);
+        for (i, (x, expected)) in xs.iter().zip(expected).enumerate() {
+            assert!(f32::abs(x - expected) < 1e-4, "{:?}", xs);
+            assert_eq!(hits[i].front_face, i % 2 == 0);
+        }
+
+        // the middle ball, from between the first two
+        let rec = row
+            .hit(ray_along_x(), 8.5, f32::INFINITY, &mut rng)
+            .unwrap();
+        assert!(f32::abs(rec.p.x - -1.0) < 1e-4);
+
+        // passing over the top misses the bounds, and so everything
+        let over = Ray {
+            origin: Point3::new(-10.0, 2.0, 0.0),
+            direction: Vec3::new(1.0, 0.0, 0.0),
+        };
+        assert!(row.intersections(over, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn test_bounding_boxes() {
+        let union = Union::new(sphere(-0.5, 1.0), sphere(0.5, 1.0));
+        let bounds = union.bounding_box().unwrap();
+        assert_eq!(bounds.min, Point3::new(-1.5, -1.0, -1.0));
+        assert_eq!(bounds.max, Point3::new(1.5, 1.0, 1.0));
+
+        let lens = Intersection::new(sphere(-0.5, 1.0), sphere(0.5, 1.0));
+        let bounds = lens.bounding_box().unwrap();
+        assert_eq!(bounds.min, Point3::new(-0.5, -1.0, -1.0));
+        assert_eq!(bounds.max, Point3::new(0.5, 1.0, 1.0));
+
+        let bitten = Difference::new(sphere(0.0, 1.0), sphere(1.0, 0.5));
+        let bounds = bitten.bounding_box().unwrap();
+        assert_eq!(bounds.min, Point3::new(-1.0, -1.0, -1.0));
+        assert_eq!(bounds.max, Point3::new(1.0, 1.0, 1.0));
+    }
+}
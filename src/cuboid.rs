@@ -0,0 +1,95 @@
+use std::rc::Rc;
+
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::hittable::Hittable;
+use crate::material::Material;
+use crate::rayhit::{HitRecord, Ray};
+use crate::vec3::{Point3, Vec3};
+
+/// Axis aligned box between two corners.
+pub struct Cuboid {
+    pub bounds: Aabb,
+    pub mat: Rc<dyn Material>,
+}
+
+impl Cuboid {
+    pub fn new(min: Point3, max: Point3, material: Rc<dyn Material>) -> Cuboid {
+        Cuboid {
+            bounds: Aabb::new(min, max),
+            mat: material,
+        }
+    }
+
+    fn outward_normal(&self, p: Point3) -> Vec3 {
+        // the face we're on is the one whose slab p is closest to leaving
+        let center = (self.bounds.min + self.bounds.max) / 2.0;
+        let half = (self.bounds.max - self.bounds.min) / 2.0;
+        let d = p - center;
+        let (x, y, z) = (d.x / half.x, d.y / half.y, d.z / half.z);
+
+        if f32::abs(x) >= f32::abs(y) && f32::abs(x) >= f32::abs(z) {
+            Vec3::new(x.signum(), 0.0, 0.0)
+        } else if f32::abs(y) >= f32::abs(z) {
+            Vec3::new(0.0, y.signum(), 0.0)
+        } else {
+            Vec3::new(0.0, 0.0, z.signum())
+        }
+    }
+
+    /// Texture coordinates across the face with the given normal, each face
+    /// getting the whole [0, 1] square, and their derivatives.
+    fn uv(&self, p: Point3, normal: Vec3) -> (f32, f32, Vec3, Vec3) {
+        let rel = p - self.bounds.min;
+        let size = self.bounds.max - self.bounds.min;
+        let (x, y, z) = (rel.x / size.x, rel.y / size.y, rel.z / size.z);
+        let dx = Vec3::new(size.x, 0.0, 0.0);
+        let dy = Vec3::new(0.0, size.y, 0.0);
+        let dz = Vec3::new(0.0, 0.0, size.z);
+
+        if normal.x != 0.0 {
+            (z, y, dz, dy)
+        } else if normal.y != 0.0 {
+            (x, z, dx, dz)
+        } else {
+            (x, y, dx, dy)
+        }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32, _rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let (t_enter, t_exit) = self.bounds.hit(&ray, f32::NEG_INFINITY, f32::INFINITY)?;
+
+        let t = if t_enter >= t_min && t_enter <= t_max {
+            t_enter
+        } else if t_exit >= t_min && t_exit <= t_max {
+            t_exit
+        } else {
+            return None;
+        };
+
+        let p = ray.at(t);
+        let outward_normal = self.outward_normal(p);
+        let (u, v, dpdu, dpdv) = self.uv(p, outward_normal);
+        let mut rec = HitRecord {
+            p,
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            u,
+            v,
+            dpdu,
+            dpdv,
+            mat: self.mat.clone(),
+            t,
+            front_face: false,
+        };
+        rec.set_face_normal(&ray, outward_normal);
+
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+}
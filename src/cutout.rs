@@ -0,0 +1,121 @@
+use std::rc::Rc;
+
+use rand::{Rng, RngCore};
+
+use crate::aabb::Aabb;
+use crate::hittable::Hittable;
+use crate::rayhit::{HitRecord, Ray};
+use crate::texture::Texture;
+
+// Give up on a ray after passing through this many holes, in case the ray
+// is grazing along the surface.
+const MAX_HOLES: usize = 64;
+
+/// An object with parts of its surface cut away, like leaves or a chain link
+/// fence on simple geometry. Opacity comes from the first channel of a
+/// texture. Rays pass straight through where it's zero, and where it's in
+/// between they pass through that fraction of the time. Since this happens
+/// in `hit`, every ray traced against the scene sees the same holes.
+pub struct Cutout {
+    object: Box<dyn Hittable>,
+    opacity: Rc<dyn Texture>,
+}
+
+impl Cutout {
+    pub fn new(object: impl Hittable + 'static, opacity: impl Texture + 'static) -> Cutout {
+        Cutout {
+            object: Box::new(object),
+            opacity: Rc::new(opacity),
+        }
+    }
+}
+
+impl Hittable for Cutout {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let mut t_min = t_min;
+
+        for _ in 0..MAX_HOLES {
+            let rec = self.object.hit(ray, t_min, t_max, rng)?;
+            let opacity = self.opacity.value(rec.u, rec.v, rec.p).x;
+            if opacity >= 1.0 || (opacity > 0.0 && rng.gen::<f32>() < opacity) {
+                return Some(rec);
+            }
+
+            t_min = rec.t + 0.0001;
+        }
+
+        None
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material};
+    use crate::sphere::Sphere;
+    use crate::texture::Checker;
+    use crate::vec3::{Color, Point3, Vec3};
+
+    fn sphere() -> Sphere {
+        let mat: Rc<dyn Material> = Rc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        });
+        Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, mat)
+    }
+
+    #[test]
+    fn test_cutout() {
+        let mut rng = rand::thread_rng();
+        // along the z axis, which hits the near side of the sphere at
+        // u = 0.75 and the far side at u = 0.25
+        let ray = Ray {
+            origin: Point3::new(0.0, 0.0, -5.0),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+
+        let opaque = Cutout::new(sphere(), 1.0);
+        assert_eq!(
+            opaque.hit(ray, 0.0, f32::INFINITY, &mut rng).unwrap().t,
+            4.0
+        );
+
+        let gone = Cutout::new(sphere(), 0.0);
+        assert!(gone.hit(ray, 0.0, f32::INFINITY, &mut rng).is_none());
+
+        // the near side is cut away, the far side is solid
+        let half = Cutout::new(sphere(), Checker::new(2, 1, 1.0, 0.0));
+        let rec = half.hit(ray, 0.0, f32::INFINITY, &mut rng).unwrap();
+        assert_eq!(rec.t, 6.0);
+        assert!(!rec.front_face);
+    }
+
+    #[test]
+    fn test_fractional_cutout() {
+        let mut rng = rand::thread_rng();
+        let ray = Ray {
+            origin: Point3::new(0.0, 0.0, -5.0),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+        };
+        let ghost = Cutout::new(sphere(), 0.5);
+
+        // half stop at the near side, a quarter at the far side, and a
+        // quarter pass through both
+        let n = 100_000;
+        let mut near = 0;
+        let mut far = 0;
+        for _ in 0..n {
+            match ghost.hit(ray, 0.0, f32::INFINITY, &mut rng) {
+                Some(rec) if rec.front_face => near += 1,
+                Some(_) => far += 1,
+                None => {}
+            }
+        }
+
+        assert!(f32::abs(near as f32 / n as f32 - 0.5) < 0.01);
+        assert!(f32::abs(far as f32 / n as f32 - 0.25) < 0.01);
+    }
+}
@@ -0,0 +1,171 @@
+/// A piecewise constant function over [0, 1], for picking points in
+/// proportion to it.
+#[derive(Clone, Debug)]
+pub struct Distribution1D {
+    func: Vec<f32>,
+    /// The running integral at the start of each piece, and the total at the
+    /// end, normalized to run from 0 to 1.
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    /// The function is given by its value over each of the equal sized
+    /// pieces. Negative values count as zero. If it's zero everywhere every
+    /// point is as likely as any other.
+    pub fn new(func: Vec<f32>) -> Distribution1D {
+        assert!(!func.is_empty());
+        let func: Vec<f32> = func.into_iter().map(|f| f32::max(f, 0.0)).collect();
+        let n = func.len() as f32;
+
+        let mut cdf = vec![0.0; func.len() + 1];
+        for i in 0..func.len() {
+            cdf[i + 1] = cdf[i] + func[i] / n;
+        }
+        let integral = cdf[func.len()];
+
+        if integral == 0.0 {
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as f32 / n;
+            }
+        } else {
+            for c in &mut cdf {
+                *c /= integral;
+            }
+        }
+        // exactly, so rounding can't leave u past the end
+        cdf[func.len()] = 1.0;
+
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// The integral of the function over [0, 1].
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Pick a point in [0, 1) with u uniform in [0, 1). Returns the point,
+    /// its pdf and the piece it's in.
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        // the last piece whose cdf starts at or before u, skipping any
+        // empty pieces
+        let i = self.cdf.partition_point(|&c| c <= u).clamp(1, self.count()) - 1;
+
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let du = if width > 0.0 {
+            (u - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+        let x = f32::min((i as f32 + du) / self.count() as f32, 1.0 - f32::EPSILON);
+
+        (x, self.pdf(i), i)
+    }
+
+    /// The pdf of `sample` picking a point in piece `i`.
+    pub fn pdf(&self, i: usize) -> f32 {
+        if self.integral == 0.0 {
+            1.0
+        } else {
+            self.func[i] / self.integral
+        }
+    }
+}
+
+/// A piecewise constant function over [0, 1]^2, sampled by picking a row
+/// from the marginal distribution and then a point within the row.
+#[derive(Clone, Debug)]
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// The function is given row by row, `width` values to a row.
+    pub fn new(func: &[f32], width: usize) -> Distribution2D {
+        assert!(width > 0 && func.len().is_multiple_of(width));
+        let rows: Vec<Distribution1D> = func
+            .chunks(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral()).collect());
+
+        Distribution2D { rows, marginal }
+    }
+
+    /// Pick a point with u1 and u2 uniform in [0, 1). Returns the point
+    /// along the row, the point across the rows, and its pdf.
+    pub fn sample(&self, u1: f32, u2: f32) -> (f32, f32, f32) {
+        let (y, pdf_y, row) = self.marginal.sample(u2);
+        let (x, pdf_x, _) = self.rows[row].sample(u1);
+        (x, y, pdf_x * pdf_y)
+    }
+
+    /// The pdf of `sample` picking the point (x, y).
+    pub fn pdf(&self, x: f32, y: f32) -> f32 {
+        let row = usize::min((y * self.rows.len() as f32) as usize, self.rows.len() - 1);
+        let width = self.rows[row].count();
+        let i = usize::min((x * width as f32) as usize, width - 1);
+
+        if self.marginal.integral() == 0.0 {
+            return 1.0;
+        }
+        self.rows[row].func[i] / self.marginal.integral()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distribution_1d() {
+        let d = Distribution1D::new(vec![1.0, 0.0, 3.0, 0.0]);
+        assert_eq!(d.integral(), 1.0);
+
+        // a quarter of the way along the cdf is the end of the first piece
+        let (x, pdf, i) = d.sample(0.125);
+        assert_eq!((x, pdf, i), (0.125, 1.0, 0));
+
+        // the empty pieces are never picked
+        for k in 0..100 {
+            let (x, pdf, i) = d.sample(k as f32 / 100.0);
+            assert!(i == 0 || i == 2);
+            assert_eq!(i, (x * 4.0) as usize);
+            assert_eq!(pdf, d.pdf(i));
+        }
+        assert_eq!(d.pdf(2), 3.0);
+
+        // all zero falls back to uniform
+        let flat = Distribution1D::new(vec![0.0, 0.0]);
+        assert_eq!(flat.sample(0.75), (0.75, 1.0, 1));
+    }
+
+    #[test]
+    fn test_distribution_2d() {
+        let d = Distribution2D::new(&[1.0, 1.0, 0.0, 6.0], 2);
+
+        // the pdf integrates to one
+        assert_eq!(
+            d.pdf(0.25, 0.25) + d.pdf(0.75, 0.25) + d.pdf(0.75, 0.75),
+            4.0
+        );
+        assert_eq!(d.pdf(0.25, 0.75), 0.0);
+
+        for i in 0..10 {
+            for j in 0..10 {
+                let (x, y, pdf) = d.sample((i as f32 + 0.5) / 10.0, (j as f32 + 0.5) / 10.0);
+                assert!(pdf > 0.0);
+                assert_eq!(pdf, d.pdf(x, y));
+            }
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::f32::consts::PI;
+
+use crate::distribution::Distribution2D;
+use crate::light::{Light, LightSample};
+use crate::light_bvh::LightBounds;
+use crate::texture::ImageTexture;
+use crate::vec3::{Color, Point3, Vec3};
+
+/// Picks directions over the whole sphere in proportion to a function
+/// sampled on an equirectangular grid, with +y at the top row.
+pub struct DirectionDistribution {
+    distribution: Distribution2D,
+}
+
+impl DirectionDistribution {
+    /// `f` is given the coordinates of each cell's center, across from the
+    /// left and down from the top.
+    pub fn new(width: usize, height: usize, f: impl Fn(f32, f32) -> f32) -> DirectionDistribution {
+        // Rows near the poles are squeezed into less solid angle, so they
+        // need picking less often.
+        let mut func = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let t = (y as f32 + 0.5) / height as f32;
+            let sin_theta = f32::sin(PI * t);
+            for x in 0..width {
+                func.push(f((x as f32 + 0.5) / width as f32, t) * sin_theta);
+            }
+        }
+
+        DirectionDistribution {
+            distribution: Distribution2D::new(&func, width),
+        }
+    }
+
+    /// Where `direction` lands on the grid, across from the left and down
+    /// from the top, both in [0, 1].
+    pub fn coords(direction: Vec3) -> (f32, f32) {
+        let w = direction.unit_vector();
+        let u = (f32::atan2(w.z, w.x) + PI) / (2.0 * PI);
+        let t = f32::acos(w.y.clamp(-1.0, 1.0)) / PI;
+        (u, t)
+    }
+
+    pub fn direction(u: f32, t: f32) -> Vec3 {
+        let phi = 2.0 * PI * u - PI;
+        let theta = PI * t;
+        Vec3::new(
+            f32::sin(theta) * f32::cos(phi),
+            f32::cos(theta),
+            f32::sin(theta) * f32::sin(phi),
+        )
+    }
+
+    /// The pdf per unit solid angle from the pdf over the grid, which
+    /// stretches each direction over 2 pi^2 sin(theta) of it.
+    fn solid_angle_pdf(pdf: f32, t: f32) -> f32 {
+        let sin_theta = f32::sin(PI * t);
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        pdf / (2.0 * PI * PI * sin_theta)
+    }
+
+    /// Pick a direction with u1 and u2 uniform in [0, 1).
+    pub fn sample(&self, u1: f32, u2: f32) -> Option<LightSample> {
+        let (u, t, pdf) = self.distribution.sample(u1, u2);
+        let pdf = DirectionDistribution::solid_angle_pdf(pdf, t);
+        if pdf == 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction: DirectionDistribution::direction(u, t),
+            pdf,
+            delta: None,
+        })
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let (u, t) = DirectionDistribution::coords(direction);
+        DirectionDistribution::solid_angle_pdf(self.distribution.pdf(u, t), t)
+    }
+}
+
+/// Light arriving from every direction, from an equirectangular image
+/// wrapped around the scene with +y at the top row. Directions are picked
+/// in proportion to the brightness of the image, so small bright patches
+/// like the sun are found without much noise.
+pub struct EnvironmentMap {
+    image: ImageTexture,
+    /// Multiplies the image.
+    pub scale: f32,
+    distribution: DirectionDistribution,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: ImageTexture, scale: f32) -> EnvironmentMap {
+        let (width, height) = (image.width(), image.height());
+        let distribution = DirectionDistribution::new(width, height, |u, t| {
+            let x = (u * width as f32) as i64;
+            let y = (t * height as f32) as i64;
+            let c = image.texel(x, y);
+            (c.x + c.y + c.z) / 3.0
+        });
+
+        EnvironmentMap {
+            image,
+            scale,
+            distribution,
+        }
+    }
+}
+
+impl Light for EnvironmentMap {
+    fn sample(&self, _origin: Point3, u1: f32, u2: f32) -> Option<LightSample> {
+        self.distribution.sample(u1, u2)
+    }
+
+    fn pdf(&self, _origin: Point3, direction: Vec3) -> f32 {
+        self.distribution.pdf(direction)
+    }
+
+    fn escaped(&self, direction: Vec3) -> Color {
+        // nearest texel, so the light matches how it's sampled
+        let (u, t) = DirectionDistribution::coords(direction);
+        let x = usize::min(
+            (u * self.image.width() as f32) as usize,
+            self.image.width() - 1,
+        );
+        let y = usize::min(
+            (t * self.image.height() as f32) as usize,
+            self.image.height() - 1,
+        );
+        self.image.texel(x as i64, y as i64) * self.scale
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Dim grey, with one bright texel just above the horizon.
+    fn sky() -> EnvironmentMap {
+        let (width, height) = (32, 16);
+        let mut pixels = vec![Color::new(0.1, 0.1, 0.1); width * height];
+        pixels[6 * width + 20] = Color::new(1000.0, 1000.0, 1000.0);
+        EnvironmentMap::new(ImageTexture::new(width, height, pixels), 1.0)
+    }
+
+    #[test]
+    fn test_environment_directions() {
+        let up = DirectionDistribution::coords(Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(up.1, 0.0);
+        let (u, t) = DirectionDistribution::coords(Vec3::new(0.0, 0.0, -1.0));
+        assert!(f32::abs(u - 0.25) < 1e-6 && f32::abs(t - 0.5) < 1e-6);
+
+        let w = DirectionDistribution::direction(0.3, 0.7);
+        let (u, t) = DirectionDistribution::coords(w);
+        assert!(f32::abs(u - 0.3) < 1e-5 && f32::abs(t - 0.7) < 1e-5);
+    }
+
+    #[test]
+    fn test_environment_sampling() {
+        let sky = sky();
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let bright = Color::new(1000.0, 1000.0, 1000.0);
+
+        // most samples find the bright texel
+        let n = 64;
+        let mut found = 0;
+        let mut irradiance = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let (u1, u2) = ((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+                let sample = sky.sample(origin, u1, u2).unwrap();
+                let pdf = sky.pdf(origin, sample.direction);
+                assert!(f32::abs(sample.pdf / pdf - 1.0) < 1e-3);
+
+                let radiance = sky.escaped(sample.direction);
+                if radiance == bright {
+                    found += 1;
+                }
+                irradiance += radiance.x * f32::max(sample.direction.y, 0.0) / sample.pdf;
+            }
+        }
+        assert!(found > n * n * 9 / 10);
+
+        // and it adds up to the light on a surface facing up, worked out
+        // over the texels of the upper half
+        let mut expected = 0.0;
+        for y in 0..8 {
+            let (t0, t1) = (y as f32 / 16.0 * PI, (y + 1) as f32 / 16.0 * PI);
+            // the integral of cos(theta) sin(theta) over the row, per unit
+            // of phi
+            let band = (f32::sin(t1).powi(2) - f32::sin(t0).powi(2)) / 2.0;
+            let row_light = if y == 6 {
+                0.1 * 31.0 + 1000.0
+            } else {
+                0.1 * 32.0
+            };
+            expected += band * row_light * 2.0 * PI / 32.0;
+        }
+        let irradiance = irradiance / (n * n) as f32;
+        assert!(f32::abs(irradiance / expected - 1.0) < 0.02);
+    }
+}
@@ -0,0 +1,231 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::ops::{Add, Mul};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::vec3::{Color, Vec3};
+
+/// A dense 3D grid of values sampled at cell centers. Lookups take
+/// coordinates in [0, 1]^3 and are trilinearly interpolated.
+#[derive(Clone, Debug)]
+pub struct VoxelGrid<T> {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    data: Vec<T>,
+}
+
+impl<T> VoxelGrid<T>
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    pub fn new(nx: usize, ny: usize, nz: usize, data: Vec<T>) -> VoxelGrid<T> {
+        assert_eq!(
+            data.len(),
+            nx * ny * nz,
+            "grid data doesn't match its dimensions"
+        );
+        VoxelGrid { nx, ny, nz, data }
+    }
+
+    fn at(&self, x: usize, y: usize, z: usize) -> T {
+        self.data[(z * self.ny + y) * self.nx + x]
+    }
+
+    /// Trilinearly interpolated value at `p`, where each component of `p`
+    /// is in [0, 1]. Points outside the grid get the value of the nearest
+    /// boundary cell.
+    pub fn lookup(&self, p: Vec3) -> T {
+        // cell centers are at (i + 0.5) / n
+        let (x0, x1, fx) = cell_coords(p.x, self.nx);
+        let (y0, y1, fy) = cell_coords(p.y, self.ny);
+        let (z0, z1, fz) = cell_coords(p.z, self.nz);
+
+        let lerp = |a: T, b: T, t: f32| a * (1.0 - t) + b * t;
+
+        let c00 = lerp(self.at(x0, y0, z0), self.at(x1, y0, z0), fx);
+        let c10 = lerp(self.at(x0, y1, z0), self.at(x1, y1, z0), fx);
+        let c01 = lerp(self.at(x0, y0, z1), self.at(x1, y0, z1), fx);
+        let c11 = lerp(self.at(x0, y1, z1), self.at(x1, y1, z1), fx);
+
+        let c0 = lerp(c00, c10, fy);
+        let c1 = lerp(c01, c11, fy);
+
+        lerp(c0, c1, fz)
+    }
+}
+
+impl VoxelGrid<f32> {
+    pub fn max_value(&self) -> f32 {
+        self.data.iter().cloned().fold(0.0, f32::max)
+    }
+}
+
+/// Returns the two cells to interpolate between along one axis and the
+/// interpolation weight.
+fn cell_coords(p: f32, n: usize) -> (usize, usize, f32) {
+    let x = (p * n as f32 - 0.5).clamp(0.0, (n - 1) as f32);
+    let x0 = x.floor() as usize;
+    let x1 = usize::min(x0 + 1, n - 1);
+
+    (x0, x1, x - x0 as f32)
+}
+
+/// The grids making up a heterogeneous volume.
+pub struct VolumeGrids {
+    pub density: VoxelGrid<f32>,
+    pub albedo: Option<VoxelGrid<Color>>,
+    pub emission: Option<VoxelGrid<Color>>,
+}
+
+/// Load volume grids from a whitespace separated text file:
+///
+/// ```text
+/// # comments run to the end of the line
+/// dims 16 16 16
+/// density <nx * ny * nz values>
+/// albedo <3 * nx * ny * nz values>      (optional)
+/// emission <3 * nx * ny * nz values>    (optional)
+/// ```
+///
+/// Values are stored x fastest, then y, then z.
+pub fn load_volume_grids(path: &Path) -> Result<VolumeGrids, Error> {
+    parse_volume_grids(&fs::read_to_string(path)?)
+}
+
+/// The most voxels a grid can have, 512^3.
+const MAX_VOXELS: usize = 1 << 27;
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn next_value<'a, T: FromStr>(
+    tokens: &mut impl Iterator<Item = &'a str>,
+    what: &str,
+) -> Result<T, Error> {
+    let token = tokens
+        .next()
+        .ok_or_else(|| invalid(format!("unexpected end of file reading {}", what)))?;
+    token
+        .parse::<T>()
+        .map_err(|_| invalid(format!("bad value '{}' in {}", token, what)))
+}
+
+pub fn parse_volume_grids(text: &str) -> Result<VolumeGrids, Error> {
+    let mut tokens = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .flat_map(|line| line.split_whitespace());
+
+    let mut dims: Option<(usize, usize, usize)> = None;
+    let mut density = None;
+    let mut albedo = None;
+    let mut emission = None;
+
+    while let Some(section) = tokens.next() {
+        if section == "dims" {
+            let nx = next_value(&mut tokens, "dims")?;
+            let ny = next_value(&mut tokens, "dims")?;
+            let nz = next_value(&mut tokens, "dims")?;
+            if nx == 0 || ny == 0 || nz == 0 {
+                return Err(invalid("grid dimensions must be positive".to_string()));
+            }
+            let count = usize::checked_mul(nx, ny).and_then(|n| n.checked_mul(nz));
+            if count.is_none_or(|count| count > MAX_VOXELS) {
+                return Err(invalid(format!(
+                    "grid of {}x{}x{} is too big, the most is {} voxels",
+                    nx, ny, nz, MAX_VOXELS
+                )));
+            }
+            dims = Some((nx, ny, nz));
+            continue;
+        }
+
+        let (nx, ny, nz) = dims.ok_or_else(|| invalid(format!("'{}' before 'dims'", section)))?;
+        let count = nx * ny * nz;
+        // grown as values are read, so a file that claims more than it has
+        // fails before allocating for it
+        match section {
+            "density" => {
+                let mut values = Vec::new();
+                for _ in 0..count {
+                    values.push(next_value(&mut tokens, section)?);
+                }
+                density = Some(VoxelGrid::new(nx, ny, nz, values));
+            }
+            "albedo" | "emission" => {
+                let mut values = Vec::new();
+                for _ in 0..count {
+                    values.push(Color::new(
+                        next_value(&mut tokens, section)?,
+                        next_value(&mut tokens, section)?,
+                        next_value(&mut tokens, section)?,
+                    ));
+                }
+                let grid = Some(VoxelGrid::new(nx, ny, nz, values));
+                if section == "albedo" {
+                    albedo = grid;
+                } else {
+                    emission = grid;
+                }
+            }
+            _ => return Err(invalid(format!("unknown section '{}'", section))),
+        }
+    }
+
+    Ok(VolumeGrids {
+        density: density.ok_or_else(|| invalid("missing 'density'".to_string()))?,
+        albedo,
+        emission,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trilinear_lookup() {
+        // 2x1x1 grid, values at cell centers x = 0.25 and x = 0.75
+        let grid = VoxelGrid::new(2, 1, 1, vec![0.0, 1.0]);
+
+        assert_eq!(grid.lookup(Vec3::new(0.25, 0.5, 0.5)), 0.0);
+        assert_eq!(grid.lookup(Vec3::new(0.5, 0.5, 0.5)), 0.5);
+        assert_eq!(grid.lookup(Vec3::new(0.75, 0.5, 0.5)), 1.0);
+        // clamped past the last cell center
+        assert_eq!(grid.lookup(Vec3::new(1.0, 0.5, 0.5)), 1.0);
+    }
+
+    #[test]
+    fn test_parse_volume_grids() {
+        let text = "
+            # a 2x1x1 test grid
+            dims 2 1 1
+            density 0.5 2.0
+            emission 1 0 0  0 0 1
+        ";
+        let grids = parse_volume_grids(text).unwrap();
+
+        assert_eq!(grids.density.max_value(), 2.0);
+        assert!(grids.albedo.is_none());
+        assert_eq!(
+            grids.emission.unwrap().lookup(Vec3::new(0.0, 0.0, 0.0)),
+            Color::new(1.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_parse_volume_grids_errors() {
+        assert!(parse_volume_grids("density 1").is_err());
+        assert!(parse_volume_grids("dims 1 1 1").is_err());
+        assert!(parse_volume_grids("dims 2 1 1 density 1").is_err());
+        assert!(parse_volume_grids("dims 1 1 1 density x").is_err());
+        assert!(parse_volume_grids("dims 1 1 1 smoke 1").is_err());
+        // too big, and too big to multiply out
+        assert!(parse_volume_grids("dims 1024 1024 1024 density 1").is_err());
+        let huge = format!("dims {} {} 2 density 1", usize::MAX / 2, usize::MAX / 2);
+        assert!(parse_volume_grids(&huge).is_err());
+    }
+}
@@ -1,7 +1,49 @@
+use std::rc::Rc;
+
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::light::{Light, LightList};
 use crate::rayhit::{Ray, HitRecord};
 
+// Stop collecting intersections after this many, in case an object isn't
+// really closed and keeps reporting hits.
+const MAX_INTERSECTIONS: usize = 64;
+
 pub trait Hittable {
-    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord>;
+
+    /// A box the whole object fits inside, if it's bounded and knows how
+    /// big it is.
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+
+    /// Every intersection of the whole line of the ray with a closed object,
+    /// in order along it. Since the object is closed, these alternate
+    /// between entering and leaving it. CSG combines these.
+    fn intersections(&self, ray: Ray, rng: &mut dyn RngCore) -> Vec<HitRecord> {
+        let mut hits = Vec::new();
+        let mut t = f32::NEG_INFINITY;
+
+        while hits.len() < MAX_INTERSECTIONS {
+            match self.hit(ray, t, f32::INFINITY, rng) {
+                Some(rec) => {
+                    t = rec.t + 0.0001;
+                    hits.push(rec);
+                }
+                None => break,
+            }
+        }
+
+        hits
+    }
+
+    /// The object as a light to sample directly, if it glows and knows how
+    /// to be sampled.
+    fn light(&self) -> Option<Rc<dyn Light>> {
+        None
+    }
 }
 
 pub struct HittableList {
@@ -9,29 +51,40 @@ pub struct HittableList {
     // Box is needed because Hittable objects can be of different
     // sizes.
     objects: Vec<Box<dyn Hittable>>,
+    /// The glowing objects, picked up as they're added.
+    pub lights: LightList,
 }
 
 impl HittableList {
     pub fn new() -> HittableList {
         HittableList {
             objects: Vec::new(),
+            lights: LightList::new(),
         }
     }
 
     pub fn add(&mut self, object: impl Hittable + 'static) {
         // I'm not 100% clear on if this is the correct way to do
         // this.
+        if let Some(light) = object.light() {
+            self.lights.add(light);
+        }
         self.objects.push(Box::new(object) as Box<dyn Hittable>);
     }
+
+    /// Add a light that isn't an object in the world, like a point light.
+    pub fn add_light(&mut self, light: impl Light + 'static) {
+        self.lights.add(Rc::new(light));
+    }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
         let mut temp_rec = None;
         let mut closest_so_far = t_max;
 
         for object in &self.objects {
-            if let Some(rec) = object.hit(ray, t_min, closest_so_far) {
+            if let Some(rec) = object.hit(ray, t_min, closest_so_far, rng) {
                 closest_so_far = rec.t;
                 temp_rec = Some(rec);
             }
@@ -0,0 +1,343 @@
+use std::rc::Rc;
+
+use rand::{Rng, RngCore};
+
+use crate::material::{Material, RoughDielectric};
+use crate::medium::Interior;
+use crate::microfacet;
+use crate::rayhit::{HitRecord, Ray};
+use crate::texture::Texture;
+use crate::vec3::{Color, Vec3};
+
+/// Bounces between the base and the underside of a coat before the light is
+/// considered absorbed.
+const MAX_INTERNAL_BOUNCES: u32 = 16;
+
+fn mul(a: Color, b: Color) -> Color {
+    Color::new(a.x * b.x, a.y * b.y, a.z * b.z)
+}
+
+/// Blend of two materials. Where `weight` is zero the surface is all `a`,
+/// where it's one all `b`. Each scatter picks one of the two at random.
+pub struct MixMaterial {
+    pub a: Rc<dyn Material>,
+    pub b: Rc<dyn Material>,
+    pub weight: Rc<dyn Texture>,
+}
+
+impl MixMaterial {
+    pub fn new(
+        a: Rc<dyn Material>,
+        b: Rc<dyn Material>,
+        weight: impl Texture + 'static,
+    ) -> MixMaterial {
+        MixMaterial {
+            a,
+            b,
+            weight: Rc::new(weight),
+        }
+    }
+
+    fn weight(&self, rec: &HitRecord) -> f32 {
+        self.weight.value(rec.u, rec.v, rec.p).x.clamp(0.0, 1.0)
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        if rng.gen::<f32>() < self.weight(rec) {
+            self.b.scatter(r_in, rec, rng)
+        } else {
+            self.a.scatter(r_in, rec, rng)
+        }
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        let w = self.weight(rec);
+        self.a.emitted(rec) * (1.0 - w) + self.b.emitted(rec) * w
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.a.is_emissive() || self.b.is_emissive()
+    }
+
+    /// The medium inside `a`, or failing that `b`. Mixing a dielectric with
+    /// an opaque material leaves the dielectric's inside.
+    fn interior(&self) -> Option<Interior> {
+        self.a.interior().or_else(|| self.b.interior())
+    }
+
+    fn scatter_interface(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        eta: f32,
+        rng: &mut dyn RngCore,
+    ) -> Option<(Color, Ray)> {
+        if rng.gen::<f32>() < self.weight(rec) {
+            self.b.scatter_interface(r_in, rec, eta, rng)
+        } else {
+            self.a.scatter_interface(r_in, rec, eta, rng)
+        }
+    }
+
+    fn eval(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        direction: Vec3,
+        rng: &mut dyn RngCore,
+    ) -> Option<(Color, f32)> {
+        let (f_a, pdf_a) = self.a.eval(r_in, rec, direction, rng)?;
+        let (f_b, pdf_b) = self.b.eval(r_in, rec, direction, rng)?;
+        let w = self.weight(rec);
+        Some((f_a * (1.0 - w) + f_b * w, pdf_a * (1.0 - w) + pdf_b * w))
+    }
+}
+
+/// A clear dielectric layer, like varnish or lacquer, on top of another
+/// material. Light either reflects off the coat or refracts into it, then
+/// bounces off the base until it makes it back out through the top. Light
+/// reflected back down at the underside of the coat gets another go at the
+/// base. The coat is infinitely thin, so there's no absorption or sideways
+/// travel within it.
+pub struct CoatedMaterial {
+    pub base: Rc<dyn Material>,
+    pub coat: RoughDielectric,
+}
+
+impl CoatedMaterial {
+    pub fn new(base: Rc<dyn Material>, index_of_refraction: f32, roughness: f32) -> CoatedMaterial {
+        CoatedMaterial {
+            base,
+            coat: RoughDielectric::new(index_of_refraction, roughness),
+        }
+    }
+}
+
+impl Material for CoatedMaterial {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        // only the outside is coated
+        if !rec.front_face {
+            return self.base.scatter(r_in, rec, rng);
+        }
+
+        // off the top of the coat, or through it
+        let (mut weight, mut ray) = self.coat.scatter(r_in, rec, rng)?;
+        if ray.direction * rec.normal > 0.0 {
+            return Some((weight, ray));
+        }
+
+        let uvw = rec.shading_frame();
+        let eta = 1.0 / self.coat.index_of_refraction;
+
+        for _ in 0..MAX_INTERNAL_BOUNCES {
+            let (attenuation, scattered) = self.base.scatter(&ray, rec, rng)?;
+            weight = mul(weight, attenuation);
+
+            let wi = uvw.to_local(scattered.direction.unit_vector());
+            // the base let the light through, it isn't coming back
+            if wi.z <= 0.0 {
+                return None;
+            }
+
+            // out through the underside of the coat, or reflected back down
+            // onto the base
+            if rng.gen::<f32>() < microfacet::fresnel_dielectric(wi.z, eta) {
+                ray = Ray {
+                    origin: rec.p,
+                    direction: uvw.local(wi.x, wi.y, -wi.z),
+                };
+                continue;
+            }
+
+            let wt = microfacet::refract(-wi, Vec3::new(0.0, 0.0, -1.0), eta)?;
+            let scattered = Ray {
+                origin: rec.p,
+                direction: uvw.local(wt.x, wt.y, wt.z),
+            };
+            return Some((weight, scattered));
+        }
+
+        None
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.base.emitted(rec)
+    }
+
+    /// The reflection off the coat, plus the base seen through it. The
+    /// base's part is a random estimate, following a path around inside the
+    /// coat the way `scatter` does and looking out along `direction` at each
+    /// bounce. The pdf only counts the first trip down to the base and back
+    /// up, near enough for weighing against the lights.
+    fn eval(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        direction: Vec3,
+        rng: &mut dyn RngCore,
+    ) -> Option<(Color, f32)> {
+        if !rec.front_face {
+            return self.base.eval(r_in, rec, direction, rng);
+        }
+        if self.coat.distribution.is_smooth() {
+            return None;
+        }
+
+        let uvw = rec.shading_frame();
+        let eta = self.coat.index_of_refraction;
+        let wo = uvw.to_local(-r_in.direction.unit_vector());
+        let wi = uvw.to_local(direction.unit_vector());
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Some((Color::new(0.0, 0.0, 0.0), 0.0));
+        }
+
+        let (coat_f, coat_pdf) = self.coat.eval_local(wo, wi, eta);
+        let coat_f = Color::new(coat_f, coat_f, coat_f);
+
+        // where light leaving along `direction` comes from inside the coat,
+        // and how much the cone is squeezed on the way
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let (Some(down), Some(back)) = (
+            microfacet::refract(wo, up, eta),
+            microfacet::refract(wi, up, eta),
+        ) else {
+            return Some((coat_f, coat_pdf));
+        };
+        let back = uvw.local(-back.x, -back.y, -back.z);
+        let exit = (1.0 - microfacet::fresnel_dielectric(wi.z, eta)) * wi.z
+            / (eta * eta * (back * rec.normal));
+
+        let first = Ray {
+            origin: rec.p,
+            direction: uvw.local(down.x, down.y, down.z),
+        };
+        let (_, base_pdf) = self.base.eval(&first, rec, back, rng)?;
+        let pdf = coat_pdf + (1.0 - microfacet::fresnel_dielectric(wo.z, eta)) * base_pdf * exit;
+
+        let mut f = coat_f;
+        let (mut weight, mut ray) = match self.coat.scatter(r_in, rec, rng) {
+            Some((weight, ray)) if ray.direction * rec.normal < 0.0 => (weight, ray),
+            _ => return Some((f, pdf)),
+        };
+
+        for _ in 0..MAX_INTERNAL_BOUNCES {
+            let (base_f, _) = self.base.eval(&ray, rec, back, rng)?;
+            f += mul(weight, base_f) * exit;
+
+            // on to the next bounce, if the light is reflected back down
+            let (attenuation, scattered) = match self.base.scatter(&ray, rec, rng) {
+                Some(scattered) => scattered,
+                None => break,
+            };
+            let w = uvw.to_local(scattered.direction.unit_vector());
+            if w.z <= 0.0 || rng.gen::<f32>() >= microfacet::fresnel_dielectric(w.z, 1.0 / eta) {
+                break;
+            }
+            weight = mul(weight, attenuation);
+            ray = Ray {
+                origin: rec.p,
+                direction: uvw.local(w.x, w.y, -w.z),
+            };
+        }
+
+        Some((f, pdf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::rayhit::test_hit;
+
+    /// Average weight of light scattered back up off `mat` for light arriving
+    /// at `cos_theta` to the normal.
+    fn albedo(mat: Rc<dyn Material>, cos_theta: f32) -> Color {
+        let mut rng = rand::thread_rng();
+        let (rec, r_in) = test_hit(mat.clone(), cos_theta, true);
+
+        let n = 100_000;
+        let mut total = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            if let Some((attenuation, scattered)) = mat.scatter(&r_in, &rec, &mut rng) {
+                assert!(scattered.direction.y > 0.0);
+                total += attenuation;
+            }
+        }
+
+        total / n as f32
+    }
+
+    fn lambertian(albedo: f32) -> Rc<dyn Material> {
+        Rc::new(Lambertian {
+            albedo: Color::new(albedo, albedo, albedo),
+        })
+    }
+
+    #[test]
+    fn test_mix() {
+        let mix = MixMaterial::new(lambertian(1.0), lambertian(0.0), 0.25);
+        let a = albedo(Rc::new(mix), 1.0);
+        assert!(f32::abs(a.x - 0.75) < 0.01, "{:?}", a);
+    }
+
+    #[test]
+    fn test_coat() {
+        // over black all that's left is the reflection off the top
+        let coated = CoatedMaterial::new(lambertian(0.0), 1.5, 0.0);
+        let a = albedo(Rc::new(coated), 1.0);
+        assert!(f32::abs(a.x - 0.04) < 0.005, "{:?}", a);
+
+        // over white everything gets out eventually
+        for cos_theta in [1.0, 0.5, 0.2] {
+            let coated = CoatedMaterial::new(lambertian(1.0), 1.5, 0.2);
+            let a = albedo(Rc::new(coated), cos_theta);
+            assert!(a.x > 0.97 && a.x <= 1.0, "{} {:?}", cos_theta, a);
+        }
+    }
+
+    #[test]
+    fn test_coat_eval() {
+        let mut rng = rand::thread_rng();
+        // the BSDF adds up to what scattering gives, and the pdf to no more
+        // than one
+        for cos_theta in [1.0, 0.5] {
+            let mat: Rc<dyn Material> = Rc::new(CoatedMaterial::new(lambertian(0.5), 1.5, 0.3));
+            let (rec, r_in) = test_hit(mat.clone(), cos_theta, true);
+
+            let n = 200_000;
+            let mut f = Color::new(0.0, 0.0, 0.0);
+            let mut pdf = 0.0;
+            for _ in 0..n {
+                let (sample_f, sample_pdf) = mat
+                    .eval(&r_in, &rec, Vec3::random_unit_vector(&mut rng), &mut rng)
+                    .unwrap();
+                f += sample_f;
+                pdf += sample_pdf;
+            }
+            let sphere = 4.0 * std::f32::consts::PI / n as f32;
+            let (f, pdf) = (f * sphere, pdf * sphere);
+
+            let a = albedo(mat, cos_theta);
+            assert!(f32::abs(f.x - a.x) < 0.03, "{} {:?} {:?}", cos_theta, f, a);
+            assert!(pdf <= 1.02, "{}", pdf);
+        }
+
+        // nothing to go on with a smooth coat
+        let smooth = CoatedMaterial::new(lambertian(0.5), 1.5, 0.0);
+        let (rec, r_in) = test_hit(lambertian(0.5), 1.0, true);
+        assert!(smooth.eval(&r_in, &rec, rec.normal, &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_mix_interior() {
+        let glass: Rc<dyn Material> = Rc::new(RoughDielectric::new(1.5, 0.1));
+        let mix = MixMaterial::new(lambertian(0.5), glass, 0.5);
+        assert_eq!(mix.interior().unwrap().index_of_refraction, 1.5);
+
+        let mix = MixMaterial::new(lambertian(0.5), lambertian(0.2), 0.5);
+        assert!(mix.interior().is_none());
+    }
+}
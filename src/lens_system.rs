@@ -0,0 +1,494 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use rand::{Rng, RngCore};
+
+use crate::camera::Camera;
+use crate::microfacet;
+use crate::rayhit::Ray;
+use crate::vec3::{Point3, Vec3};
+
+/// A double Gauss 50mm f/2 lens, from US patent 2,673,491 by Tronnier as
+/// given in Modern Lens Design and scaled from 100mm.
+pub const DOUBLE_GAUSS_50MM: &str = "
+    # radius   thickness  ior    aperture
+    29.475     3.76       1.67   25.2
+    84.83      0.12       1      25.2
+    19.275     4.025      1.67   23
+    40.77      3.275      1.699  23
+    12.75      5.705      1      18
+    0          4.5        0      17.1
+    -14.495    1.18       1.603  17
+    40.77      6.065      1.658  20
+    -20.385    0.19       1      20
+    437.065    3.22       1.717  20
+    -39.73     5          1      20
+";
+
+/// One surface of a lens, from the front of the lens toward the film.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LensElement {
+    /// Radius of the spherical surface, positive when it bulges toward the
+    /// front. Zero for the aperture stop.
+    pub curvature_radius: f32,
+    /// How far along the axis it is to the next surface, or the film.
+    pub thickness: f32,
+    /// Index of refraction of what's between this surface and the next.
+    pub ior: f32,
+    pub aperture_radius: f32,
+}
+
+impl LensElement {
+    fn is_stop(&self) -> bool {
+        self.curvature_radius == 0.0
+    }
+}
+
+/// A lens made of spherical elements, with lengths in meters.
+#[derive(Clone, Debug)]
+pub struct LensSystem {
+    pub elements: Vec<LensElement>,
+}
+
+impl LensSystem {
+    pub fn load(path: &Path) -> Result<LensSystem, Error> {
+        LensSystem::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse a lens prescription, a row for each surface from the front:
+    /// curvature radius, thickness, index of refraction and aperture
+    /// diameter, all in millimeters. A radius of zero is the aperture stop.
+    /// Anything after a # is a comment.
+    pub fn parse(text: &str) -> Result<LensSystem, Error> {
+        let mut elements = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let invalid = || {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("bad lens element on line {}: {}", n + 1, line.trim()),
+                )
+            };
+            let values = line
+                .split_whitespace()
+                .map(|v| v.parse::<f32>().map_err(|_| invalid()))
+                .collect::<Result<Vec<f32>, Error>>()?;
+            match values[..] {
+                [radius, thickness, ior, aperture] if aperture > 0.0 => {
+                    elements.push(LensElement {
+                        curvature_radius: radius / 1000.0,
+                        thickness: thickness / 1000.0,
+                        // the stop has nothing after it to refract
+                        ior: if ior == 0.0 { 1.0 } else { ior },
+                        aperture_radius: aperture / 2000.0,
+                    })
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        if elements.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "lens has no elements"));
+        }
+        Ok(LensSystem { elements })
+    }
+
+    pub fn double_gauss() -> LensSystem {
+        LensSystem::parse(DOUBLE_GAUSS_50MM).unwrap()
+    }
+
+    /// Open or close the aperture stop to the diameter in millimeters.
+    pub fn set_stop_diameter(&mut self, diameter: f32) {
+        for element in &mut self.elements {
+            if element.is_stop() {
+                element.aperture_radius = diameter / 2000.0;
+            }
+        }
+    }
+
+    /// Where the back of the lens is, from the film.
+    fn rear_z(&self) -> f32 {
+        -self.elements[self.elements.len() - 1].thickness
+    }
+
+    fn rear_aperture_radius(&self) -> f32 {
+        self.elements[self.elements.len() - 1].aperture_radius
+    }
+
+    /// Trace a ray from the film out through the front of the lens. The
+    /// film is at z = 0 with the lens toward -z. None if the ray hits the
+    /// side of the lens or is reflected inside it.
+    pub fn trace_from_film(&self, ray: Ray) -> Option<Ray> {
+        let mut ray = ray;
+        let mut element_z = 0.0;
+        for (i, element) in self.elements.iter().enumerate().rev() {
+            element_z -= element.thickness;
+
+            let (t, normal) = if element.is_stop() {
+                if ray.direction.z >= 0.0 {
+                    return None;
+                }
+                ((element_z - ray.origin.z) / ray.direction.z, None)
+            } else {
+                let (t, normal) = intersect_element(
+                    &ray,
+                    element.curvature_radius,
+                    element_z + element.curvature_radius,
+                )?;
+                (t, Some(normal))
+            };
+
+            let p = ray.at(t);
+            if p.x * p.x + p.y * p.y > element.aperture_radius * element.aperture_radius {
+                return None;
+            }
+            ray.origin = p;
+
+            if let Some(normal) = normal {
+                let ior_behind = element.ior;
+                let ior_ahead = if i > 0 { self.elements[i - 1].ior } else { 1.0 };
+                let w = -ray.direction.unit_vector();
+                ray.direction = microfacet::refract(w, normal, ior_ahead / ior_behind)?;
+            }
+        }
+
+        Some(ray)
+    }
+
+    /// How far in front of the film a point on the axis is in focus. Traces
+    /// a ray close to the axis and sees where it crosses it.
+    fn focus_distance(&self) -> f32 {
+        let h = self.rear_aperture_radius() * 0.01;
+        let ray = Ray {
+            origin: Point3::new(0.0, 0.0, 0.0),
+            direction: Vec3::new(h, 0.0, self.rear_z()),
+        };
+        match self.trace_from_film(ray) {
+            // still heading toward the axis, or it's past infinity
+            Some(out) if out.direction.x < 0.0 => -out.at(-out.origin.x / out.direction.x).z,
+            _ => f32::INFINITY,
+        }
+    }
+
+    /// Move the film so things `distance` meters in front of it are in
+    /// focus.
+    pub fn focus(&mut self, distance: f32) {
+        // the further back the film, the closer the focus
+        let last = self.elements.len() - 1;
+        let (mut near, mut far) = (1e-4, 0.5);
+        for _ in 0..50 {
+            let mid = (near + far) / 2.0;
+            self.elements[last].thickness = mid;
+            if self.focus_distance() > distance {
+                near = mid;
+            } else {
+                far = mid;
+            }
+        }
+        self.elements[last].thickness = (near + far) / 2.0;
+    }
+}
+
+/// Where the ray hits a spherical surface centered on the axis at
+/// `z_center`, and the normal there facing back along the ray.
+fn intersect_element(ray: &Ray, radius: f32, z_center: f32) -> Option<(f32, Vec3)> {
+    let o = ray.origin - Vec3::new(0.0, 0.0, z_center);
+    let d = ray.direction;
+    let a = d * d;
+    let b = 2.0 * (d * o);
+    let c = o * o - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let root = f32::sqrt(discriminant);
+    let (t0, t1) = ((-b - root) / (2.0 * a), (-b + root) / (2.0 * a));
+    // of the two places the ray crosses the whole sphere, the lens is the
+    // cap nearest the axis crossing at z_center - radius
+    let closer = (d.z > 0.0) ^ (radius < 0.0);
+    let t = if closer {
+        f32::min(t0, t1)
+    } else {
+        f32::max(t0, t1)
+    };
+    if t < 0.0 {
+        return None;
+    }
+
+    let n = (o + d * t).unit_vector();
+    let n = if n * d > 0.0 { -n } else { n };
+    Some((t, n))
+}
+
+/// How many rings of the film the exit pupil is worked out for.
+const PUPIL_RINGS: usize = 64;
+
+/// A camera looking through a lens system, which gives its own vignetting,
+/// distortion and bokeh. Each ring of the film only sees the scene through
+/// part of the back of the lens, the exit pupil, so rays are aimed at a box
+/// around that rather than the whole back of the lens.
+#[derive(Clone, Debug)]
+pub struct LensSystemCamera {
+    lens: LensSystem,
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    /// Half the size of the film.
+    half_width: f32,
+    half_height: f32,
+    /// Bounds of the exit pupil, as min x, max x, min y and max y on the
+    /// back of the lens, for film points on the +x axis in each ring.
+    pupil_bounds: Vec<(f32, f32, f32, f32)>,
+    /// The area of the pupil seen from the middle of the film, which gets a
+    /// weight of one.
+    axial_pupil_area: f32,
+}
+
+impl LensSystemCamera {
+    /// `sensor_width` is in millimeters, and the film is moved to focus at
+    /// `focus_distance` meters.
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        aspect_ratio: f32,
+        lens: &LensSystem,
+        sensor_width: f32,
+        focus_distance: f32,
+    ) -> LensSystemCamera {
+        let mut lens = lens.clone();
+        lens.focus(focus_distance);
+
+        let w = (lookfrom - lookat).unit_vector();
+        let u = vup.cross(w).unit_vector();
+        let v = w.cross(u);
+        let half_width = sensor_width / 2000.0;
+        let half_height = half_width / aspect_ratio;
+
+        let film_radius = f32::sqrt(half_width * half_width + half_height * half_height);
+        let (pupil_bounds, axial_pupil_area) = exit_pupil_bounds(&lens, film_radius);
+
+        LensSystemCamera {
+            lens,
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            half_width,
+            half_height,
+            pupil_bounds,
+            axial_pupil_area,
+        }
+    }
+
+    fn film_radius(&self) -> f32 {
+        f32::sqrt(self.half_width * self.half_width + self.half_height * self.half_height)
+    }
+}
+
+/// Find the exit pupil for each ring of the film by tracing rays from a few
+/// points in the ring to a grid over the back of the lens.
+fn exit_pupil_bounds(lens: &LensSystem, film_radius: f32) -> (Vec<(f32, f32, f32, f32)>, f32) {
+    const GRID: usize = 48;
+    const FILM_POINTS: usize = 3;
+
+    let rear_z = lens.rear_z();
+    // a little bigger than the back element, which can be smaller than
+    // the pupil of an element further in
+    let extent = lens.rear_aperture_radius() * 1.5;
+    let cell = 2.0 * extent / GRID as f32;
+
+    let mut bounds = Vec::with_capacity(PUPIL_RINGS);
+    let mut axial_pupil_area = 0.0;
+    for ring in 0..PUPIL_RINGS {
+        let mut b = (
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+        );
+        let mut through = 0;
+        for k in 0..FILM_POINTS {
+            let r = (ring as f32 + k as f32 / (FILM_POINTS - 1) as f32) / PUPIL_RINGS as f32;
+            let film = Point3::new(r * film_radius, 0.0, 0.0);
+            for i in 0..GRID {
+                for j in 0..GRID {
+                    let x = -extent + (i as f32 + 0.5) * cell;
+                    let y = -extent + (j as f32 + 0.5) * cell;
+                    let ray = Ray {
+                        origin: film,
+                        direction: Point3::new(x, y, rear_z) - film,
+                    };
+                    if lens.trace_from_film(ray).is_some() {
+                        b = (
+                            f32::min(b.0, x),
+                            f32::max(b.1, x),
+                            f32::min(b.2, y),
+                            f32::max(b.3, y),
+                        );
+                        if ring == 0 && k == 0 {
+                            through += 1;
+                        }
+                    }
+                }
+            }
+        }
+        if ring == 0 {
+            axial_pupil_area = through as f32 * cell * cell;
+        }
+
+        // grow by a cell, the grid can miss the very edge
+        if b.0 > b.1 {
+            bounds.push((0.0, 0.0, 0.0, 0.0));
+        } else {
+            bounds.push((b.0 - cell, b.1 + cell, b.2 - cell, b.3 + cell));
+        }
+    }
+
+    (bounds, axial_pupil_area)
+}
+
+impl Camera for LensSystemCamera {
+    fn get_ray(&self, s: f32, t: f32, rng: &mut dyn RngCore) -> Option<Ray> {
+        self.get_weighted_ray(s, t, rng).map(|(ray, _)| ray)
+    }
+
+    fn get_weighted_ray(&self, s: f32, t: f32, rng: &mut dyn RngCore) -> Option<(Ray, f32)> {
+        // the lens turns the image upside down
+        let film = Point3::new(
+            -(2.0 * s - 1.0) * self.half_width,
+            -(2.0 * t - 1.0) * self.half_height,
+            0.0,
+        );
+
+        // pick a point in the pupil for this ring, turned to face the film
+        // point
+        let r = f32::sqrt(film.x * film.x + film.y * film.y);
+        let ring = usize::min(
+            (r / self.film_radius() * PUPIL_RINGS as f32) as usize,
+            PUPIL_RINGS - 1,
+        );
+        let (x0, x1, y0, y1) = self.pupil_bounds[ring];
+        let area = (x1 - x0) * (y1 - y0);
+        if area == 0.0 {
+            return None;
+        }
+        let x = x0 + rng.gen::<f32>() * (x1 - x0);
+        let y = y0 + rng.gen::<f32>() * (y1 - y0);
+        let (sin, cos) = if r > 0.0 {
+            (film.y / r, film.x / r)
+        } else {
+            (0.0, 1.0)
+        };
+        let pupil = Point3::new(cos * x - sin * y, sin * x + cos * y, self.lens.rear_z());
+
+        let direction = pupil - film;
+        let out = self.lens.trace_from_film(Ray {
+            origin: film,
+            direction,
+        })?;
+
+        // light falls off with the fourth power of the cosine to the film,
+        // and with the size of the pupil the point sees
+        let cos_theta = -direction.z / direction.length();
+        let weight = cos_theta.powi(4) * area / self.axial_pupil_area;
+
+        let to_world = |p: Vec3| self.u * p.x + self.v * p.y + self.w * p.z;
+        Some((
+            Ray {
+                origin: self.origin + to_world(out.origin),
+                direction: to_world(out.direction),
+            },
+            weight,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lens() {
+        let lens = LensSystem::double_gauss();
+        assert_eq!(lens.elements.len(), 11);
+        assert_eq!(lens.elements[5].curvature_radius, 0.0);
+        assert_eq!(lens.elements[5].ior, 1.0);
+        assert!(f32::abs(lens.elements[0].aperture_radius - 0.0126) < 1e-6);
+
+        assert!(LensSystem::parse("1 2 3").is_err());
+        assert!(LensSystem::parse("# nothing").is_err());
+    }
+
+    #[test]
+    fn test_lens_focus() {
+        let mut lens = LensSystem::double_gauss();
+
+        // straight down the axis and out the front
+        let axial = lens
+            .trace_from_film(Ray {
+                origin: Point3::new(0.0, 0.0, 0.0),
+                direction: Vec3::new(0.0, 0.0, -1.0),
+            })
+            .unwrap();
+        assert!(axial.direction.x == 0.0 && axial.direction.z < 0.0);
+
+        // rays from the middle of the film through the lens near its axis
+        // meet at the focus distance, further out spherical aberration
+        // moves them
+        lens.focus(5.0);
+        let rear_z = lens.rear_z();
+        for h in [0.0005, 0.001] {
+            let out = lens
+                .trace_from_film(Ray {
+                    origin: Point3::new(0.0, 0.0, 0.0),
+                    direction: Vec3::new(h, 0.0, rear_z),
+                })
+                .unwrap();
+            let z = out.at(-out.origin.x / out.direction.x).z;
+            assert!(f32::abs(-z / 5.0 - 1.0) < 0.02, "{} {}", h, z);
+        }
+
+        // this design's back focus is a little over 36mm, and focusing
+        // closer than infinity moves the film a little further back
+        assert!(-rear_z > 0.036 && -rear_z < 0.04);
+    }
+
+    #[test]
+    fn test_lens_camera() {
+        let mut rng = rand::thread_rng();
+        let camera = LensSystemCamera::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            1.5,
+            &LensSystem::double_gauss(),
+            36.0,
+            10.0,
+        );
+
+        // the middle looks straight ahead, the right of the image to the
+        // right
+        let mut center = None;
+        let mut right = None;
+        for _ in 0..100 {
+            center = center.or(camera.get_weighted_ray(0.5, 0.5, &mut rng));
+            right = right.or(camera.get_weighted_ray(0.9, 0.5, &mut rng));
+        }
+        let (center, center_weight) = center.unwrap();
+        let (right, right_weight) = right.unwrap();
+        let to_focus = center.at(-10.0 / center.direction.z);
+        assert!(f32::abs(to_focus.x) < 0.02 && f32::abs(to_focus.y) < 0.02);
+        assert!(right.direction.x > 0.0);
+
+        // and the edges are darker
+        assert!(center_weight > 0.5 && right_weight < center_weight);
+    }
+}
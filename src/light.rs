@@ -0,0 +1,412 @@
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use crate::light_bvh::{LightBounds, LightBvh};
+use crate::material::Material;
+use crate::rayhit::HitRecord;
+use crate::vec3::{Color, Point3, Vec3};
+
+/// A direction picked toward a light.
+#[derive(Copy, Clone, Debug)]
+pub struct LightSample {
+    /// Unit direction from the origin toward the light.
+    pub direction: Vec3,
+    /// The pdf of picking the direction, per unit solid angle. For delta
+    /// lights it's just the probability of picking the light.
+    pub pdf: f32,
+    /// For delta lights, at a single point or in a single direction, the
+    /// distance to the light and the light arriving from it. Other lights
+    /// are found by tracing a shadow ray along the direction.
+    pub delta: Option<(f32, Color)>,
+}
+
+/// Something that gives off light which can be aimed at directly, rather
+/// than waiting for scattered rays to find it. Used for next event
+/// estimation.
+pub trait Light {
+    /// Pick a direction from `origin` toward the light, with u1 and u2
+    /// uniform in [0, 1).
+    fn sample(&self, origin: Point3, u1: f32, u2: f32) -> Option<LightSample>;
+
+    /// The pdf of `sample` returning `direction` from `origin`, zero if the
+    /// direction misses the light. Always zero for delta lights, which
+    /// scattered rays can't find.
+    fn pdf(&self, origin: Point3, direction: Vec3) -> f32;
+
+    /// Light arriving along `direction` from a light infinitely far away,
+    /// for rays that leave the scene.
+    fn escaped(&self, _direction: Vec3) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    /// Where the light is and how much it gives off, for picking lights.
+    /// None for lights infinitely far away.
+    fn bounds(&self) -> Option<LightBounds>;
+}
+
+/// How lights are picked for next event estimation.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LightSampling {
+    /// Every light is as likely as any other.
+    Uniform,
+    /// In proportion to their power.
+    Power,
+    /// By how much they might light the point, using a light BVH.
+    #[default]
+    Bvh,
+}
+
+/// The emitted light of a surface facing out along `normal`, averaged over
+/// the color channels. For estimating the power of area lights.
+pub fn surface_radiance(mat: &Rc<dyn Material>, p: Point3, normal: Vec3) -> f32 {
+    let rec = HitRecord {
+        p,
+        normal,
+        u: 0.5,
+        v: 0.5,
+        dpdu: Vec3::new(0.0, 0.0, 0.0),
+        dpdv: Vec3::new(0.0, 0.0, 0.0),
+        mat: mat.clone(),
+        t: 0.0,
+        front_face: true,
+    };
+    let emitted = mat.emitted(&rec);
+    (emitted.x + emitted.y + emitted.z) / 3.0
+}
+
+/// All the lights in a scene. Until `build` is called with another way of
+/// picking them, each is picked with equal probability.
+pub struct LightList {
+    lights: Vec<Rc<dyn Light>>,
+    sampling: LightSampling,
+    /// Lights infinitely far away, which aren't in the BVH or power
+    /// distribution and are picked uniformly instead.
+    infinite: Vec<usize>,
+    /// For power sampling, the cumulative probabilities of the other lights
+    /// and the probability of each.
+    power_cdf: Vec<(usize, f32)>,
+    power_pmf: Vec<f32>,
+    bvh: Option<LightBvh>,
+}
+
+impl LightList {
+    pub fn new() -> LightList {
+        LightList {
+            lights: Vec::new(),
+            sampling: LightSampling::Uniform,
+            infinite: Vec::new(),
+            power_cdf: Vec::new(),
+            power_pmf: Vec::new(),
+            bvh: None,
+        }
+    }
+
+    pub fn add(&mut self, light: Rc<dyn Light>) {
+        if light.bounds().is_none() {
+            self.infinite.push(self.lights.len());
+        }
+        self.lights.push(light);
+
+        if self.sampling != LightSampling::Uniform {
+            self.build(self.sampling);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    /// Set up for picking lights the given way.
+    pub fn build(&mut self, sampling: LightSampling) {
+        self.sampling = sampling;
+        self.infinite.clear();
+        self.power_cdf.clear();
+        self.power_pmf = vec![0.0; self.lights.len()];
+        self.bvh = None;
+
+        let mut bounded = Vec::new();
+        for (i, light) in self.lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) => bounded.push((i, bounds)),
+                None => self.infinite.push(i),
+            }
+        }
+
+        match sampling {
+            LightSampling::Uniform => {}
+            LightSampling::Power => {
+                let total: f32 = bounded.iter().map(|(_, bounds)| bounds.power).sum();
+                if total > 0.0 {
+                    let mut cdf = 0.0;
+                    for (i, bounds) in &bounded {
+                        if bounds.power > 0.0 {
+                            cdf += bounds.power / total;
+                            self.power_cdf.push((*i, cdf));
+                            self.power_pmf[*i] = bounds.power / total;
+                        }
+                    }
+                }
+            }
+            LightSampling::Bvh => self.bvh = Some(LightBvh::new(&bounded, self.lights.len())),
+        }
+    }
+
+    /// Whether there are lights with bounds that can be picked.
+    fn has_bounded(&self) -> bool {
+        match self.sampling {
+            LightSampling::Uniform => false,
+            LightSampling::Power => !self.power_cdf.is_empty(),
+            LightSampling::Bvh => self.bvh.as_ref().is_some_and(|bvh| !bvh.is_empty()),
+        }
+    }
+
+    /// The probability of picking one of the infinite lights, which share
+    /// the choice equally with all the bounded lights together.
+    fn infinite_probability(&self) -> f32 {
+        if self.infinite.is_empty() {
+            return 0.0;
+        }
+
+        let n = self.infinite.len() as f32;
+        n / (n + if self.has_bounded() { 1.0 } else { 0.0 })
+    }
+
+    /// Pick a light for lighting `origin` with u uniform in [0, 1). Returns
+    /// its index and the probability of picking it.
+    fn pick(&self, origin: Point3, u: f32) -> Option<(usize, f32)> {
+        if self.lights.is_empty() {
+            return None;
+        }
+
+        if self.sampling == LightSampling::Uniform {
+            let n = self.lights.len();
+            let i = usize::min((u * n as f32) as usize, n - 1);
+            return Some((i, 1.0 / n as f32));
+        }
+
+        let p_infinite = self.infinite_probability();
+        if u < p_infinite {
+            let n = self.infinite.len();
+            let i = usize::min((u / p_infinite * n as f32) as usize, n - 1);
+            return Some((self.infinite[i], p_infinite / n as f32));
+        }
+        if !self.has_bounded() {
+            return None;
+        }
+        let u = f32::min((u - p_infinite) / (1.0 - p_infinite), 1.0 - f32::EPSILON);
+
+        let (i, pmf) = match (&self.sampling, &self.bvh) {
+            (LightSampling::Bvh, Some(bvh)) => bvh.sample(origin, u)?,
+            _ => {
+                let pick = self.power_cdf.partition_point(|(_, cdf)| *cdf <= u);
+                let (i, _) = self.power_cdf[usize::min(pick, self.power_cdf.len() - 1)];
+                (i, self.power_pmf[i])
+            }
+        };
+        Some((i, pmf * (1.0 - p_infinite)))
+    }
+
+    /// The probability of `pick` returning light `i` for `origin`.
+    fn pmf(&self, origin: Point3, i: usize) -> f32 {
+        if self.sampling == LightSampling::Uniform {
+            return 1.0 / self.lights.len() as f32;
+        }
+
+        let p_infinite = self.infinite_probability();
+        if self.infinite.contains(&i) {
+            return p_infinite / self.infinite.len() as f32;
+        }
+
+        let pmf = match &self.bvh {
+            Some(bvh) => bvh.pmf(origin, i),
+            None => self.power_pmf[i],
+        };
+        pmf * (1.0 - p_infinite)
+    }
+
+    /// Pick a light with u0, then a direction toward it with u1 and u2. The
+    /// pdf returned is that of the chosen light only, use `pdf` for the
+    /// whole list.
+    pub fn sample(&self, origin: Point3, u0: f32, u1: f32, u2: f32) -> Option<LightSample> {
+        let (i, pmf) = self.pick(origin, u0)?;
+        let sample = self.lights[i].sample(origin, u1, u2)?;
+        Some(LightSample {
+            pdf: sample.pdf * pmf,
+            ..sample
+        })
+    }
+
+    /// The pdf of `sample` returning `direction` from `origin` through any
+    /// of the lights. Lights can overlap when seen from `origin`, so this
+    /// sums over all of them.
+    pub fn pdf(&self, origin: Point3, direction: Vec3) -> f32 {
+        let mut total = 0.0;
+        for (i, light) in self.lights.iter().enumerate() {
+            let pdf = light.pdf(origin, direction);
+            if pdf > 0.0 {
+                total += pdf * self.pmf(origin, i);
+            }
+        }
+        total
+    }
+
+    /// Light arriving along `direction` from all the lights infinitely far
+    /// away.
+    pub fn escaped(&self, direction: Vec3) -> Color {
+        let mut total = Color::new(0.0, 0.0, 0.0);
+        for &i in &self.infinite {
+            total += self.lights[i].escaped(direction);
+        }
+        total
+    }
+}
+
+/// Veach's power heuristic with exponent 2, the weight for a sample taken
+/// with pdf `a` when it could also have come from a strategy with pdf `b`.
+pub fn power_heuristic(a: f32, b: f32) -> f32 {
+    let a2 = a * a;
+    let b2 = b * b;
+    if a2 + b2 == 0.0 {
+        return 0.0;
+    }
+
+    a2 / (a2 + b2)
+}
+
+/// One minus the cosine of the cone's half angle, worked out from its sine
+/// squared so that it doesn't round to zero for distant lights.
+fn one_minus_cos(sin2_theta_max: f32) -> f32 {
+    sin2_theta_max / (1.0 + f32::sqrt(1.0 - sin2_theta_max))
+}
+
+/// A direction uniformly distributed within the cone around the z axis
+/// with the given sine squared of its half angle.
+pub fn sample_cone(sin2_theta_max: f32, u1: f32, u2: f32) -> Vec3 {
+    let cos_theta = 1.0 - u1 * one_minus_cos(sin2_theta_max);
+    let sin_theta = f32::sqrt(f32::max(0.0, 1.0 - cos_theta * cos_theta));
+    let phi = 2.0 * PI * u2;
+
+    Vec3::new(
+        sin_theta * f32::cos(phi),
+        sin_theta * f32::sin(phi),
+        cos_theta,
+    )
+}
+
+/// The pdf of `sample_cone`, per unit solid angle.
+pub fn cone_pdf(sin2_theta_max: f32) -> f32 {
+    1.0 / (2.0 * PI * one_minus_cos(sin2_theta_max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Hittable;
+    use crate::material::DiffuseLight;
+    use crate::rayhit::Ray;
+    use crate::sphere::Sphere;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_power_heuristic() {
+        assert_eq!(power_heuristic(1.0, 0.0), 1.0);
+        assert_eq!(power_heuristic(0.0, 1.0), 0.0);
+        assert_eq!(power_heuristic(0.0, 0.0), 0.0);
+        assert!(f32::abs(power_heuristic(1.0, 2.0) + power_heuristic(2.0, 1.0) - 1.0) < 1e-6);
+    }
+
+    #[test]
+    fn test_sample_cone() {
+        let sin2_theta_max = 0.36;
+        for i in 0..100 {
+            let w = sample_cone(sin2_theta_max, i as f32 / 100.0, 0.37);
+            assert!(f32::abs(w.length() - 1.0) < 1e-5);
+            assert!(w.z >= 0.8 - 1e-6);
+        }
+
+        // the pdf integrates to one over the cone
+        let solid_angle = 2.0 * PI * (1.0 - 0.8);
+        assert!(f32::abs(cone_pdf(sin2_theta_max) * solid_angle - 1.0) < 1e-5);
+
+        // a tiny cone still has a finite pdf
+        let solid_angle = PI * 1e-10;
+        assert!(f32::abs(cone_pdf(1e-10) * solid_angle - 1.0) < 1e-5);
+    }
+
+    const SAMPLES: usize = 20000;
+
+    /// The mean and variance of estimating the light arriving at a point
+    /// under a grid of small lights, a few of them much brighter, and what
+    /// it should come to.
+    fn estimate_irradiance(sampling: LightSampling) -> (f32, f32, f32) {
+        let origin = Point3::new(-6.0, 0.0, -6.0);
+        let mut spheres = Vec::new();
+        let mut lights = LightList::new();
+        let mut exact = 0.0;
+        for i in 0..64 {
+            let center = Point3::new((i % 8) as f32 * 2.0 - 7.0, 1.0, (i / 8) as f32 * 2.0 - 7.0);
+            let brightness = if i % 13 == 0 { 200.0 } else { 1.0 };
+            let sphere = Sphere::new(
+                center,
+                0.1,
+                Rc::new(DiffuseLight {
+                    emit: Color::new(brightness, brightness, brightness),
+                }),
+            );
+            lights.add(sphere.light().unwrap());
+            spheres.push(sphere);
+
+            // small and far enough away to count as a point
+            let to_light = center - origin;
+            let d2 = to_light.length_squared();
+            exact += brightness * PI * 0.01 / d2 * (to_light.y / d2.sqrt());
+        }
+        lights.build(sampling);
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let (mut sum, mut sum_sq) = (0.0, 0.0);
+        for _ in 0..SAMPLES {
+            let mut estimate = 0.0;
+            if let Some(sample) = lights.sample(origin, rng.gen(), rng.gen(), rng.gen()) {
+                let ray = Ray {
+                    origin,
+                    direction: sample.direction,
+                };
+                let hit = spheres
+                    .iter()
+                    .filter_map(|sphere| sphere.hit(ray, 1e-4, f32::INFINITY, &mut rng))
+                    .min_by(|a, b| a.t.total_cmp(&b.t));
+                if let Some(rec) = hit {
+                    let cos_theta = f32::max(sample.direction.y, 0.0);
+                    estimate = rec.mat.emitted(&rec).x * cos_theta / sample.pdf;
+                }
+            }
+            sum += estimate;
+            sum_sq += estimate * estimate;
+        }
+
+        let mean = sum / SAMPLES as f32;
+        (mean, sum_sq / SAMPLES as f32 - mean * mean, exact)
+    }
+
+    #[test]
+    fn test_light_sampling_variance() {
+        let mut variances = Vec::new();
+        for sampling in [
+            LightSampling::Uniform,
+            LightSampling::Power,
+            LightSampling::Bvh,
+        ] {
+            // unbiased, to within a few standard errors
+            let (mean, variance, exact) = estimate_irradiance(sampling);
+            let error = f32::sqrt(variance / SAMPLES as f32);
+            assert!(f32::abs(mean - exact) < 4.0 * error + 0.01 * exact);
+            variances.push(variance);
+        }
+
+        // with less noise the better the lights are picked
+        assert!(variances[1] < variances[0]);
+        assert!(variances[2] < variances[1]);
+    }
+}
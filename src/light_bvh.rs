@@ -0,0 +1,402 @@
+use std::f32::consts::PI;
+
+use crate::aabb::Aabb;
+use crate::vec3::{Point3, Vec3};
+
+/// Where a light is, how much it gives off and which way, for guessing how
+/// much it can light a point without looking at it in detail. Following
+/// Conty Estevez and Kulla 2018, as in pbrt-v4.
+#[derive(Copy, Clone, Debug)]
+pub struct LightBounds {
+    pub bounds: Aabb,
+    /// Total power, averaged over the color channels.
+    pub power: f32,
+    /// Axis of the cone around the normals of the emitting surfaces.
+    pub axis: Vec3,
+    /// Cosine of that cone's half angle, -1 for every direction.
+    pub cos_theta_o: f32,
+    /// Cosine of how far past the normals the light goes, 0 for diffuse
+    /// emitters that shine out to the horizon.
+    pub cos_theta_e: f32,
+}
+
+/// cos(max(0, a - b)) from the sines and cosines of a and b.
+fn cos_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b {
+        return 1.0;
+    }
+    cos_a * cos_b + sin_a * sin_b
+}
+
+/// sin(max(0, a - b)) from the sines and cosines of a and b.
+fn sin_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b {
+        return 0.0;
+    }
+    sin_a * cos_b - cos_a * sin_b
+}
+
+fn sin_from_cos(cos_theta: f32) -> f32 {
+    f32::sqrt(f32::max(0.0, 1.0 - cos_theta * cos_theta))
+}
+
+/// Rotate `v` by `angle` radians around the unit vector `axis`.
+fn rotate(v: Vec3, axis: Vec3, angle: f32) -> Vec3 {
+    let (sin, cos) = f32::sin_cos(angle);
+    v * cos + axis.cross(v) * sin + axis * ((axis * v) * (1.0 - cos))
+}
+
+impl LightBounds {
+    /// A guess at how much light reaches `p`, which is zero only where none
+    /// can.
+    pub fn importance(&self, p: Point3) -> f32 {
+        if self.power == 0.0 {
+            return 0.0;
+        }
+
+        let center = self.bounds.center();
+        let radius = (self.bounds.max - self.bounds.min).length() / 2.0;
+        let to_p = p - center;
+        let distance_squared = to_p.length_squared();
+
+        // the angle from the axis to p, less the spread of the normals and
+        // the size of the bounds seen from p
+        let cos_theta_w = if distance_squared > 0.0 {
+            self.axis * (to_p / f32::sqrt(distance_squared))
+        } else {
+            1.0
+        };
+        let sin_theta_w = sin_from_cos(cos_theta_w);
+        let (sin_theta_b, cos_theta_b) = if distance_squared > radius * radius {
+            let sin2_theta_b = radius * radius / distance_squared;
+            (f32::sqrt(sin2_theta_b), f32::sqrt(1.0 - sin2_theta_b))
+        } else {
+            (0.0, -1.0)
+        };
+        let sin_theta_o = sin_from_cos(self.cos_theta_o);
+
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0;
+        }
+
+        // inside the bounds the distance is no help, so use their size
+        let distance_squared = f32::max(distance_squared, radius * radius);
+        self.power * cos_theta_p / f32::max(distance_squared, 1e-6)
+    }
+
+    /// Bounds covering both lights.
+    pub fn union(&self, other: &LightBounds) -> LightBounds {
+        if self.power == 0.0 {
+            return *other;
+        }
+        if other.power == 0.0 {
+            return *self;
+        }
+
+        let (axis, cos_theta_o) =
+            union_cones(self.axis, self.cos_theta_o, other.axis, other.cos_theta_o);
+        LightBounds {
+            bounds: self.bounds.union(&other.bounds),
+            power: self.power + other.power,
+            axis,
+            cos_theta_o,
+            cos_theta_e: f32::min(self.cos_theta_e, other.cos_theta_e),
+        }
+    }
+}
+
+/// The smallest cone of directions containing both cones, given by their
+/// axes and the cosines of their half angles.
+fn union_cones(a: Vec3, cos_a: f32, b: Vec3, cos_b: f32) -> (Vec3, f32) {
+    let everywhere = (Vec3::new(0.0, 0.0, 1.0), -1.0);
+
+    let theta_a = f32::acos(cos_a.clamp(-1.0, 1.0));
+    let theta_b = f32::acos(cos_b.clamp(-1.0, 1.0));
+    let theta_d = f32::acos((a * b).clamp(-1.0, 1.0));
+
+    // one cone already holds the other
+    if f32::min(theta_d + theta_b, PI) <= theta_a {
+        return (a, cos_a);
+    }
+    if f32::min(theta_d + theta_a, PI) <= theta_b {
+        return (b, cos_b);
+    }
+
+    let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+    if theta_o >= PI {
+        return everywhere;
+    }
+
+    // turn a's axis toward b's until the cone reaches both
+    let rotation_axis = a.cross(b);
+    if rotation_axis.length_squared() == 0.0 {
+        return everywhere;
+    }
+    let axis = rotate(a, rotation_axis.unit_vector(), theta_o - theta_a);
+    (axis, f32::cos(theta_o))
+}
+
+enum NodeKind {
+    /// Index of the light in the list it was built from.
+    Leaf(usize),
+    /// Indices of the two children.
+    Interior(usize, usize),
+}
+
+struct Node {
+    bounds: LightBounds,
+    kind: NodeKind,
+}
+
+/// Tree of light bounds for picking lights in proportion to how much they
+/// might light a point. Each step down picks a child by its importance, so
+/// far away or faint groups of lights are rarely visited. Splits are at the
+/// median along the widest spread of the lights' centers, which is simpler
+/// than the paper's orientation aware cost and works well enough when
+/// lights are scattered around.
+pub struct LightBvh {
+    nodes: Vec<Node>,
+    /// For each light, the turns from the root to its leaf, one bit per
+    /// level starting from the lowest with 1 for the second child.
+    trails: Vec<Option<u64>>,
+}
+
+impl LightBvh {
+    /// Build the tree over `lights`, pairs of an index and its bounds. The
+    /// indices can go up to `count`. Lights that give off nothing are left
+    /// out.
+    pub fn new(lights: &[(usize, LightBounds)], count: usize) -> LightBvh {
+        let mut bvh = LightBvh {
+            nodes: Vec::new(),
+            trails: (0..count).map(|_| None).collect(),
+        };
+
+        let mut lights: Vec<(usize, LightBounds)> = lights
+            .iter()
+            .filter(|(_, bounds)| bounds.power > 0.0)
+            .copied()
+            .collect();
+        if !lights.is_empty() {
+            bvh.build(&mut lights, 0, 0);
+        }
+        bvh
+    }
+
+    fn build(&mut self, lights: &mut [(usize, LightBounds)], trail: u64, depth: u32) -> usize {
+        let index = self.nodes.len();
+        if lights.len() == 1 {
+            let (light, bounds) = lights[0];
+            self.nodes.push(Node {
+                bounds,
+                kind: NodeKind::Leaf(light),
+            });
+            self.trails[light] = Some(trail);
+            return index;
+        }
+
+        // sort along the axis the centers are most spread out on, and split
+        // in the middle
+        let mut centers = Aabb::new(lights[0].1.bounds.center(), lights[0].1.bounds.center());
+        for (_, bounds) in lights.iter() {
+            let c = bounds.bounds.center();
+            centers = centers.union(&Aabb::new(c, c));
+        }
+        let extent = centers.max - centers.min;
+        let key = |bounds: &LightBounds| {
+            let c = bounds.bounds.center();
+            if extent.x >= extent.y && extent.x >= extent.z {
+                c.x
+            } else if extent.y >= extent.z {
+                c.y
+            } else {
+                c.z
+            }
+        };
+        lights.sort_by(|a, b| key(&a.1).total_cmp(&key(&b.1)));
+
+        // placeholder until the children are built
+        self.nodes.push(Node {
+            bounds: lights[0].1,
+            kind: NodeKind::Leaf(lights[0].0),
+        });
+        let (first, second) = lights.split_at_mut(lights.len() / 2);
+        let a = self.build(first, trail, depth + 1);
+        let b = self.build(second, trail | (1 << depth), depth + 1);
+        self.nodes[index] = Node {
+            bounds: self.nodes[a].bounds.union(&self.nodes[b].bounds),
+            kind: NodeKind::Interior(a, b),
+        };
+        index
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Whether the root, when it's the only node, can light `p` at all.
+    /// Otherwise the importances of the children decide.
+    fn root_reaches(&self, node: usize, p: Point3) -> bool {
+        node > 0 || self.nodes[0].bounds.importance(p) > 0.0
+    }
+
+    /// Pick a light to light `p` with u uniform in [0, 1). Returns its index
+    /// and the probability of picking it.
+    pub fn sample(&self, p: Point3, u: f32) -> Option<(usize, f32)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut node = 0;
+        let mut pmf = 1.0;
+        let mut u = u;
+        loop {
+            match self.nodes[node].kind {
+                NodeKind::Leaf(light) => {
+                    return self.root_reaches(node, p).then_some((light, pmf));
+                }
+                NodeKind::Interior(a, b) => {
+                    let importance_a = self.nodes[a].bounds.importance(p);
+                    let importance_b = self.nodes[b].bounds.importance(p);
+                    if importance_a == 0.0 && importance_b == 0.0 {
+                        return None;
+                    }
+
+                    let p_a = importance_a / (importance_a + importance_b);
+                    if u < p_a {
+                        node = a;
+                        pmf *= p_a;
+                        u /= p_a;
+                    } else {
+                        node = b;
+                        pmf *= 1.0 - p_a;
+                        u = (u - p_a) / (1.0 - p_a);
+                    }
+                    u = f32::min(u, 1.0 - f32::EPSILON);
+                }
+            }
+        }
+    }
+
+    /// The probability of `sample` picking `light` for `p`.
+    pub fn pmf(&self, p: Point3, light: usize) -> f32 {
+        let mut trail = match self.trails.get(light).copied().flatten() {
+            Some(trail) => trail,
+            None => return 0.0,
+        };
+
+        let mut node = 0;
+        let mut pmf = 1.0;
+        loop {
+            match self.nodes[node].kind {
+                NodeKind::Leaf(_) => {
+                    return if self.root_reaches(node, p) { pmf } else { 0.0 };
+                }
+                NodeKind::Interior(a, b) => {
+                    let importance_a = self.nodes[a].bounds.importance(p);
+                    let importance_b = self.nodes[b].bounds.importance(p);
+                    if importance_a == 0.0 && importance_b == 0.0 {
+                        return 0.0;
+                    }
+
+                    let p_a = importance_a / (importance_a + importance_b);
+                    if trail & 1 == 0 {
+                        node = a;
+                        pmf *= p_a;
+                    } else {
+                        node = b;
+                        pmf *= 1.0 - p_a;
+                    }
+                    trail >>= 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(p: Point3, power: f32) -> LightBounds {
+        LightBounds {
+            bounds: Aabb::new(p, p),
+            power,
+            axis: Vec3::new(0.0, 0.0, 1.0),
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_importance() {
+        // inverse square falloff from a point
+        let light = point(Point3::new(0.0, 0.0, 0.0), 1.0);
+        let near = light.importance(Point3::new(1.0, 0.0, 0.0));
+        let far = light.importance(Point3::new(2.0, 0.0, 0.0));
+        assert!(f32::abs(near / far - 4.0) < 1e-4);
+
+        // a panel facing up lights nothing below it
+        let panel = LightBounds {
+            bounds: Aabb::new(Point3::new(-1.0, 0.0, -1.0), Point3::new(1.0, 0.0, 1.0)),
+            power: 1.0,
+            axis: Vec3::new(0.0, 1.0, 0.0),
+            cos_theta_o: 1.0,
+            cos_theta_e: 0.0,
+        };
+        assert!(panel.importance(Point3::new(0.0, 3.0, 0.0)) > 0.0);
+        assert!(panel.importance(Point3::new(5.0, 0.5, 0.0)) > 0.0);
+        assert_eq!(panel.importance(Point3::new(0.0, -3.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn test_union_cones() {
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let y = Vec3::new(0.0, 1.0, 0.0);
+
+        // two narrow cones 90 degrees apart need a 45 degree cone between
+        let (axis, cos_theta) = union_cones(x, 1.0, y, 1.0);
+        assert!((axis - Vec3::new(1.0, 1.0, 0.0).unit_vector()).length() < 1e-5);
+        assert!(f32::abs(cos_theta - f32::cos(PI / 4.0)) < 1e-5);
+
+        // one inside the other
+        let (axis, cos_theta) = union_cones(x, 0.0, Vec3::new(1.0, 0.1, 0.0).unit_vector(), 0.99);
+        assert_eq!((axis, cos_theta), (x, 0.0));
+
+        // opposite hemispheres cover everything
+        assert_eq!(union_cones(x, 0.0, -x, 0.0).1, -1.0);
+    }
+
+    #[test]
+    fn test_light_bvh() {
+        let lights: Vec<(usize, LightBounds)> = (0..20)
+            .map(|i| (i, point(Point3::new(i as f32, 0.0, 0.0), 1.0)))
+            .chain(std::iter::once((
+                20,
+                point(Point3::new(0.0, 0.0, 0.0), 0.0),
+            )))
+            .collect();
+        let bvh = LightBvh::new(&lights, 21);
+        let p = Point3::new(2.2, 1.0, 0.0);
+
+        // the pmf matches how often each light is picked, and adds up to one
+        let n = 100_000;
+        let mut counts = [0; 21];
+        for i in 0..n {
+            let (light, pmf) = bvh.sample(p, (i as f32 + 0.5) / n as f32).unwrap();
+            assert!(f32::abs(pmf - bvh.pmf(p, light)) < 1e-5);
+            counts[light] += 1;
+        }
+        let total: f32 = (0..21).map(|i| bvh.pmf(p, i)).sum();
+        assert!(f32::abs(total - 1.0) < 1e-4);
+        for (i, count) in counts.iter().enumerate() {
+            assert!(f32::abs(*count as f32 / n as f32 - bvh.pmf(p, i)) < 2e-3);
+        }
+
+        // nearby lights are picked more, and the dark one never
+        assert!(bvh.pmf(p, 2) > 10.0 * bvh.pmf(p, 19));
+        assert_eq!(bvh.pmf(p, 20), 0.0);
+    }
+}
@@ -1,13 +1,18 @@
 #![deny(clippy::all)]
 #![forbid(unsafe_code)]
 
-use std::{fs::File, io::Error};
+use std::{fs::{self, File}, io::Error, io::ErrorKind};
 use std::io::BufWriter;
 use std::path::Path;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 mod material;
-use material::{Dialetric, Lambertian, Metal};
+mod microfacet;
+use material::{
+    Dialetric, DiffuseLight, HenyeyGreenstein, Isotropic, Lambertian, Material, Metal,
+    RoughDielectric, Subsurface, Velvet,
+};
 
 // I'm not sure that I'm doing this correctly.
 mod vec3;
@@ -19,13 +24,98 @@ use sphere::Sphere;
 mod hittable;
 use hittable::{Hittable, HittableList};
 
+mod animation;
+use animation::{CameraAnimation, CameraPose, Interpolation, Track};
+
+mod aperture;
+use aperture::Aperture;
+
 mod camera;
-use camera::Camera;
+use camera::{
+    Camera, EquirectangularCamera, FisheyeCamera, FisheyeProjection, OrthographicCamera,
+    PerspectiveCamera, PhysicalCamera, PhysicalSettings, StereoCamera, StereoLayout,
+    StereoProjection, StereoSettings,
+};
 
 mod rayhit;
-use rayhit::Ray;
+use rayhit::{HitRecord, Ray};
+
+mod onb;
+
+mod aabb;
+use aabb::Aabb;
+
+mod cuboid;
+use cuboid::Cuboid;
+
+mod cutout;
+use cutout::Cutout;
+
+mod distribution;
+
+mod environment;
+use environment::EnvironmentMap;
+
+mod checkpoint;
+use checkpoint::Checkpoint;
+
+mod csg;
+use csg::{Difference, Intersection, Union};
+
+mod sdf;
+use sdf::{
+    Mandelbulb, Repeat, RoundBox, SdfObject, SdfSphere, SmoothUnion, Torus, Translate, TriPrism,
+    Twist,
+};
+
+mod grid;
+
+mod light;
+use light::LightSampling;
+
+mod light_bvh;
+
+mod lens_system;
+use lens_system::{LensSystem, LensSystemCamera};
+
+mod layered;
+use layered::{CoatedMaterial, MixMaterial};
 
-use rand::{rngs::ThreadRng, Rng};
+mod medium;
+use medium::MediumStack;
+
+mod normal_map;
+use normal_map::NormalMapped;
+
+mod punctual;
+use punctual::{DirectionalLight, PointLight, SpotLight};
+
+mod quad;
+use quad::Quad;
+
+mod principled;
+use principled::Principled;
+
+mod sky;
+use sky::Sky;
+
+mod spectrum;
+use spectrum::{Dispersion, SampledWavelengths};
+
+mod thin_film;
+use thin_film::{IridescentDielectric, IridescentMetal, ThinFilm};
+
+mod transform;
+use transform::{Transform, Transformed};
+
+mod texture;
+use texture::{Checker, ImageTexture};
+
+mod volume;
+use volume::{ConstantMedium, Fog, GridVolume};
+
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
 
 fn clamp(x: f32, min: f32, max: f32) -> f32 {
     if x < min {
@@ -56,32 +146,258 @@ fn calculate_color(color: Vec3, samples_per_pixel: i32) -> Vec3 {
     }
 }
 
-/// Determine the color of a pixel for a given ray.
-fn color_pixel(ray: &Ray, world: &HittableList, depth: i32) -> Vec3 {
+/// Everything rays are traced against.
+struct Scene {
+    world: HittableList,
+    /// Haze over the whole scene, if any.
+    fog: Option<Fog>,
+    /// Light arriving from rays that leave the scene.
+    background: Color,
+}
+
+impl Scene {
+    fn new() -> Scene {
+        Scene {
+            world: HittableList::new(),
+            fog: None,
+            // white-ish background
+            background: Color::new(0.8, 0.8, 0.8),
+        }
+    }
+
+    /// Light arriving along rays that leave the scene.
+    fn escaped(&self, direction: Vec3) -> Color {
+        self.background + self.world.lights.escaped(direction)
+    }
+}
+
+/// Light reaching `rec` straight from one of the lights, picked at random
+/// and weighted against finding the same light by scattering. None if the
+/// material can't be evaluated for light sampling.
+fn sample_lights(
+    ray: &Ray,
+    rec: &HitRecord,
+    scene: &Scene,
+    spectral: impl Fn(Color) -> Color,
+    rng: &mut dyn RngCore,
+) -> Option<Color> {
+    let lights = &scene.world.lights;
+    let black = Color::new(0.0, 0.0, 0.0);
+    // a light that can't be seen from here, like a spotlight pointed
+    // elsewhere, was still sampled
+    let sample = match lights.sample(rec.p, rng.gen(), rng.gen(), rng.gen()) {
+        Some(sample) => sample,
+        None => return Some(black),
+    };
+    let (f, bsdf_pdf) = rec.mat.eval(ray, rec, sample.direction, rng)?;
+    let f = spectral(f);
+
+    let shadow = Ray {
+        origin: rec.p,
+        direction: sample.direction,
+    };
+    let fogged = |distance: f32, rng: &mut dyn RngCore| match &scene.fog {
+        Some(fog) => fog.sample(&shadow, 0.01, distance, rng).is_some(),
+        None => false,
+    };
+
+    // Scattered rays never find delta lights, so there's nothing to weigh
+    // them against. They only need a clear view.
+    if let Some((distance, emitted)) = sample.delta {
+        let blocked = scene.world.hit(shadow, 0.01, distance, rng).is_some()
+            || (distance.is_finite() && fogged(distance, rng));
+        if blocked {
+            return Some(black);
+        }
+
+        let emitted = spectral(emitted);
+        let weight = 1.0 / sample.pdf;
+        return Some(Color::new(
+            f.x * emitted.x * weight,
+            f.y * emitted.y * weight,
+            f.z * emitted.z * weight,
+        ));
+    }
+
+    // Whatever glowing thing the shadow ray finds first counts, with the
+    // pdf of all the lights together. This way lights hidden behind other
+    // lights, and anything else that glows, are weighted the same as when
+    // they're found by scattering.
+    let emitted = match scene.world.hit(shadow, 0.01, 99999999999.0, rng) {
+        Some(light_rec) if fogged(light_rec.t, rng) => black,
+        Some(light_rec) => light_rec.mat.emitted(&light_rec),
+        None => scene.escaped(sample.direction),
+    };
+
+    let light_pdf = lights.pdf(rec.p, sample.direction);
+    if light_pdf == 0.0 {
+        return Some(black);
+    }
+    let weight = light::power_heuristic(light_pdf, bsdf_pdf) / light_pdf;
+    let emitted = spectral(emitted);
+    Some(Color::new(
+        f.x * emitted.x * weight,
+        f.y * emitted.y * weight,
+        f.z * emitted.z * weight,
+    ))
+}
+
+/// Determine the color of a pixel for a given ray. `media` are the
+/// dielectrics the ray is traveling inside of. In spectral mode
+/// `wavelengths` are the wavelengths carried by the path, and the result
+/// holds the radiance at each of them instead of RGB. `bsdf_pdf` is the pdf
+/// the ray was scattered with when the lights were also sampled at its
+/// origin, so that light found by the ray can be weighted against them.
+fn color_pixel(
+    ray: &Ray,
+    scene: &Scene,
+    media: &MediumStack,
+    wavelengths: Option<&SampledWavelengths>,
+    bsdf_pdf: Option<f32>,
+    depth: i32,
+    rng: &mut dyn RngCore,
+) -> Vec3 {
     if depth <= 0 {
         return Vec3::new(0.0, 0.0, 0.0);
     }
 
-    if let Some(rec) = world.hit(*ray, 0.01, 99999999999.0) {
-        if let Some((attenuation, scattered)) = rec.mat.scatter(ray, &rec) {
+    // colors are all RGB, so look them up at the path's wavelengths in
+    // spectral mode
+    let spectral = |color: Color| match wavelengths {
+        Some(wavelengths) => wavelengths.rgb_values(color),
+        None => color,
+    };
+
+    if let Some(mut rec) = scene.world.hit(*ray, 0.01, 99999999999.0, rng) {
+        // the fog may scatter the ray before it gets to the surface, as long
+        // as we're not inside something
+        if let Some(fog_rec) = scene
+            .fog
+            .as_ref()
+            .filter(|_| media.is_empty())
+            .and_then(|fog| fog.sample(ray, 0.01, rec.t, rng))
+        {
+            rec = fog_rec;
+        }
+
+        // colored glass absorbs light along the way, and translucent
+        // materials scatter it around inside
+        let ray_length = ray.direction.length();
+        let (scatter_distance, transmittance) =
+            media.sample_distance(rec.t * ray_length, wavelengths, rng);
+        if let Some(distance) = scatter_distance {
+            let scattered = Ray {
+                origin: ray.at(distance / ray_length),
+                direction: Vec3::random_unit_vector(rng),
+            };
+            let res = color_pixel(&scattered, scene, media, wavelengths, None, depth - 1, rng);
+            return Vec3 {
+                x: res.x * transmittance.x,
+                y: res.y * transmittance.y,
+                z: res.z * transmittance.z,
+            };
+        }
+
+        let mut media = media.clone();
+        let mut emitted = spectral(rec.mat.emitted(&rec));
+        if let Some(bsdf_pdf) = bsdf_pdf {
+            let light_pdf = scene.world.lights.pdf(ray.origin, ray.direction);
+            emitted = emitted * light::power_heuristic(bsdf_pdf, light_pdf);
+        }
+
+        let mut path_weight = Color::new(1.0, 1.0, 1.0);
+        let mut next_wavelengths = wavelengths.copied();
+        let mut direct = Color::new(0.0, 0.0, 0.0);
+        let mut next_bsdf_pdf = None;
+        let scattered = match rec.mat.interior() {
+            Some(interior) => {
+                match media.interface(&interior, rec.front_face, wavelengths) {
+                    Some(eta) => {
+                        let scattered = rec.mat.scatter_interface(ray, &rec, eta, rng);
+                        // the normal faces against the ray, so refracted rays
+                        // go the other way
+                        if let Some((_, scattered)) = &scattered {
+                            if scattered.direction * rec.normal < 0.0 {
+                                media.cross(interior, rec.front_face);
+                            }
+                        }
+                        // dispersion sends each wavelength its own way, only
+                        // the hero wavelength can follow this path
+                        if let (Some(carried), Some(_)) = (wavelengths, interior.dispersion) {
+                            let (carried, weight) = carried.terminate_secondary();
+                            next_wavelengths = Some(carried);
+                            path_weight = weight;
+                        }
+                        scattered
+                    }
+                    None => {
+                        // a surface hidden inside a higher priority medium,
+                        // carry straight on through it
+                        media.cross(interior, rec.front_face);
+                        Some((
+                            Color::new(1.0, 1.0, 1.0),
+                            Ray {
+                                origin: rec.p,
+                                direction: ray.direction,
+                            },
+                        ))
+                    }
+                }
+            }
+            None => {
+                let scattered = rec.mat.scatter(ray, &rec, rng);
+                // shadow rays don't know about absorption inside glass, so
+                // lights are only sampled out in the open
+                if media.is_empty() && !scene.world.lights.is_empty() {
+                    if let Some(light) = sample_lights(ray, &rec, scene, spectral, rng) {
+                        direct = light;
+                        next_bsdf_pdf = scattered.as_ref().and_then(|(_, scattered)| {
+                            rec.mat
+                                .eval(ray, &rec, scattered.direction, rng)
+                                .map(|(_, pdf)| pdf)
+                        });
+                    }
+                }
+                scattered
+            }
+        };
+
+        if let Some((attenuation, scattered)) = scattered {
+            let attenuation = spectral(attenuation);
             // Don't overload the * operator to do dot product...
-            let res = color_pixel(&scattered, world, depth - 1);
+            let res = color_pixel(
+                &scattered,
+                scene,
+                &media,
+                next_wavelengths.as_ref(),
+                next_bsdf_pdf,
+                depth - 1,
+                rng,
+            );
             let vec = Vec3 {
-                x: res.x * attenuation.x,
-                y: res.y * attenuation.y,
-                z: res.z * attenuation.z,
+                x: (emitted.x + direct.x + res.x * attenuation.x * path_weight.x) * transmittance.x,
+                y: (emitted.y + direct.y + res.y * attenuation.y * path_weight.y) * transmittance.y,
+                z: (emitted.z + direct.z + res.z * attenuation.z * path_weight.z) * transmittance.z,
             };
             return vec;
         }
 
-        return Color::new(0.0, 0.0, 0.0);
+        return Vec3 {
+            x: (emitted.x + direct.x) * transmittance.x,
+            y: (emitted.y + direct.y) * transmittance.y,
+            z: (emitted.z + direct.z) * transmittance.z,
+        };
     }
 
-    // white-ish background
-    Vec3::new(0.8, 0.8, 0.8)
+    let mut escaped = scene.escaped(ray.direction);
+    if let Some(bsdf_pdf) = bsdf_pdf {
+        let light_pdf = scene.world.lights.pdf(ray.origin, ray.direction);
+        escaped = escaped * light::power_heuristic(bsdf_pdf, light_pdf);
+    }
+    spectral(escaped)
 }
 
-fn generate_large_scene(rng: &mut ThreadRng, world: &mut HittableList) {
+fn generate_large_scene(rng: &mut StdRng, world: &mut HittableList) {
     let material_ground = Lambertian {
         albedo: Color::new(0.5, 0.5, 0.5),
     };
@@ -102,26 +418,22 @@ fn generate_large_scene(rng: &mut ThreadRng, world: &mut HittableList) {
 
             if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
                 if choose_mat < 0.8 {
-                    let albedo = Color::random(0.0, 1.0) - Color::random(0.0, 1.0);
+                    let albedo = Color::random(rng, 0.0, 1.0) - Color::random(rng, 0.0, 1.0);
                     let sphere_material = Lambertian { albedo };
                     world.add(Sphere::new(center, 0.2, Rc::new(sphere_material)));
                 } else if choose_mat < 0.95 {
-                    let albedo = Color::random(0.5, 1.0);
-                    let fuzz = rng.gen_range(0.0..0.5);
-                    let sphere_material = Metal { albedo, fuzz };
+                    let albedo = Color::random(rng, 0.5, 1.0);
+                    let roughness = rng.gen_range(0.0..0.5);
+                    let sphere_material = Metal::new(albedo, roughness);
                     world.add(Sphere::new(center, 0.2, Rc::new(sphere_material)));
                 } else {
-                    let sphere_material = Dialetric {
-                        index_of_refraction: 1.5,
-                    };
+                    let sphere_material = Dialetric::new(1.5);
                     world.add(Sphere::new(center, 0.2, Rc::new(sphere_material)));
                 }
             }
         }
 
-        let material1 = Dialetric {
-            index_of_refraction: 1.5,
-        };
+        let material1 = Dialetric::new(1.5);
         world.add(Sphere::new(
             Point3::new(0.0, 1.0, 0.0),
             1.0,
@@ -137,10 +449,7 @@ fn generate_large_scene(rng: &mut ThreadRng, world: &mut HittableList) {
             Rc::new(material2),
         ));
 
-        let material3 = Metal {
-            albedo: Color::new(0.7, 0.6, 0.5),
-            fuzz: 0.0,
-        };
+        let material3 = Metal::gold(0.0);
         world.add(Sphere::new(
             Point3::new(4.0, 1.0, 0.0),
             1.0,
@@ -149,34 +458,9 @@ fn generate_large_scene(rng: &mut ThreadRng, world: &mut HittableList) {
     }
 }
 
-fn main() -> Result<(), Error> {
-    env_logger::init();
-
-    let samples_per_pixel = 50;
-
-    let mut rng = rand::thread_rng();
-    const ASPECT_RATIO: f32 = 3.0 / 2.0;
-    const WIDTH: u32 = 900;
-    const HEIGHT: u32 = (WIDTH as f32 / ASPECT_RATIO) as u32;
-
-    let lookfrom = Point3::new(13.0, 2.0, 3.0);
-    let lookat = Point3::new(0.0, 0.0, 0.0);
-    let vup = Vec3::new(0.0, 1.0, 0.0);
-
-    let camera = Camera::new(
-        lookfrom,
-        lookat,
-        vup,
-        20.0,
-        WIDTH as f32 / HEIGHT as f32,
-        0.1,
-        10.0,
-    );
-
-    let mut world = HittableList::new();
-
-    // generate_large_scene(&mut rng, &mut world);
-
+/// The default scene, three big spheres plus some smoke. Returns the fog
+/// that goes with it.
+fn generate_default_scene(world: &mut HittableList) -> Fog {
     let material_ground = Lambertian {
         albedo: Color::new(0.5, 0.5, 0.5),
     };
@@ -186,10 +470,32 @@ fn main() -> Result<(), Error> {
         Rc::new(material_ground),
     ));
 
+    // a puff of smoke in front of the spheres
+    world.add(ConstantMedium::new(
+        Sphere::new(
+            Point3::new(2.0, 0.6, 2.0),
+            0.6,
+            Rc::new(Lambertian {
+                albedo: Color::new(0.0, 0.0, 0.0),
+            }),
+        ),
+        2.0,
+        Rc::new(Isotropic {
+            albedo: Color::new(0.9, 0.9, 0.9),
+        }),
+    ));
+
+    // a light haze over the whole scene
+    let fog = Fog::new(
+        0.005,
+        Rc::new(HenyeyGreenstein {
+            albedo: Color::new(0.9, 0.9, 0.9),
+            g: 0.6,
+        }),
+    );
+
     for _a in -1..1 {
-        let material1 = Dialetric {
-            index_of_refraction: 1.5,
-        };
+        let material1 = Dialetric::new(1.5);
         world.add(Sphere::new(
             Point3::new(0.0, 1.0, 0.0),
             1.0,
@@ -205,10 +511,7 @@ fn main() -> Result<(), Error> {
             Rc::new(material2),
         ));
 
-        let material3 = Metal {
-            albedo: Color::new(0.7, 0.6, 0.5),
-            fuzz: 0.0,
-        };
+        let material3 = Metal::new(Color::new(0.7, 0.6, 0.5), 0.0);
         world.add(Sphere::new(
             Point3::new(4.0, 1.0, 0.0),
             1.0,
@@ -216,46 +519,1495 @@ fn main() -> Result<(), Error> {
         ));
     }
 
-    let path = Path::new("image.png");
-    let file = File::create(path).unwrap();
-    let w = &mut BufWriter::new(file);
+    fog
+}
 
-    let mut encoder = png::Encoder::new(w, WIDTH, HEIGHT);
-    encoder.set_color(png::ColorType::Rgba);
-    encoder.set_depth(png::BitDepth::Eight);
-    encoder.set_trns(vec![0xFFu8, 0xFFu8, 0xFFu8, 0xFFu8]);
+/// Dice and lenses carved out of boxes and spheres.
+fn generate_csg_scene(world: &mut HittableList) {
+    let material_ground = Lambertian {
+        albedo: Color::new(0.5, 0.5, 0.5),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(material_ground),
+    ));
 
-    let mut image: Vec<u8> = Vec::new();
+    // a die is the intersection of a cube and a sphere, with the pips
+    // subtracted
+    let ivory = Rc::new(Lambertian {
+        albedo: Color::new(0.9, 0.85, 0.7),
+    });
+    let pip_material = Rc::new(Lambertian {
+        albedo: Color::new(0.1, 0.1, 0.1),
+    });
+    let die = Intersection::new(
+        Cuboid::new(
+            Point3::new(-1.0, 0.0, -1.0),
+            Point3::new(1.0, 2.0, 1.0),
+            ivory.clone(),
+        ),
+        Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.35, ivory),
+    );
+    // five on the face toward the camera
+    let mut pips = Union::new(
+        Sphere::new(Point3::new(1.05, 1.0, 0.0), 0.18, pip_material.clone()),
+        Sphere::new(Point3::new(1.05, 1.5, 0.5), 0.18, pip_material.clone()),
+    );
+    for (y, z) in [(1.5, -0.5), (0.5, 0.5), (0.5, -0.5)] {
+        pips = Union::new(
+            pips,
+            Sphere::new(Point3::new(1.05, y, z), 0.18, pip_material.clone()),
+        );
+    }
+    world.add(Difference::new(die, pips));
 
-    for j in (0..HEIGHT).rev() {
-        for i in 0..WIDTH {
-            let mut pixel_color = Vec3::new(0.0, 0.0, 0.0);
-            for _s in 0..samples_per_pixel {
-                // u and v are the how far, as a percentage, x and y are from
-                // the vertical and horizontal of our viewport. This is used
-                // to map our pixel coords to the "camera" coords.
-                let u = (i as f32 + rng.gen::<f32>()) / (WIDTH - 1) as f32;
-                let v = (j as f32 + rng.gen::<f32>()) / (HEIGHT - 1) as f32;
-
-                // origin is the camera (0, 0 ,0) and direction is the point in
-                // the viewport whose color value we are calculating.
-                let ray = camera.get_ray(u, v);
-                pixel_color += color_pixel(&ray, &world, 50);
-            }
+    // a biconvex glass lens
+    world.add(Intersection::new(
+        Sphere::new(
+            Point3::new(3.0, 1.0, 2.2),
+            1.2,
+            Rc::new(Dialetric::new(1.5)),
+        ),
+        Sphere::new(
+            Point3::new(5.0, 1.0, 2.2),
+            1.2,
+            Rc::new(Dialetric::new(1.5)),
+        ),
+    ));
 
-            let color = calculate_color(pixel_color, samples_per_pixel);
-            let ir = (color.x) as u8;
-            let ig = (color.y) as u8;
-            let ib = (color.z) as u8;
-            image.push(ir);
-            image.push(ig);
-            image.push(ib);
-            image.push(255);
-        }
+    // a metal bowl, a hollowed out half sphere
+    let bowl_material = Rc::new(Metal::copper(0.3));
+    world.add(Difference::new(
+        Sphere::new(Point3::new(1.0, 1.0, -3.0), 1.0, bowl_material.clone()),
+        Union::new(
+            Sphere::new(Point3::new(1.0, 1.0, -3.0), 0.9, bowl_material.clone()),
+            Cuboid::new(
+                Point3::new(-0.5, 1.0, -4.5),
+                Point3::new(2.5, 2.5, -1.5),
+                bowl_material,
+            ),
+        ),
+    ));
+}
+
+/// Procedural shapes rendered from distance fields.
+fn generate_sdf_scene(world: &mut HittableList) {
+    let material_ground = Lambertian {
+        albedo: Color::new(0.5, 0.5, 0.5),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(material_ground),
+    ));
+
+    // a Mandelbulb in the middle
+    world.add(SdfObject::new(
+        Translate {
+            sdf: Box::new(Mandelbulb {
+                power: 8.0,
+                iterations: 12,
+            }),
+            offset: Vec3::new(0.0, 1.2, 0.0),
+        },
+        Rc::new(Lambertian {
+            albedo: Color::new(0.8, 0.3, 0.2),
+        }),
+    ));
+
+    // a torus melting into a rounded box
+    world.add(SdfObject::new(
+        Translate {
+            sdf: Box::new(SmoothUnion {
+                a: Box::new(RoundBox {
+                    half_extents: Vec3::new(0.5, 0.5, 0.5),
+                    radius: 0.1,
+                }),
+                b: Box::new(Translate {
+                    sdf: Box::new(Torus {
+                        major_radius: 0.8,
+                        minor_radius: 0.15,
+                    }),
+                    offset: Vec3::new(0.0, 0.6, 0.0),
+                }),
+                k: 0.3,
+            }),
+            offset: Vec3::new(1.0, 0.6, 3.0),
+        },
+        Rc::new(Metal::aluminum(0.2)),
+    ));
+
+    // a twisted column
+    world.add(SdfObject::new(
+        Translate {
+            sdf: Box::new(Twist {
+                sdf: Box::new(RoundBox {
+                    half_extents: Vec3::new(0.3, 1.0, 0.3),
+                    radius: 0.05,
+                }),
+                rate: 1.5,
+            }),
+            offset: Vec3::new(1.0, 1.05, -2.5),
+        },
+        Rc::new(Lambertian {
+            albedo: Color::new(0.2, 0.4, 0.8),
+        }),
+    ));
+
+    // a row of frosted glass marbles
+    world.add(SdfObject::new(
+        Translate {
+            sdf: Box::new(Repeat {
+                sdf: Box::new(SdfSphere {
+                    center: Point3::new(0.0, 0.0, 0.0),
+                    radius: 0.2,
+                }),
+                spacing: Vec3::new(1.0, 1.0, 0.6),
+                count: [0, 0, 5],
+            }),
+            offset: Vec3::new(3.5, 0.2, 0.0),
+        },
+        Rc::new(RoughDielectric::new(1.5, 0.3)),
+    ));
+}
+
+/// Colored glass, and a glass bowl of wine.
+fn generate_glass_scene(world: &mut HittableList) {
+    let material_ground = Lambertian {
+        albedo: Color::new(0.5, 0.5, 0.5),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(material_ground),
+    ));
+
+    // The wine is modelled a little larger than the inside of the bowl so
+    // there's no gap between them. The bowl has the higher priority so it
+    // wins where they overlap.
+    let mut glass = Dialetric::new(1.5);
+    glass.priority = 2;
+    let glass: Rc<dyn Material> = Rc::new(glass);
+    let mut wine = Dialetric::colored(1.34, Color::new(0.6, 0.05, 0.1), 0.5);
+    wine.priority = 1;
+    let wine: Rc<dyn Material> = Rc::new(wine);
+
+    let center = Point3::new(0.0, 1.0, 0.0);
+    world.add(Difference::new(
+        Sphere::new(center, 1.0, glass.clone()),
+        Union::new(
+            Sphere::new(center, 0.9, glass.clone()),
+            Cuboid::new(
+                Point3::new(-1.5, 1.5, -1.5),
+                Point3::new(1.5, 2.5, 1.5),
+                glass,
+            ),
+        ),
+    ));
+    world.add(Intersection::new(
+        Sphere::new(center, 0.92, wine.clone()),
+        Cuboid::new(
+            Point3::new(-1.5, -0.5, -1.5),
+            Point3::new(1.5, 1.2, 1.5),
+            wine,
+        ),
+    ));
+
+    // bottle green glass and frosted amber glass
+    world.add(Sphere::new(
+        Point3::new(-4.0, 1.0, 0.0),
+        1.0,
+        Rc::new(Dialetric::colored(1.5, Color::new(0.2, 0.7, 0.3), 1.0)),
+    ));
+    world.add(Sphere::new(
+        Point3::new(4.0, 1.0, 0.0),
+        1.0,
+        Rc::new(RoughDielectric::colored(
+            1.5,
+            0.2,
+            Color::new(0.9, 0.5, 0.1),
+            1.0,
+        )),
+    ));
+}
+
+/// Dispersive glass, best rendered with --spectral.
+fn generate_prism_scene(world: &mut HittableList) {
+    let material_ground = Lambertian {
+        albedo: Color::new(0.5, 0.5, 0.5),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(material_ground),
+    ));
+
+    // a dense flint glass prism
+    world.add(SdfObject::new(
+        Translate {
+            sdf: Box::new(TriPrism {
+                size: 1.0,
+                half_length: 1.0,
+            }),
+            offset: Vec3::new(0.0, 0.5, 0.0),
+        },
+        Rc::new(Dialetric::dispersive(Dispersion::sf11())),
+    ));
+
+    // crown glass, and something far more dispersive than any real glass
+    world.add(Sphere::new(
+        Point3::new(0.0, 0.8, -2.4),
+        0.8,
+        Rc::new(Dialetric::dispersive(Dispersion::bk7())),
+    ));
+    world.add(Sphere::new(
+        Point3::new(0.0, 0.8, 2.4),
+        0.8,
+        Rc::new(Dialetric::dispersive(Dispersion::Cauchy {
+            a: 1.45,
+            b: 0.05,
+        })),
+    ));
+}
+
+/// A range of looks from the one principled material, with textured
+/// parameters on the big sphere at the back.
+fn generate_principled_scene(world: &mut HittableList) {
+    let material_ground = Lambertian {
+        albedo: Color::new(0.5, 0.5, 0.5),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(material_ground),
+    ));
+
+    let materials = [
+        // plastic
+        Principled::new(Color::new(0.8, 0.1, 0.1)).roughness(0.3),
+        // brushed gold
+        Principled::new(Color::new(1.0, 0.78, 0.34))
+            .metallic(1.0)
+            .roughness(0.35)
+            .anisotropic(0.8),
+        // car paint
+        Principled::new(Color::new(0.05, 0.1, 0.5))
+            .roughness(0.6)
+            .clearcoat(1.0, 0.05),
+        // green glass
+        Principled::new(Color::new(0.7, 0.95, 0.7))
+            .roughness(0.0)
+            .transmission(1.0, 1.5),
+        // velvet
+        Principled::new(Color::new(0.3, 0.05, 0.3))
+            .roughness(1.0)
+            .specular(0.0)
+            .sheen(1.0, 0.5),
+    ];
+    for (i, mat) in materials.into_iter().enumerate() {
+        world.add(Sphere::new(
+            Point3::new(1.5, 0.5, -2.4 + 1.2 * i as f32),
+            0.5,
+            Rc::new(mat),
+        ));
     }
 
-    let mut writer = encoder.write_header().unwrap();
-    writer.write_image_data(&image).unwrap();
+    // gold squares on white plastic
+    let textured = Principled::new(Checker::new(
+        16,
+        8,
+        Color::new(1.0, 0.78, 0.34),
+        Color::new(0.8, 0.8, 0.8),
+    ))
+    .metallic(Checker::new(16, 8, 1.0, 0.0))
+    .roughness(0.2);
+    world.add(Sphere::new(
+        Point3::new(-4.0, 1.5, 0.0),
+        1.5,
+        Rc::new(textured),
+    ));
+}
 
-    Ok(())
+/// Materials built out of other materials.
+fn generate_layered_scene(world: &mut HittableList) {
+    let material_ground = Lambertian {
+        albedo: Color::new(0.5, 0.5, 0.5),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(material_ground),
+    ));
+
+    // glossy paint
+    let paint: Rc<dyn Material> = Rc::new(CoatedMaterial::new(
+        Rc::new(Lambertian {
+            albedo: Color::new(0.7, 0.05, 0.05),
+        }),
+        1.5,
+        0.0,
+    ));
+    world.add(Sphere::new(Point3::new(0.0, 1.0, -2.2), 1.0, paint.clone()));
+
+    // lacquered brushed copper
+    let lacquered: Rc<dyn Material> =
+        Rc::new(CoatedMaterial::new(Rc::new(Metal::copper(0.5)), 1.5, 0.0));
+    world.add(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, lacquered));
+
+    // the paint worn through to the metal underneath in patches
+    let worn = MixMaterial::new(
+        paint,
+        Rc::new(Metal::aluminum(0.4)),
+        Checker::new(12, 6, 0.0, 1.0),
+    );
+    world.add(Sphere::new(Point3::new(0.0, 1.0, 2.2), 1.0, Rc::new(worn)));
+}
+
+/// Surface detail from bump and normal maps.
+fn generate_bump_scene(world: &mut HittableList, normal_map: Option<ImageTexture>) {
+    let material_ground = Lambertian {
+        albedo: Color::new(0.5, 0.5, 0.5),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(material_ground),
+    ));
+
+    // tiles with sunken grout lines
+    let tiles = NormalMapped::bump_map(
+        Rc::new(Principled::new(Color::new(0.8, 0.8, 0.75)).roughness(0.3)),
+        Checker::new(6, 6, 0.0, 0.01),
+        1.0,
+    );
+    world.add(Cuboid::new(
+        Point3::new(-1.0, 0.0, -3.2),
+        Point3::new(1.0, 2.0, -1.2),
+        Rc::new(tiles),
+    ));
+
+    // hammered metal, or the normal map if there is one
+    let metal: Rc<dyn Material> = Rc::new(Metal::aluminum(0.25));
+    let middle = match normal_map {
+        Some(map) => NormalMapped::normal_map(metal, map),
+        None => NormalMapped::bump_map(metal, Checker::new(32, 16, 0.0, 0.01), 1.0),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, 1.0, 0.0),
+        1.0,
+        Rc::new(middle),
+    ));
+
+    // quilted
+    let quilted = NormalMapped::bump_map(
+        Rc::new(Lambertian {
+            albedo: Color::new(0.8, 0.3, 0.05),
+        }),
+        Checker::new(16, 8, 0.0, 0.02),
+        1.0,
+    );
+    world.add(Sphere::new(
+        Point3::new(0.0, 1.0, 2.2),
+        1.0,
+        Rc::new(quilted),
+    ));
+}
+
+/// Geometry with holes cut in it by opacity textures.
+fn generate_cutout_scene(world: &mut HittableList) {
+    let material_ground = Lambertian {
+        albedo: Color::new(0.5, 0.5, 0.5),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(material_ground),
+    ));
+
+    // a lattice fence in front of everything
+    let wood: Rc<dyn Material> = Rc::new(Lambertian {
+        albedo: Color::new(0.45, 0.3, 0.15),
+    });
+    world.add(Cutout::new(
+        Cuboid::new(
+            Point3::new(2.0, 0.0, -2.5),
+            Point3::new(2.05, 1.2, 2.5),
+            wood,
+        ),
+        Checker::new(20, 5, 1.0, 0.0),
+    ));
+
+    // a hollow cage, lit inside through the gaps
+    let cage: Rc<dyn Material> = Rc::new(Metal::gold(0.3));
+    world.add(Cutout::new(
+        Sphere::new(Point3::new(0.0, 1.0, -1.2), 1.0, cage),
+        Checker::new(16, 8, 1.0, 0.0),
+    ));
+
+    // only partly there
+    let ghost: Rc<dyn Material> = Rc::new(Lambertian {
+        albedo: Color::new(0.2, 0.4, 0.8),
+    });
+    world.add(Cutout::new(
+        Sphere::new(Point3::new(0.0, 1.0, 1.2), 1.0, ghost),
+        0.4,
+    ));
+}
+
+/// Translucent materials, with light scattering around under the surface.
+fn generate_subsurface_scene(world: &mut HittableList) {
+    let material_ground = Lambertian {
+        albedo: Color::new(0.5, 0.5, 0.5),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(material_ground),
+    ));
+
+    let marble = Subsurface::new(
+        Color::new(0.9, 0.9, 0.88),
+        Color::new(0.3, 0.3, 0.3),
+        1.5,
+        0.0,
+    );
+    world.add(Sphere::new(
+        Point3::new(0.0, 1.0, -2.2),
+        1.0,
+        Rc::new(marble),
+    ));
+
+    // red travels furthest through skin
+    let skin = Subsurface::new(
+        Color::new(0.85, 0.6, 0.5),
+        Color::new(0.6, 0.25, 0.15),
+        1.4,
+        0.3,
+    );
+    world.add(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, Rc::new(skin)));
+
+    let jade = Subsurface::new(
+        Color::new(0.3, 0.8, 0.45),
+        Color::new(0.4, 1.0, 0.6),
+        1.6,
+        0.1,
+    );
+    world.add(Sphere::new(Point3::new(0.0, 1.0, 2.2), 1.0, Rc::new(jade)));
+}
+
+/// Thin film interference and cloth.
+fn generate_iridescent_scene(world: &mut HittableList) {
+    let material_ground = Lambertian {
+        albedo: Color::new(0.5, 0.5, 0.5),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(material_ground),
+    ));
+
+    // a soap bubble is a film with air on both sides
+    let bubble = IridescentDielectric::new(
+        1.0,
+        ThinFilm {
+            thickness: 450.0,
+            index_of_refraction: 1.33,
+        },
+    );
+    world.add(Sphere::new(
+        Point3::new(0.0, 1.2, -2.2),
+        1.0,
+        Rc::new(bubble),
+    ));
+
+    // heat tinted steel
+    let steel = IridescentMetal::new(
+        Color::new(2.9, 2.9, 2.9),
+        Color::new(3.1, 3.1, 3.1),
+        0.2,
+        ThinFilm {
+            thickness: 280.0,
+            index_of_refraction: 2.2,
+        },
+    );
+    world.add(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, Rc::new(steel)));
+
+    let velvet = Velvet::new(Color::new(0.25, 0.02, 0.05), Color::new(1.0, 0.5, 0.6), 0.5);
+    world.add(Sphere::new(
+        Point3::new(0.0, 1.0, 2.2),
+        1.0,
+        Rc::new(velvet),
+    ));
+}
+
+/// A dark room lit by a glowing ball and a panel overhead, which need the
+/// lights sampled directly to render without lots of noise.
+fn generate_lights_scene(scene: &mut Scene) {
+    scene.background = Color::new(0.01, 0.01, 0.02);
+    let world = &mut scene.world;
+
+    let material_ground = Lambertian {
+        albedo: Color::new(0.5, 0.5, 0.5),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(material_ground),
+    ));
+
+    let white = Lambertian {
+        albedo: Color::new(0.8, 0.8, 0.8),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, 1.0, -2.2),
+        1.0,
+        Rc::new(white),
+    ));
+
+    let metal = Metal::new(Color::new(0.9, 0.9, 0.9), 0.3);
+    world.add(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, Rc::new(metal)));
+
+    let velvet = Velvet::new(Color::new(0.1, 0.1, 0.4), Color::new(0.6, 0.6, 1.0), 0.5);
+    world.add(Sphere::new(
+        Point3::new(0.0, 1.0, 2.2),
+        1.0,
+        Rc::new(velvet),
+    ));
+
+    // a warm glowing ball on the ground
+    world.add(Sphere::new(
+        Point3::new(2.0, 0.3, -1.1),
+        0.3,
+        Rc::new(DiffuseLight {
+            emit: Color::new(12.0, 8.0, 4.0),
+        }),
+    ));
+
+    // and a panel facing down from above
+    world.add(Quad::new(
+        Point3::new(-2.0, 4.0, -3.0),
+        Vec3::new(3.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 6.0),
+        Rc::new(DiffuseLight {
+            emit: Color::new(3.0, 3.0, 3.0),
+        }),
+    ));
+}
+
+/// Lit only by lights that aren't objects: a low sun giving soft shadows,
+/// a spotlight on the middle sphere and a point light off to the side.
+fn generate_punctual_scene(scene: &mut Scene) {
+    scene.background = Color::new(0.05, 0.07, 0.1);
+    let world = &mut scene.world;
+
+    let material_ground = Lambertian {
+        albedo: Color::new(0.5, 0.5, 0.5),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(material_ground),
+    ));
+
+    let white = Lambertian {
+        albedo: Color::new(0.8, 0.8, 0.8),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, 1.0, -2.2),
+        1.0,
+        Rc::new(white),
+    ));
+
+    world.add(Sphere::new(
+        Point3::new(0.0, 1.0, 0.0),
+        1.0,
+        Rc::new(Metal::gold(0.3)),
+    ));
+
+    let red = Lambertian {
+        albedo: Color::new(0.7, 0.1, 0.1),
+    };
+    world.add(Sphere::new(Point3::new(0.0, 1.0, 2.2), 1.0, Rc::new(red)));
+
+    world.add_light(DirectionalLight::new(
+        Vec3::new(-1.0, 0.6, -1.0),
+        Color::new(1.6, 1.4, 1.1),
+        5.0,
+    ));
+    world.add_light(SpotLight::new(
+        Point3::new(3.0, 6.0, 0.0),
+        Point3::new(0.0, 1.0, 0.0),
+        Color::new(40.0, 40.0, 36.0),
+        8.0,
+        15.0,
+    ));
+    world.add_light(PointLight {
+        position: Point3::new(2.5, 0.6, 3.6),
+        intensity: Color::new(1.0, 1.5, 3.0),
+    });
+}
+
+/// A dark field scattered with a couple of hundred small glowing balls of very
+/// different brightness. Picking lights uniformly wastes most samples on
+/// dim or distant ones, which the light BVH avoids.
+fn generate_many_lights_scene(rng: &mut StdRng, scene: &mut Scene) {
+    scene.background = Color::new(0.0, 0.0, 0.0);
+    let world = &mut scene.world;
+
+    let material_ground = Lambertian {
+        albedo: Color::new(0.5, 0.5, 0.5),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(material_ground),
+    ));
+
+    let white = Lambertian {
+        albedo: Color::new(0.8, 0.8, 0.8),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, 1.0, -2.2),
+        1.0,
+        Rc::new(white),
+    ));
+
+    let metal = Metal::new(Color::new(0.9, 0.9, 0.9), 0.2);
+    world.add(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, Rc::new(metal)));
+
+    let blue = Lambertian {
+        albedo: Color::new(0.2, 0.3, 0.7),
+    };
+    world.add(Sphere::new(Point3::new(0.0, 1.0, 2.2), 1.0, Rc::new(blue)));
+
+    for a in -7..7 {
+        for b in -7..7 {
+            let center = Point3::new(
+                a as f32 + 0.9 * rng.gen::<f32>(),
+                0.1,
+                b as f32 + 0.9 * rng.gen::<f32>(),
+            );
+            if (center - Point3::new(0.0, 0.1, 0.0)).length() < 3.5 {
+                continue;
+            }
+
+            // mostly dim, with the odd bright one
+            let brightness = 80.0 * f32::powi(rng.gen::<f32>(), 6) + 0.5;
+            let tint = Color::random(rng, 0.3, 1.0);
+            world.add(Sphere::new(
+                center,
+                0.1,
+                Rc::new(DiffuseLight {
+                    emit: tint * brightness,
+                }),
+            ));
+        }
+    }
+}
+
+/// Lit by a made up sky with a small, very bright sun low in it. Without
+/// picking directions toward the sun the diffuse spheres are covered in
+/// fireflies.
+fn generate_environment_scene(scene: &mut Scene) {
+    scene.background = Color::new(0.0, 0.0, 0.0);
+    let world = &mut scene.world;
+
+    let material_ground = Lambertian {
+        albedo: Color::new(0.5, 0.5, 0.5),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(material_ground),
+    ));
+
+    let white = Lambertian {
+        albedo: Color::new(0.8, 0.8, 0.8),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, 1.0, -2.2),
+        1.0,
+        Rc::new(white),
+    ));
+
+    let metal = Metal::new(Color::new(0.9, 0.9, 0.9), 0.2);
+    world.add(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, Rc::new(metal)));
+
+    let green = Lambertian {
+        albedo: Color::new(0.2, 0.6, 0.3),
+    };
+    world.add(Sphere::new(Point3::new(0.0, 1.0, 2.2), 1.0, Rc::new(green)));
+
+    // blue overhead fading to white at the horizon, dark brown below
+    let (width, height) = (512, 256);
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let t = (y as f32 + 0.5) / height as f32;
+        for _x in 0..width {
+            pixels.push(if t < 0.5 {
+                let a = t * 2.0;
+                Color::new(0.2, 0.35, 0.8) * (1.0 - a) + Color::new(0.9, 0.9, 0.95) * a
+            } else {
+                Color::new(0.1, 0.08, 0.06)
+            });
+        }
+    }
+
+    // the sun, a few texels across and behind the camera's left shoulder
+    let (sun_x, sun_y) = (width * 5 / 8, height * 3 / 8);
+    for y in sun_y - 2..sun_y + 2 {
+        for x in sun_x - 2..sun_x + 2 {
+            pixels[y * width + x] = Color::new(1200.0, 1100.0, 1000.0);
+        }
+    }
+
+    world.add_light(EnvironmentMap::new(
+        ImageTexture::new(width, height, pixels),
+        1.0,
+    ));
+}
+
+/// A glass ball bouncing between a spinning box and a metal ball that
+/// tarnishes and polishes again, over two seconds. The camera for it comes
+/// from `generate_animated_camera`.
+fn generate_animated_scene(world: &mut HittableList, time: f32) {
+    let material_ground = Lambertian {
+        albedo: Color::new(0.5, 0.5, 0.5),
+    };
+    world.add(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Rc::new(material_ground),
+    ));
+
+    // hangs at the top of each bounce and is moving fastest when it lands
+    let mut height = Track::new(Interpolation::Bezier);
+    for bounce in 0..2 {
+        let start = bounce as f32;
+        height.add_with_handles(start, 2.6, 0.0, 0.0);
+        height.add_with_handles(start + 0.5, 1.0, 0.7, 0.7);
+    }
+    height.add_with_handles(2.0, 2.6, 0.0, 0.0);
+    world.add(Sphere::new(
+        Point3::new(0.0, height.at(time), 0.0),
+        1.0,
+        Rc::new(Dialetric::new(1.5)),
+    ));
+
+    let spin = Track::from_keys(
+        Interpolation::Linear,
+        &[
+            (0.0, Vec3::new(20.0, 0.0, 0.0)),
+            (2.0, Vec3::new(20.0, 180.0, 0.0)),
+        ],
+    );
+    let half = Vec3::new(0.7, 0.7, 0.7);
+    world.add(Transformed::new(
+        Cuboid::new(
+            -half,
+            half,
+            Rc::new(Lambertian {
+                albedo: Color::new(0.4, 0.2, 0.8),
+            }),
+        ),
+        Transform {
+            translation: Vec3::new(-4.0, 1.0, 0.0),
+            rotation: spin.at(time),
+            ..Transform::default()
+        },
+    ));
+
+    let roughness = Track::from_keys(
+        Interpolation::CatmullRom,
+        &[(0.0, 0.0), (1.0, 0.4), (2.0, 0.05)],
+    );
+    let albedo = Track::from_keys(
+        Interpolation::Linear,
+        &[
+            (0.0, Color::new(0.7, 0.6, 0.5)),
+            (2.0, Color::new(0.9, 0.9, 0.9)),
+        ],
+    );
+    world.add(Sphere::new(
+        Point3::new(4.0, 1.0, 0.0),
+        1.0,
+        Rc::new(Metal::new(albedo.at(time), roughness.at(time))),
+    ));
+}
+
+/// Swings around the front of the animated scene, zooming out a little.
+fn generate_animated_camera() -> CameraAnimation {
+    CameraAnimation {
+        lookfrom: Track::from_keys(
+            Interpolation::CatmullRom,
+            &[
+                (0.0, Point3::new(13.0, 2.0, 3.0)),
+                (1.0, Point3::new(11.0, 4.0, 8.0)),
+                (2.0, Point3::new(6.0, 3.0, 12.0)),
+            ],
+        ),
+        lookat: Track::constant(Point3::new(0.0, 1.0, 0.0)),
+        vfov: Track::from_keys(Interpolation::Linear, &[(0.0, 20.0), (2.0, 28.0)]),
+        focus_distance: None,
+    }
+}
+
+/// Command line options. Everything is optional, running with no arguments
+/// renders the default scene.
+#[derive(Default)]
+struct Options {
+    /// Which scene to render, "large", "csg", "sdf", "glass", "prism",
+    /// "principled", "layered", "bump", "cutout", "subsurface",
+    /// "iridescent", "lights", "punctual", "many-lights", "environment" or
+    /// "animated". The default scene is used if this isn't set.
+    scene: Option<String>,
+    /// Voxel grid file to add to the scene as a heterogeneous volume.
+    volume: Option<String>,
+    /// Tangent space normal map PNG for the sphere in the middle of the bump
+    /// scene.
+    normal_map: Option<String>,
+    /// Which camera to render with, "perspective", "orthographic",
+    /// "fisheye", "fisheye-equisolid", "equirectangular", "physical",
+    /// "stereo", "stereo-panorama" or "lens". Perspective if this isn't
+    /// set.
+    camera: Option<String>,
+    /// Lens prescription for the lens camera, a double Gauss 50mm if this
+    /// isn't set.
+    lens: Option<String>,
+    /// Diameter of the lens camera's aperture stop in millimeters.
+    lens_stop: Option<f32>,
+    /// Lens, sensor, shutter and ISO for the physical camera. The sensor
+    /// width is also used by the lens camera.
+    physical: PhysicalSettings,
+    /// Layout, eye distance and convergence for the stereo cameras.
+    stereo: StereoSettings,
+    /// Equirectangular .hdr or PNG image to light the scene with, in place
+    /// of the background.
+    environment: Option<String>,
+    /// Daylight from a sky and sun in place of the background, given as
+    /// "elevation,azimuth" or "elevation,azimuth,turbidity" with the angles
+    /// in degrees.
+    sky: Option<Sky>,
+    /// Trace wavelengths rather than RGB, so that glass can disperse light.
+    spectral: bool,
+    /// How lights are picked for sampling them directly, "uniform", "power"
+    /// or "bvh".
+    light_sampling: LightSampling,
+    /// First and last frame to render, each to its own numbered image. A
+    /// single image if this isn't set.
+    frames: Option<(u32, u32)>,
+    /// Frames per second, 24 if this isn't set.
+    fps: Option<f32>,
+    /// Carry on from the checkpoints of an earlier render with the same
+    /// arguments, rather than starting again.
+    resume: bool,
+    /// Seconds between saving checkpoints, 60 if this isn't set.
+    checkpoint_interval: Option<f32>,
+    /// Where the random numbers start from, for the scenes placed at random
+    /// and the render itself. The same seed gives the same image.
+    seed: u64,
+}
+
+fn parse_sky(value: &str) -> Result<Sky, Error> {
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("bad sky {}", value));
+    let numbers = value
+        .split(',')
+        .map(|n| n.trim().parse::<f32>().map_err(|_| invalid()))
+        .collect::<Result<Vec<f32>, Error>>()?;
+
+    match numbers[..] {
+        [elevation, azimuth] => Ok(Sky::new(elevation, azimuth, 3.0)),
+        [elevation, azimuth, turbidity] => Ok(Sky::new(elevation, azimuth, turbidity)),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_number(arg: &str, value: &str) -> Result<f32, Error> {
+    match value.parse::<f32>() {
+        Ok(n) if n > 0.0 => Ok(n),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("bad value {} for {}", value, arg),
+        )),
+    }
+}
+
+/// A range of frames is "first-last", or a single frame on its own.
+fn parse_frames(value: &str) -> Result<(u32, u32), Error> {
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("bad frames {}", value));
+    let (first, last) = value.split_once('-').unwrap_or((value, value));
+    let first = first.trim().parse::<u32>().map_err(|_| invalid())?;
+    let last = last.trim().parse::<u32>().map_err(|_| invalid())?;
+    if last < first {
+        return Err(invalid());
+    }
+    Ok((first, last))
+}
+
+/// An aperture is "circle", a number of blades, or a PNG mask.
+fn parse_aperture(value: &str) -> Result<Aperture, Error> {
+    if value == "circle" {
+        return Ok(Aperture::Circle);
+    }
+    match value.parse::<u32>() {
+        Ok(blades) if blades >= 3 => Ok(Aperture::Polygon {
+            blades,
+            rotation: 0.0,
+        }),
+        Ok(_) => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("an aperture needs at least 3 blades, not {}", value),
+        )),
+        Err(_) => Aperture::load(Path::new(value)),
+    }
+}
+
+fn parse_args() -> Result<Options, Error> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("missing value for {}", arg),
+                )
+            })
+        };
+
+        match arg.as_str() {
+            "--scene" => options.scene = Some(value()?),
+            "--volume" => options.volume = Some(value()?),
+            "--normal-map" => options.normal_map = Some(value()?),
+            "--camera" => options.camera = Some(value()?),
+            "--lens" => options.lens = Some(value()?),
+            "--lens-stop" => options.lens_stop = Some(parse_number(&arg, &value()?)?),
+            "--f-number" => options.physical.f_number = parse_number(&arg, &value()?)?,
+            "--focal-length" => options.physical.focal_length = parse_number(&arg, &value()?)?,
+            "--shutter" => options.physical.shutter = parse_number(&arg, &value()?)?,
+            "--iso" => options.physical.iso = parse_number(&arg, &value()?)?,
+            "--sensor-width" => options.physical.sensor_width = parse_number(&arg, &value()?)?,
+            "--aperture" => options.physical.aperture = parse_aperture(&value()?)?,
+            "--stereo-layout" => {
+                options.stereo.layout = match value()?.as_str() {
+                    "side-by-side" => StereoLayout::SideBySide,
+                    "top-bottom" => StereoLayout::TopBottom,
+                    other => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("unknown stereo layout {}", other),
+                        ))
+                    }
+                }
+            }
+            "--ipd" => options.stereo.ipd = parse_number(&arg, &value()?)?,
+            "--convergence" => options.stereo.convergence = Some(parse_number(&arg, &value()?)?),
+            "--environment" => options.environment = Some(value()?),
+            "--sky" => options.sky = Some(parse_sky(&value()?)?),
+            "--spectral" => options.spectral = true,
+            "--frames" => options.frames = Some(parse_frames(&value()?)?),
+            "--fps" => options.fps = Some(parse_number(&arg, &value()?)?),
+            "--resume" => options.resume = true,
+            "--seed" => {
+                let value = value()?;
+                options.seed = value.parse::<u64>().map_err(|_| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("bad value {} for {}", value, arg),
+                    )
+                })?
+            }
+            "--checkpoint-interval" => {
+                options.checkpoint_interval = Some(parse_number(&arg, &value()?)?)
+            }
+            "--light-sampling" => {
+                options.light_sampling = match value()?.as_str() {
+                    "uniform" => LightSampling::Uniform,
+                    "power" => LightSampling::Power,
+                    "bvh" => LightSampling::Bvh,
+                    other => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("unknown light sampling {}", other),
+                        ))
+                    }
+                }
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("unknown argument {}", arg),
+                ))
+            }
+        }
+    }
+
+    Ok(options)
+}
+
+const ASPECT_RATIO: f32 = 3.0 / 2.0;
+const WIDTH: u32 = 900;
+const HEIGHT: u32 = (WIDTH as f32 / ASPECT_RATIO) as u32;
+
+/// Where the camera is over time for the scene being rendered.
+fn camera_animation(options: &Options) -> CameraAnimation {
+    match options.scene.as_deref() {
+        Some("animated") => generate_animated_camera(),
+        _ => CameraAnimation::still(
+            Point3::new(13.0, 2.0, 3.0),
+            Point3::new(0.0, 0.0, 0.0),
+            20.0,
+        ),
+    }
+}
+
+fn build_camera(
+    options: &Options,
+    lens: &LensSystem,
+    pose: &CameraPose,
+) -> Result<Box<dyn Camera>, Error> {
+    let (lookfrom, lookat) = (pose.lookfrom, pose.lookat);
+    let vup = Vec3::new(0.0, 1.0, 0.0);
+    let aspect_ratio = WIDTH as f32 / HEIGHT as f32;
+    let focus_distance = pose
+        .focus_distance
+        .unwrap_or_else(|| (lookat - lookfrom).length());
+
+    let camera: Box<dyn Camera> = match options.camera.as_deref() {
+        None | Some("perspective") => Box::new(PerspectiveCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            pose.vfov,
+            aspect_ratio,
+            0.1,
+            pose.focus_distance.unwrap_or(10.0),
+        )),
+        Some("orthographic") => Box::new(OrthographicCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            4.0,
+            aspect_ratio,
+        )),
+        Some("fisheye") => Box::new(FisheyeCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            180.0,
+            aspect_ratio,
+            FisheyeProjection::Equidistant,
+        )),
+        Some("fisheye-equisolid") => Box::new(FisheyeCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            180.0,
+            aspect_ratio,
+            FisheyeProjection::Equisolid,
+        )),
+        Some("equirectangular") => Box::new(EquirectangularCamera::new(lookfrom, lookat, vup)),
+        Some("physical") => Box::new(PhysicalCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            aspect_ratio,
+            focus_distance,
+            &options.physical,
+        )),
+        Some("stereo") => Box::new(StereoCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            aspect_ratio,
+            StereoProjection::Perspective { vfov: pose.vfov },
+            &options.stereo,
+        )),
+        Some("stereo-panorama") => Box::new(StereoCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            aspect_ratio,
+            StereoProjection::Equirectangular,
+            &options.stereo,
+        )),
+        Some("lens") => Box::new(LensSystemCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            aspect_ratio,
+            lens,
+            options.physical.sensor_width,
+            focus_distance,
+        )),
+        Some(camera) => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown camera {}", camera),
+            ))
+        }
+    };
+
+    Ok(camera)
+}
+
+/// The scene as it is `time` seconds in, which only matters for animated
+/// scenes.
+fn build_scene(options: &Options, time: f32) -> Result<Scene, Error> {
+    // the scenes placed at random come out the same for the same seed, so
+    // a render can be carried on with
+    let rng = &mut StdRng::seed_from_u64(options.seed);
+    let mut scene = Scene::new();
+    let world = &mut scene.world;
+
+    match options.scene.as_deref() {
+        None => scene.fog = Some(generate_default_scene(world)),
+        Some("large") => {
+            generate_large_scene(rng, world);
+        }
+        Some("csg") => {
+            generate_csg_scene(world);
+        }
+        Some("sdf") => {
+            generate_sdf_scene(world);
+        }
+        Some("glass") => {
+            generate_glass_scene(world);
+        }
+        Some("prism") => {
+            generate_prism_scene(world);
+        }
+        Some("principled") => {
+            generate_principled_scene(world);
+        }
+        Some("layered") => {
+            generate_layered_scene(world);
+        }
+        Some("bump") => {
+            let normal_map = match &options.normal_map {
+                Some(path) => Some(ImageTexture::load(Path::new(path), false)?),
+                None => None,
+            };
+            generate_bump_scene(world, normal_map);
+        }
+        Some("cutout") => {
+            generate_cutout_scene(world);
+        }
+        Some("subsurface") => {
+            generate_subsurface_scene(world);
+        }
+        Some("iridescent") => {
+            generate_iridescent_scene(world);
+        }
+        Some("lights") => {
+            generate_lights_scene(&mut scene);
+        }
+        Some("punctual") => {
+            generate_punctual_scene(&mut scene);
+        }
+        Some("many-lights") => {
+            generate_many_lights_scene(rng, &mut scene);
+        }
+        Some("environment") => {
+            generate_environment_scene(&mut scene);
+        }
+        Some("animated") => {
+            generate_animated_scene(world, time);
+        }
+        Some(scene) => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown scene {}", scene),
+            ))
+        }
+    }
+
+    if let Some(path) = &options.volume {
+        let grids = grid::load_volume_grids(Path::new(path))?;
+        scene.world.add(GridVolume::new(
+            Aabb::new(Point3::new(-2.5, 0.0, 2.0), Point3::new(-0.5, 2.0, 4.0)),
+            grids,
+            10.0,
+            1.0,
+        ));
+    }
+    if let Some(path) = &options.environment {
+        let path = Path::new(path);
+        let image = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("hdr") => ImageTexture::load_hdr(path)?,
+            _ => ImageTexture::load(path, true)?,
+        };
+        scene.background = Color::new(0.0, 0.0, 0.0);
+        scene.world.add_light(EnvironmentMap::new(image, 1.0));
+    }
+    if let Some(sky) = options.sky {
+        scene.background = Color::new(0.0, 0.0, 0.0);
+        scene.world.add_light(sky.sun());
+        scene.world.add_light(sky.light());
+    }
+    scene.world.lights.build(options.light_sampling);
+
+    Ok(scene)
+}
+
+/// The arguments that change what's rendered, to tell checkpoints of
+/// different renders apart. The frame range doesn't, each frame has its
+/// own checkpoint.
+fn render_settings() -> String {
+    let mut settings = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--resume" => {}
+            "--frames" | "--checkpoint-interval" => {
+                args.next();
+            }
+            _ => settings.push(arg),
+        }
+    }
+    settings.join(" ")
+}
+
+/// Add one more sample to every pixel of `checkpoint`. All of the pass's
+/// random numbers come from one generator seeded by the render's seed and
+/// the pass's index, so a render carried on from a checkpoint comes out
+/// exactly the same as one that never stopped.
+fn render_pass(scene: &Scene, camera: &dyn Camera, spectral: bool, checkpoint: &mut Checkpoint) {
+    let pass = checkpoint.passes as u64 + 1;
+    let mut rng = StdRng::seed_from_u64(checkpoint.seed ^ pass.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    let (width, height) = (checkpoint.width, checkpoint.height);
+
+    for row in 0..height {
+        let j = height - 1 - row;
+        for i in 0..width {
+            // u and v are the how far, as a percentage, x and y are from
+            // the vertical and horizontal of our viewport. This is used
+            // to map our pixel coords to the "camera" coords.
+            let u = (i as f32 + rng.gen::<f32>()) / (width - 1) as f32;
+            let v = (j as f32 + rng.gen::<f32>()) / (height - 1) as f32;
+            let wavelength_u = rng.gen::<f32>();
+
+            // origin is the camera (0, 0 ,0) and direction is the point in
+            // the viewport whose color value we are calculating.
+            let (ray, weight) = match camera.get_weighted_ray(u, v, &mut rng) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            let media = MediumStack::new();
+            let sum = &mut checkpoint.sums[(row * width + i) as usize];
+            if spectral {
+                let wavelengths = SampledWavelengths::sample(wavelength_u);
+                let radiance =
+                    color_pixel(&ray, scene, &media, Some(&wavelengths), None, 50, &mut rng);
+                *sum += wavelengths.to_rgb(radiance) * weight;
+            } else {
+                *sum += color_pixel(&ray, scene, &media, None, None, 50, &mut rng) * weight;
+            }
+        }
+    }
+
+    checkpoint.passes += 1;
+}
+
+/// Render to a PNG at `path`, saving what's been done so far beside it every
+/// so often so that an interrupted render can be resumed. The checkpoint is
+/// removed once the image is written.
+fn render(scene: &Scene, camera: &dyn Camera, options: &Options, path: &Path) -> Result<(), Error> {
+    let samples_per_pixel = 50;
+    let checkpoint_path = path.with_extension("checkpoint");
+    let settings = render_settings();
+
+    let mut checkpoint = if options.resume && checkpoint_path.exists() {
+        let checkpoint = Checkpoint::load(&checkpoint_path, WIDTH, HEIGHT)?;
+        if !checkpoint.matches(samples_per_pixel, options.seed, &settings) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{} is from a different render: {}",
+                    checkpoint_path.display(),
+                    checkpoint.settings
+                ),
+            ));
+        }
+        log::info!(
+            "resuming {} from {} of {} samples",
+            path.display(),
+            checkpoint.passes,
+            samples_per_pixel
+        );
+        checkpoint
+    } else {
+        Checkpoint::new(WIDTH, HEIGHT, samples_per_pixel, options.seed, settings)
+    };
+
+    let interval = Duration::from_secs_f32(options.checkpoint_interval.unwrap_or(60.0));
+    let mut last_saved = Instant::now();
+    while !checkpoint.is_finished() {
+        render_pass(scene, camera, options.spectral, &mut checkpoint);
+
+        if !checkpoint.is_finished() && last_saved.elapsed() >= interval {
+            checkpoint.save(&checkpoint_path)?;
+            last_saved = Instant::now();
+        }
+    }
+
+    // the checkpoint is only needed until the image is written, and kept
+    // if that fails
+    if let Err(err) = write_image(path, &checkpoint, camera.exposure()) {
+        checkpoint.save(&checkpoint_path)?;
+        return Err(err);
+    }
+    match fs::remove_file(&checkpoint_path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Write the average of the samples in `checkpoint` to a PNG.
+fn write_image(path: &Path, checkpoint: &Checkpoint, exposure: f32) -> Result<(), Error> {
+    let file = File::create(path)?;
+    let w = &mut BufWriter::new(file);
+
+    let mut encoder = png::Encoder::new(w, checkpoint.width, checkpoint.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_trns(vec![0xFFu8, 0xFFu8, 0xFFu8, 0xFFu8]);
+
+    let mut image: Vec<u8> = Vec::new();
+    for pixel_color in &checkpoint.sums {
+        let color = calculate_color(*pixel_color * exposure, checkpoint.passes as i32);
+        let ir = (color.x) as u8;
+        let ig = (color.y) as u8;
+        let ib = (color.z) as u8;
+        image.push(ir);
+        image.push(ig);
+        image.push(ib);
+        image.push(255);
+    }
+
+    let mut writer = encoder.write_header().map_err(Error::other)?;
+    writer.write_image_data(&image).map_err(Error::other)?;
+
+    Ok(())
+}
+
+fn main() -> Result<(), Error> {
+    env_logger::init();
+
+    let options = parse_args()?;
+
+    let mut lens = match &options.lens {
+        Some(path) => LensSystem::load(Path::new(path))?,
+        None => LensSystem::double_gauss(),
+    };
+    if let Some(diameter) = options.lens_stop {
+        lens.set_stop_diameter(diameter);
+    }
+    let animation = camera_animation(&options);
+
+    let (first, last) = match options.frames {
+        Some(frames) => frames,
+        None => {
+            let scene = build_scene(&options, 0.0)?;
+            let camera = build_camera(&options, &lens, &animation.at(0.0))?;
+            return render(&scene, camera.as_ref(), &options, Path::new("image.png"));
+        }
+    };
+
+    let fps = options.fps.unwrap_or(24.0);
+    let animated = options.scene.as_deref() == Some("animated");
+    let mut scene = build_scene(&options, first as f32 / fps)?;
+    for frame in first..=last {
+        let time = frame as f32 / fps;
+        // the other scenes don't change
+        if animated && frame != first {
+            scene = build_scene(&options, time)?;
+        }
+
+        let camera = build_camera(&options, &lens, &animation.at(time))?;
+        let path = format!("frame-{:04}.png", frame);
+        log::info!("rendering {}", path);
+        render(&scene, camera.as_ref(), &options, Path::new(&path))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dispersive_glass_furnace() {
+        // Clear glass under a uniform white sky neither adds nor takes away
+        // light, however many dispersive interfaces a path goes through.
+        let mut scene = Scene::new();
+        scene.background = Color::new(1.0, 1.0, 1.0);
+        scene.world.add(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            Rc::new(Dialetric::dispersive(Dispersion::sf11())),
+        ));
+
+        let mut rng = StdRng::seed_from_u64(33);
+        let n = 20_000;
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+            let wavelengths = SampledWavelengths::sample((i as f32 + 0.5) / n as f32);
+            let ray = Ray {
+                origin: Point3::new(rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5), -5.0),
+                direction: Vec3::new(0.0, 0.0, 1.0),
+            };
+            let media = MediumStack::new();
+            let radiance =
+                color_pixel(&ray, &scene, &media, Some(&wavelengths), None, 50, &mut rng);
+            sum += wavelengths.to_rgb(radiance);
+        }
+        let rgb = sum / n as f32;
+
+        assert!(
+            (rgb - Color::new(1.0, 1.0, 1.0)).length() < 0.05,
+            "{:?}",
+            rgb
+        );
+    }
+
+    #[test]
+    fn test_resumed_render_matches() {
+        let options = Options {
+            scene: Some("many-lights".to_string()),
+            seed: 3,
+            ..Options::default()
+        };
+        let lens = LensSystem::double_gauss();
+        let camera = build_camera(&options, &lens, &camera_animation(&options).at(0.0)).unwrap();
+        let render = |checkpoint: &mut Checkpoint| {
+            // built again each time, as a resumed render would
+            let scene = build_scene(&options, 0.0).unwrap();
+            while !checkpoint.is_finished() {
+                render_pass(&scene, camera.as_ref(), false, checkpoint);
+            }
+        };
+
+        let mut straight = Checkpoint::new(12, 8, 4, options.seed, String::new());
+        render(&mut straight);
+
+        // stopped after a pass and saved, then carried on
+        let mut stopped = Checkpoint::new(12, 8, 4, options.seed, String::new());
+        let scene = build_scene(&options, 0.0).unwrap();
+        render_pass(&scene, camera.as_ref(), false, &mut stopped);
+        let mut bytes = Vec::new();
+        stopped.write_to(&mut bytes).unwrap();
+        let mut resumed = Checkpoint::read_from(&mut bytes.as_slice(), 12, 8).unwrap();
+        render(&mut resumed);
+
+        assert_eq!(resumed.sums, straight.sums);
+
+        // which isn't just because nothing is random
+        let mut reseeded = Checkpoint::new(12, 8, 4, options.seed + 1, String::new());
+        render(&mut reseeded);
+        assert_ne!(reseeded.sums, straight.sums);
+    }
 }
@@ -1,10 +1,58 @@
-use rand::Rng;
+use rand::{Rng, RngCore};
 
+use crate::medium::Interior;
+use crate::microfacet::{self, fresnel_conductor_rgb, Ggx};
+use crate::onb::Onb;
+use crate::spectrum::Dispersion;
 use crate::vec3::{Color, Vec3};
 use crate::rayhit::{HitRecord, Ray};
 
 pub trait Material {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>;
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<(Color, Ray)>;
+
+    /// Light given off at the hit point. Most materials don't emit.
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    /// The medium enclosed by surfaces of this material, for dielectrics.
+    /// The integrator keeps track of these along paths.
+    fn interior(&self) -> Option<Interior> {
+        None
+    }
+
+    /// Scatter off a dielectric surface where the medium on the far side of
+    /// the surface is known. `eta` is the index of refraction on the far
+    /// side over the near side. `scatter` assumes air on the outside.
+    fn scatter_interface(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        _eta: f32,
+        rng: &mut dyn RngCore,
+    ) -> Option<(Color, Ray)> {
+        self.scatter(r_in, rec, rng)
+    }
+
+    /// Whether the material gives off light, so that objects made of it
+    /// should be sampled as lights.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// The BSDF times the cosine for light arriving from `direction`, and
+    /// the pdf of `scatter` picking that direction, for light sampling. None
+    /// for materials that can't be evaluated, like smooth ones, which only
+    /// find lights by scattering into them.
+    fn eval(
+        &self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _direction: Vec3,
+        _rng: &mut dyn RngCore,
+    ) -> Option<(Color, f32)> {
+        None
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -13,8 +61,8 @@ pub struct Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        let mut scatter_direction = rec.normal + Vec3::random_unit_vector(rng);
 
         // catch degenerate scatter direction
         if scatter_direction.near_zero() {
@@ -28,33 +76,230 @@ impl Material for Lambertian {
 
         Some((self.albedo, scattered))
     }
+
+    fn eval(
+        &self,
+        _r_in: &Ray,
+        rec: &HitRecord,
+        direction: Vec3,
+        _rng: &mut dyn RngCore,
+    ) -> Option<(Color, f32)> {
+        let cos_theta = f32::max(0.0, direction.unit_vector() * rec.normal);
+        let pdf = cos_theta / std::f32::consts::PI;
+        Some((self.albedo * pdf, pdf))
+    }
+}
+
+/// A surface that gives off light from its front face, and reflects none.
+#[derive(Clone, Copy)]
+pub struct DiffuseLight {
+    pub emit: Color,
+}
+
+impl Material for DiffuseLight {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _rng: &mut dyn RngCore,
+    ) -> Option<(Color, Ray)> {
+        None
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        if rec.front_face {
+            self.emit
+        } else {
+            Color::new(0.0, 0.0, 0.0)
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
 }
 
+/// Metal modelled as a rough conductor. Reflection off the microfacets uses
+/// the GGX distribution with visible normal sampling, and the exact Fresnel
+/// term for the metal's complex index of refraction eta + ik.
+#[derive(Clone, Copy, Debug)]
 pub struct Metal {
-    pub albedo: Color,
-    pub fuzz: f32,
+    pub eta: Color,
+    pub k: Color,
+    pub distribution: Ggx,
+}
+
+impl Metal {
+    /// A metal with the given reflectance at normal incidence. There's no
+    /// absorption term, the index of refraction is picked to give `albedo`
+    /// head on and the reflection goes to white at grazing angles.
+    pub fn new(albedo: Color, roughness: f32) -> Metal {
+        let eta = |r: f32| {
+            let r = f32::sqrt(r.clamp(0.0, 0.999));
+            (1.0 + r) / (1.0 - r)
+        };
+
+        Metal {
+            eta: Color::new(eta(albedo.x), eta(albedo.y), eta(albedo.z)),
+            k: Color::new(0.0, 0.0, 0.0),
+            distribution: Ggx::from_roughness(roughness, roughness),
+        }
+    }
+
+    /// A metal from its measured complex index of refraction, with
+    /// separate roughness along the two tangent directions.
+    pub fn anisotropic(eta: Color, k: Color, roughness_u: f32, roughness_v: f32) -> Metal {
+        Metal {
+            eta,
+            k,
+            distribution: Ggx::from_roughness(roughness_u, roughness_v),
+        }
+    }
+
+    pub fn gold(roughness: f32) -> Metal {
+        Metal::anisotropic(
+            Color::new(0.143, 0.374, 1.442),
+            Color::new(3.983, 2.385, 1.603),
+            roughness,
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f32) -> Metal {
+        Metal::anisotropic(
+            Color::new(0.200, 0.924, 1.102),
+            Color::new(3.912, 2.452, 2.142),
+            roughness,
+            roughness,
+        )
+    }
+
+    pub fn aluminum(roughness: f32) -> Metal {
+        Metal::anisotropic(
+            Color::new(1.657, 0.880, 0.521),
+            Color::new(9.224, 6.270, 4.837),
+            roughness,
+            roughness,
+        )
+    }
+
+    /// Sample a reflected direction for `wo`, both in the local shading
+    /// frame. Returns the reflected direction and its weight, f * cos / pdf.
+    fn sample(&self, wo: Vec3, u1: f32, u2: f32) -> Option<(Color, Vec3)> {
+        if self.distribution.is_smooth() {
+            let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+            return Some((fresnel_conductor_rgb(wo.z, self.eta, self.k), wi));
+        }
+
+        let wm = self.distribution.sample_visible_normal(wo, u1, u2);
+        let wi = microfacet::reflect(wo, wm);
+        // reflected below the surface, the light is lost
+        if wi.z <= 0.0 {
+            return None;
+        }
+
+        // With visible normal sampling the D and cosine terms cancel, which
+        // leaves F * G2 / G1.
+        let f = fresnel_conductor_rgb(wo * wm, self.eta, self.k);
+        let weight = self.distribution.g(wo, wi) / self.distribution.g1(wo);
+
+        Some((f * weight, wi))
+    }
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        let reflected = r_in.direction.unit_vector().reflect(rec.normal);
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        let uvw = rec.shading_frame();
+        let wo = uvw.to_local(-r_in.direction.unit_vector());
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let (attenuation, wi) = self.sample(wo, rng.gen::<f32>(), rng.gen::<f32>())?;
+
         let scattered = Ray {
             origin: rec.p,
-            direction: reflected + (Vec3::random_in_unit_sphere() * self.fuzz),
+            direction: uvw.local(wi.x, wi.y, wi.z),
         };
-        let attenuation = self.albedo;
 
-        if scattered.direction * rec.normal > 0.0 {
-            Some((attenuation, scattered))
-        } else {
-            None
+        Some((attenuation, scattered))
+    }
+
+    fn eval(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        direction: Vec3,
+        _rng: &mut dyn RngCore,
+    ) -> Option<(Color, f32)> {
+        if self.distribution.is_smooth() {
+            return None;
         }
+
+        let uvw = rec.shading_frame();
+        let wo = uvw.to_local(-r_in.direction.unit_vector());
+        let wi = uvw.to_local(direction.unit_vector());
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Some((Color::new(0.0, 0.0, 0.0), 0.0));
+        }
+
+        // visible normal sampling picks wm with pdf G1 * max(0, wo.wm) * D
+        // / wo.z, and reflecting about it adds the 1 / (4 wo.wm)
+        let wm = (wo + wi).unit_vector();
+        let d = self.distribution.d(wm);
+        let f = fresnel_conductor_rgb(wo * wm, self.eta, self.k);
+        let pdf = self.distribution.g1(wo) * d / (4.0 * wo.z);
+
+        Some((f * (d * self.distribution.g(wo, wi) / (4.0 * wo.z)), pdf))
     }
 }
 
 #[derive(Copy, Clone, Debug, Default)]
 pub struct Dialetric {
     pub index_of_refraction: f32,
+    /// Absorption coefficient of the inside per unit distance. Zero for
+    /// clear glass.
+    pub absorption: Color,
+    /// See `Interior::priority`.
+    pub priority: u32,
+    /// Wavelength dependent index of refraction, used in spectral mode.
+    /// `index_of_refraction` is used otherwise.
+    pub dispersion: Option<Dispersion>,
+}
+
+impl Dialetric {
+    pub fn new(index_of_refraction: f32) -> Dialetric {
+        Dialetric {
+            index_of_refraction,
+            ..Default::default()
+        }
+    }
+
+    /// Glass that splits light into its colors in spectral mode. Outside of
+    /// spectral mode it uses the index of refraction for yellow light.
+    pub fn dispersive(dispersion: Dispersion) -> Dialetric {
+        Dialetric {
+            index_of_refraction: dispersion.ior(587.6),
+            dispersion: Some(dispersion),
+            ..Default::default()
+        }
+    }
+
+    /// Colored glass that lets through `color` of the light after traveling
+    /// `distance` through it.
+    pub fn colored(index_of_refraction: f32, color: Color, distance: f32) -> Dialetric {
+        Dialetric {
+            index_of_refraction,
+            absorption: absorption_for(color, distance),
+            ..Default::default()
+        }
+    }
+}
+
+/// Absorption coefficient that leaves `color` of the light after `distance`.
+fn absorption_for(color: Color, distance: f32) -> Color {
+    let sigma = |c: f32| -f32::ln(c.clamp(1e-6, 1.0)) / distance;
+    Color::new(sigma(color.x), sigma(color.y), sigma(color.z))
 }
 
 pub fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
@@ -64,20 +309,42 @@ pub fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
 }
 
 impl Material for Dialetric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        let attenuation = Color::new(1.0, 1.0, 1.0);
-        let refration_ratio = if rec.front_face {
-            1.0 / self.index_of_refraction
-        } else {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        let eta = if rec.front_face {
             self.index_of_refraction
+        } else {
+            1.0 / self.index_of_refraction
         };
 
+        self.scatter_interface(r_in, rec, eta, rng)
+    }
+
+    fn interior(&self) -> Option<Interior> {
+        Some(Interior {
+            index_of_refraction: self.index_of_refraction,
+            absorption: self.absorption,
+            scattering: Color::new(0.0, 0.0, 0.0),
+            priority: self.priority,
+            dispersion: self.dispersion,
+        })
+    }
+
+    fn scatter_interface(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        eta: f32,
+        rng: &mut dyn RngCore,
+    ) -> Option<(Color, Ray)> {
+        // Absorption happens along the path inside, not at the surface.
+        let attenuation = Color::new(1.0, 1.0, 1.0);
+        let refration_ratio = 1.0 / eta;
+
         let unit_direction = r_in.direction.unit_vector();
 
         let cos_theta = f32::min(-unit_direction * rec.normal, 1.0);
         let sin_theta = f32::sqrt(1.0 - (cos_theta * cos_theta));
 
-        let mut rng = rand::thread_rng();
         let cannot_refarct = refration_ratio * sin_theta > 1.0;
         let direction = if cannot_refarct
             || reflectance(cos_theta, refration_ratio) > rng.gen_range(0.0..1.0)
@@ -96,3 +363,748 @@ impl Material for Dialetric {
         ))
     }
 }
+
+/// Glass with a rough surface, e.g. frosted or sandblasted glass. Light is
+/// reflected or transmitted through GGX microfacets (Walter et al. 2007)
+/// with the choice between the two made by the exact Fresnel term. With a
+/// roughness of zero this is the same as `Dialetric`.
+#[derive(Clone, Copy, Debug)]
+pub struct RoughDielectric {
+    pub index_of_refraction: f32,
+    pub distribution: Ggx,
+    /// Absorption coefficient of the inside per unit distance.
+    pub absorption: Color,
+    /// See `Interior::priority`.
+    pub priority: u32,
+}
+
+impl RoughDielectric {
+    pub fn new(index_of_refraction: f32, roughness: f32) -> RoughDielectric {
+        RoughDielectric {
+            index_of_refraction,
+            distribution: Ggx::from_roughness(roughness, roughness),
+            absorption: Color::new(0.0, 0.0, 0.0),
+            priority: 0,
+        }
+    }
+
+    /// Frosted colored glass, see `Dialetric::colored`.
+    pub fn colored(
+        index_of_refraction: f32,
+        roughness: f32,
+        color: Color,
+        distance: f32,
+    ) -> RoughDielectric {
+        RoughDielectric {
+            absorption: absorption_for(color, distance),
+            ..RoughDielectric::new(index_of_refraction, roughness)
+        }
+    }
+
+    /// Sample a scattered direction for `wo`, both in the local shading
+    /// frame. `eta` is the index of refraction on the far side of the
+    /// surface over the near side. Returns the direction and its weight.
+    fn sample(&self, wo: Vec3, eta: f32, u: [f32; 3]) -> Option<(Color, Vec3)> {
+        let wm = if self.distribution.is_smooth() {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            self.distribution.sample_visible_normal(wo, u[0], u[1])
+        };
+
+        // Reflection and refraction are picked in proportion to the Fresnel
+        // term so it cancels out of the weight. The remaining weight is the
+        // same for both with visible normal sampling.
+        let reflectance = microfacet::fresnel_dielectric(wo * wm, eta);
+        let wi = if u[2] < reflectance {
+            let wi = microfacet::reflect(wo, wm);
+            if wi.z <= 0.0 {
+                return None;
+            }
+            wi
+        } else {
+            let wi = microfacet::refract(wo, wm, eta)?;
+            if wi.z >= 0.0 {
+                return None;
+            }
+            wi
+        };
+
+        let weight = if self.distribution.is_smooth() {
+            1.0
+        } else {
+            self.distribution.g(wo, wi) / self.distribution.g1(wo)
+        };
+
+        Some((Color::new(weight, weight, weight), wi))
+    }
+
+    /// The BSDF times the cosine, and the pdf of `sample`, for scattering
+    /// from `wo` to `wi` in the local shading frame. Only meaningful for
+    /// rough surfaces, smooth ones are all delta.
+    pub fn eval_local(&self, wo: Vec3, wi: Vec3, eta: f32) -> (f32, f32) {
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return (0.0, 0.0);
+        }
+
+        if wi.z > 0.0 {
+            // as for metal, with the Fresnel term picking reflection
+            let wm = (wo + wi).unit_vector();
+            let reflectance = microfacet::fresnel_dielectric(wo * wm, eta);
+            let d = self.distribution.d(wm);
+            let f = reflectance * d * self.distribution.g(wo, wi) / (4.0 * wo.z);
+            let pdf = reflectance * self.distribution.g1(wo) * d / (4.0 * wo.z);
+            return (f, pdf);
+        }
+
+        // the generalized half vector, facing up, and the change from
+        // microfacet normals to refracted directions
+        let wm = wo + wi * eta;
+        if wm.near_zero() {
+            return (0.0, 0.0);
+        }
+        let wm = if wm.z < 0.0 { -wm } else { wm }.unit_vector();
+        let (cos_o, cos_i) = (wo * wm, wi * wm);
+        if cos_o <= 0.0 || cos_i >= 0.0 {
+            return (0.0, 0.0);
+        }
+        let denom = cos_i + cos_o / eta;
+        let dwm_dwi = -cos_i / (denom * denom);
+
+        let transmittance = 1.0 - microfacet::fresnel_dielectric(cos_o, eta);
+        let d = self.distribution.d(wm);
+        // no 1 / eta^2 for the squeezing of radiance, to match `sample`
+        let f = transmittance * d * self.distribution.g(wo, wi) * cos_o * dwm_dwi / wo.z;
+        let pdf = transmittance * self.distribution.g1(wo) * cos_o * d * dwm_dwi / wo.z;
+        (f, pdf)
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        let eta = if rec.front_face {
+            self.index_of_refraction
+        } else {
+            1.0 / self.index_of_refraction
+        };
+
+        self.scatter_interface(r_in, rec, eta, rng)
+    }
+
+    fn interior(&self) -> Option<Interior> {
+        Some(Interior {
+            index_of_refraction: self.index_of_refraction,
+            absorption: self.absorption,
+            scattering: Color::new(0.0, 0.0, 0.0),
+            priority: self.priority,
+            dispersion: None,
+        })
+    }
+
+    fn scatter_interface(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        eta: f32,
+        rng: &mut dyn RngCore,
+    ) -> Option<(Color, Ray)> {
+        let uvw = rec.shading_frame();
+        let wo = uvw.to_local(-r_in.direction.unit_vector());
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let u = [rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()];
+        let (attenuation, wi) = self.sample(wo, eta, u)?;
+
+        let scattered = Ray {
+            origin: rec.p,
+            direction: uvw.local(wi.x, wi.y, wi.z),
+        };
+
+        Some((attenuation, scattered))
+    }
+
+    fn eval(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        direction: Vec3,
+        _rng: &mut dyn RngCore,
+    ) -> Option<(Color, f32)> {
+        if self.distribution.is_smooth() {
+            return None;
+        }
+
+        let eta = if rec.front_face {
+            self.index_of_refraction
+        } else {
+            1.0 / self.index_of_refraction
+        };
+        let uvw = rec.shading_frame();
+        let wo = uvw.to_local(-r_in.direction.unit_vector());
+        let wi = uvw.to_local(direction.unit_vector());
+        let (f, pdf) = self.eval_local(wo, wi, eta);
+        Some((Color::new(f, f, f), pdf))
+    }
+}
+
+/// Translucent material like wax, marble, jade or skin. Light refracts in
+/// through a dielectric surface, scatters around inside and comes back out
+/// somewhere else, if it isn't absorbed first. The integrator random walks
+/// through the inside like it does through colored glass, each step counting
+/// against the bounce limit, so mean free paths much shorter than the object
+/// is big get cut short and come out darker.
+#[derive(Clone, Copy, Debug)]
+pub struct Subsurface {
+    pub boundary: RoughDielectric,
+    /// Scattering coefficient of the inside per unit distance. The
+    /// absorption is the boundary's.
+    pub scattering: Color,
+}
+
+impl Subsurface {
+    /// `albedo` is the color the material looks overall, after all the
+    /// scattering inside. `mean_free_path` is how far light travels between
+    /// scattering events on average, per channel, the larger it is the more
+    /// translucent the material.
+    pub fn new(
+        albedo: Color,
+        mean_free_path: Color,
+        index_of_refraction: f32,
+        roughness: f32,
+    ) -> Subsurface {
+        let extinction = |mfp: f32| 1.0 / f32::max(mfp, 1e-6);
+        let extinction = Color::new(
+            extinction(mean_free_path.x),
+            extinction(mean_free_path.y),
+            extinction(mean_free_path.z),
+        );
+        let single = Color::new(
+            single_scattering_albedo(albedo.x),
+            single_scattering_albedo(albedo.y),
+            single_scattering_albedo(albedo.z),
+        );
+        let scattering = Color::new(
+            single.x * extinction.x,
+            single.y * extinction.y,
+            single.z * extinction.z,
+        );
+
+        Subsurface {
+            boundary: RoughDielectric {
+                absorption: extinction - scattering,
+                ..RoughDielectric::new(index_of_refraction, roughness)
+            },
+            scattering,
+        }
+    }
+}
+
+/// The albedo of each scattering event that gives an overall albedo of
+/// `multiple` after many of them, from the fit in Chiang et al. 2016.
+fn single_scattering_albedo(multiple: f32) -> f32 {
+    let a = multiple.clamp(0.0, 1.0);
+    let s = 4.09712 + 4.20863 * a - f32::sqrt(9.59217 + 41.6808 * a + 17.7126 * a * a);
+    (1.0 - s * s).clamp(0.0, 1.0)
+}
+
+impl Material for Subsurface {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        self.boundary.scatter(r_in, rec, rng)
+    }
+
+    fn interior(&self) -> Option<Interior> {
+        let mut interior = self.boundary.interior()?;
+        interior.scattering = self.scattering;
+        Some(interior)
+    }
+
+    fn scatter_interface(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        eta: f32,
+        rng: &mut dyn RngCore,
+    ) -> Option<(Color, Ray)> {
+        self.boundary.scatter_interface(r_in, rec, eta, rng)
+    }
+}
+
+/// Cloth like velvet, with a diffuse base and a sheen from fibers
+/// sticking up out of the surface that catch the light at grazing angles.
+/// The sheen uses the "Charlie" distribution of Estevez and Kulla 2017.
+#[derive(Clone, Copy, Debug)]
+pub struct Velvet {
+    pub diffuse: Color,
+    pub sheen: Color,
+    /// From 0, fibers all standing straight up, to 1, lying every which
+    /// way.
+    pub roughness: f32,
+}
+
+impl Velvet {
+    pub fn new(diffuse: Color, sheen: Color, roughness: f32) -> Velvet {
+        Velvet {
+            diffuse,
+            sheen,
+            roughness,
+        }
+    }
+
+    /// The sheen BRDF, in the local shading frame.
+    fn sheen_brdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        let alpha = self.roughness.clamp(0.01, 1.0);
+        let wh = (wo + wi).unit_vector();
+        let sin_h = f32::sqrt(f32::max(0.0, 1.0 - wh.z * wh.z));
+        let d = (2.0 + 1.0 / alpha) * sin_h.powf(1.0 / alpha) / (2.0 * std::f32::consts::PI);
+
+        // the simpler visibility term from Neubelt and Pettineo 2013
+        let v = 1.0 / (4.0 * (wi.z + wo.z - wi.z * wo.z));
+        d * v
+    }
+}
+
+impl Material for Velvet {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        let uvw = rec.shading_frame();
+        let wo = uvw.to_local(-r_in.direction.unit_vector());
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let mut wi = Vec3::new(0.0, 0.0, 1.0) + Vec3::random_unit_vector(rng);
+        if wi.near_zero() {
+            wi = Vec3::new(0.0, 0.0, 1.0);
+        }
+        let wi = wi.unit_vector();
+
+        // cosine sampled, so the weight is f * pi
+        let sheen = self.sheen_brdf(wo, wi) * std::f32::consts::PI;
+        let scattered = Ray {
+            origin: rec.p,
+            direction: uvw.local(wi.x, wi.y, wi.z),
+        };
+        Some((self.diffuse + self.sheen * sheen, scattered))
+    }
+
+    fn eval(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        direction: Vec3,
+        _rng: &mut dyn RngCore,
+    ) -> Option<(Color, f32)> {
+        let uvw = rec.shading_frame();
+        let wo = uvw.to_local(-r_in.direction.unit_vector());
+        let wi = uvw.to_local(direction.unit_vector());
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Some((Color::new(0.0, 0.0, 0.0), 0.0));
+        }
+
+        let pdf = wi.z / std::f32::consts::PI;
+        let sheen = self.sheen_brdf(wo, wi) * std::f32::consts::PI;
+        Some(((self.diffuse + self.sheen * sheen) * pdf, pdf))
+    }
+}
+
+/// Phase function for participating media that scatters equally in all
+/// directions.
+#[derive(Clone, Copy)]
+pub struct Isotropic {
+    pub albedo: Color,
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        let scattered = Ray {
+            origin: rec.p,
+            direction: Vec3::random_unit_vector(rng),
+        };
+
+        Some((self.albedo, scattered))
+    }
+}
+
+/// Henyey-Greenstein phase function. `g` is the mean cosine between the
+/// incoming and scattered directions, so g > 0 favors forward scattering
+/// (fog, clouds) and g < 0 favors back scattering. g = 0 is isotropic.
+#[derive(Clone, Copy)]
+pub struct HenyeyGreenstein {
+    pub albedo: Color,
+    pub g: f32,
+}
+
+impl HenyeyGreenstein {
+    /// Sample the cosine of the angle between the incoming and scattered
+    /// directions.
+    pub fn sample_cos_theta(&self, xi: f32) -> f32 {
+        let g = self.g;
+        if f32::abs(g) < 1e-3 {
+            return 1.0 - 2.0 * xi;
+        }
+
+        let sqr_term = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+        ((1.0 + g * g - sqr_term * sqr_term) / (2.0 * g)).clamp(-1.0, 1.0)
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        let cos_theta = self.sample_cos_theta(rng.gen::<f32>());
+        let sin_theta = f32::sqrt(f32::max(0.0, 1.0 - cos_theta * cos_theta));
+        let phi = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
+
+        let uvw = Onb::build_from_w(r_in.direction);
+        let direction = uvw.local(
+            sin_theta * f32::cos(phi),
+            sin_theta * f32::sin(phi),
+            cos_theta,
+        );
+
+        let scattered = Ray {
+            origin: rec.p,
+            direction,
+        };
+
+        Some((self.albedo, scattered))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::rayhit::test_hit;
+
+    /// Average of the sampling weight for light arriving from `wo`, which is
+    /// the fraction of energy reflected.
+    fn directional_albedo(metal: &Metal, wo: Vec3) -> Color {
+        let n = 256;
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+            for j in 0..n {
+                let u1 = (i as f32 + 0.5) / n as f32;
+                let u2 = (j as f32 + 0.5) / n as f32;
+                if let Some((weight, _)) = metal.sample(wo, u1, u2) {
+                    sum += weight;
+                }
+            }
+        }
+
+        sum / (n * n) as f32
+    }
+
+    #[test]
+    fn test_metal_white_furnace() {
+        // With a Fresnel term of one, a single scattering microfacet model
+        // can lose energy to masking but must never gain it. Smooth surfaces
+        // should lose next to nothing away from grazing angles.
+        let white = |roughness_u, roughness_v| {
+            Metal::anisotropic(
+                Color::new(0.0, 0.0, 0.0),
+                Color::new(1e4, 1e4, 1e4),
+                roughness_u,
+                roughness_v,
+            )
+        };
+
+        for cos_theta in [1.0, 0.7, 0.3, 0.1] {
+            let sin_theta = f32::sqrt(1.0 - cos_theta * cos_theta);
+            let wo = Vec3::new(sin_theta, 0.0, cos_theta);
+
+            for (roughness_u, roughness_v) in
+                [(0.0, 0.0), (0.2, 0.2), (0.5, 0.5), (1.0, 1.0), (0.2, 0.8)]
+            {
+                let albedo = directional_albedo(&white(roughness_u, roughness_v), wo);
+                assert!(albedo.x <= 1.001, "gained energy: {:?}", albedo);
+                if f32::max(roughness_u, roughness_v) <= 0.2 && cos_theta >= 0.3 {
+                    assert!(albedo.x > 0.98, "lost energy: {:?}", albedo);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_metal_matches_reference_albedo() {
+        // Reference values from brute force quadrature of D * G2 / (4 cos)
+        // over the hemisphere. Rough GGX loses a lot of energy to masking.
+        let white = |roughness| {
+            Metal::anisotropic(
+                Color::new(0.0, 0.0, 0.0),
+                Color::new(1e4, 1e4, 1e4),
+                roughness,
+                roughness,
+            )
+        };
+
+        let wo = Vec3::new(0.0, 0.0, 1.0);
+        assert!(f32::abs(directional_albedo(&white(0.5), wo).x - 0.9158) < 0.005);
+        assert!(f32::abs(directional_albedo(&white(1.0), wo).x - 0.3069) < 0.005);
+
+        let wo = Vec3::new(f32::sqrt(1.0 - 0.09), 0.0, 0.3);
+        assert!(f32::abs(directional_albedo(&white(0.5), wo).x - 0.8379) < 0.005);
+        assert!(f32::abs(directional_albedo(&white(1.0), wo).x - 0.5601) < 0.005);
+    }
+
+    #[test]
+    fn test_metal_presets_reflectance() {
+        // head on reflectance of gold is yellow, aluminum is a bright grey
+        let wo = Vec3::new(0.0, 0.0, 1.0);
+        let gold = directional_albedo(&Metal::gold(0.0), wo);
+        assert!(gold.x > 0.9 && gold.y > 0.7 && gold.z < 0.5);
+
+        let aluminum = directional_albedo(&Metal::aluminum(0.0), wo);
+        assert!(aluminum.x > 0.9 && aluminum.z > 0.9);
+    }
+
+    #[test]
+    fn test_smooth_rough_dielectric_matches_dialetric() {
+        let mut rng = rand::thread_rng();
+        let mat = Rc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        });
+        let dialetric = Dialetric::new(1.5);
+        let rough = RoughDielectric::new(1.5, 0.0);
+
+        // Entering and leaving the glass, at various angles. From inside at
+        // 60 degrees everything is reflected.
+        for (front_face, cos_theta) in [
+            (true, 1.0),
+            (true, 0.5),
+            (true, 0.25),
+            (false, 0.9),
+            (false, 0.5),
+        ] {
+            let sin_theta = f32::sqrt(1.0 - cos_theta * cos_theta);
+            let (rec, r_in) = test_hit(mat.clone(), cos_theta, front_face);
+
+            let n = 20_000;
+            let mut reflected = [0, 0];
+            for _ in 0..n {
+                for (i, material) in [&dialetric as &dyn Material, &rough].iter().enumerate() {
+                    let (attenuation, scattered) = material.scatter(&r_in, &rec, &mut rng).unwrap();
+                    assert_eq!(attenuation, Color::new(1.0, 1.0, 1.0));
+
+                    let direction = scattered.direction.unit_vector();
+                    if direction.y > 0.0 {
+                        reflected[i] += 1;
+                        let expected = Vec3::new(sin_theta, cos_theta, 0.0);
+                        assert!((direction - expected).length() < 1e-4);
+                    } else {
+                        let expected = r_in
+                            .direction
+                            .refract(rec.normal, if front_face { 1.0 / 1.5 } else { 1.5 });
+                        assert!((direction - expected.unit_vector()).length() < 1e-4);
+                    }
+                }
+            }
+
+            // Dialetric uses Schlick's approximation rather than the exact
+            // Fresnel term, so allow for a small difference.
+            let difference = (reflected[0] as f32 - reflected[1] as f32) / n as f32;
+            assert!(f32::abs(difference) < 0.03, "{} {:?}", cos_theta, reflected);
+        }
+    }
+
+    #[test]
+    fn test_single_scattering_albedo() {
+        assert!(single_scattering_albedo(0.0) < 1e-4);
+        assert!(single_scattering_albedo(1.0) > 0.9999);
+
+        // it takes a lot of very bright scattering events to look bright
+        let mut last = 0.0;
+        for a in [0.1, 0.3, 0.5, 0.7, 0.9] {
+            let single = single_scattering_albedo(a);
+            assert!(single > a && single > last);
+            last = single;
+        }
+    }
+
+    #[test]
+    fn test_velvet_sheen() {
+        // The sheen lobe alone never reflects more than comes in, and it's
+        // brightest at grazing angles.
+        for roughness in [0.2, 0.5, 1.0] {
+            let velvet = Velvet::new(
+                Color::new(0.0, 0.0, 0.0),
+                Color::new(1.0, 1.0, 1.0),
+                roughness,
+            );
+            let albedo = |cos_theta: f32| {
+                let wo = Vec3::new(f32::sqrt(1.0 - cos_theta * cos_theta), 0.0, cos_theta);
+                let n = 256;
+                let mut sum = 0.0;
+                for i in 0..n {
+                    for j in 0..n {
+                        // cosine weighted directions over the hemisphere
+                        let r = f32::sqrt((i as f32 + 0.5) / n as f32);
+                        let phi = 2.0 * std::f32::consts::PI * (j as f32 + 0.5) / n as f32;
+                        let wi = Vec3::new(r * phi.cos(), r * phi.sin(), f32::sqrt(1.0 - r * r));
+                        sum += velvet.sheen_brdf(wo, wi) * std::f32::consts::PI;
+                    }
+                }
+                sum / (n * n) as f32
+            };
+
+            let head_on = albedo(1.0);
+            let grazing = albedo(0.1);
+            assert!(grazing > head_on, "{} {} {}", roughness, head_on, grazing);
+            assert!(grazing < 1.0, "{} {}", roughness, grazing);
+        }
+    }
+
+    #[test]
+    fn test_velvet_furnace() {
+        // Through scatter, a white diffuse base reflects everything, and
+        // adding sheen brightens grazing light more than head on light.
+        let mut rng = StdRng::seed_from_u64(39);
+        let mut albedo = |velvet: &Velvet, cos_theta: f32| {
+            let (rec, r_in) = test_hit(Rc::new(*velvet), cos_theta, true);
+            let n = 20_000;
+            let mut sum = Color::new(0.0, 0.0, 0.0);
+            for _ in 0..n {
+                let (attenuation, scattered) = velvet.scatter(&r_in, &rec, &mut rng).unwrap();
+                assert!(scattered.direction.y > 0.0);
+                sum += attenuation;
+            }
+            sum / n as f32
+        };
+
+        let white = Velvet::new(Color::new(1.0, 1.0, 1.0), Color::new(0.0, 0.0, 0.0), 0.5);
+        for cos_theta in [1.0, 0.5, 0.1] {
+            let a = albedo(&white, cos_theta);
+            assert!((a - Color::new(1.0, 1.0, 1.0)).length() < 1e-4, "{:?}", a);
+        }
+
+        for roughness in [0.2, 0.5, 1.0] {
+            let sheen = Velvet::new(
+                Color::new(0.0, 0.0, 0.0),
+                Color::new(1.0, 0.5, 0.25),
+                roughness,
+            );
+            let head_on = albedo(&sheen, 1.0);
+            let grazing = albedo(&sheen, 0.1);
+            assert!(
+                grazing.x > head_on.x,
+                "{} {:?} {:?}",
+                roughness,
+                head_on,
+                grazing
+            );
+            assert!(grazing.x < 1.0, "{} {:?}", roughness, grazing);
+            // tinted by the sheen color
+            assert!(f32::abs(grazing.y - grazing.x * 0.5) < 1e-4);
+            assert!(f32::abs(grazing.z - grazing.x * 0.25) < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_eval_matches_scatter() {
+        let mut rng = StdRng::seed_from_u64(40);
+        let mat = Rc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        });
+        let (rec, r_in) = test_hit(mat, 0.8, true);
+
+        let materials: [&dyn Material; 5] = [
+            &Lambertian {
+                albedo: Color::new(0.5, 0.5, 0.5),
+            },
+            &Metal::gold(0.4),
+            &Metal::anisotropic(
+                Color::new(1.0, 1.0, 1.0),
+                Color::new(3.0, 3.0, 3.0),
+                0.2,
+                0.6,
+            ),
+            &Velvet::new(Color::new(0.2, 0.2, 0.2), Color::new(1.0, 1.0, 1.0), 0.5),
+            &RoughDielectric::new(1.5, 0.4),
+        ];
+        for material in materials {
+            // each scattered ray's weight is f * cos / pdf
+            for _ in 0..1000 {
+                if let Some((attenuation, scattered)) = material.scatter(&r_in, &rec, &mut rng) {
+                    let (f, pdf) = material
+                        .eval(&r_in, &rec, scattered.direction, &mut rng)
+                        .unwrap();
+                    assert!(pdf > 0.0);
+                    assert!((f / pdf - attenuation).length() < 1e-3 * attenuation.length());
+                }
+            }
+
+            // and the pdf covers the hemisphere once, over a grid of
+            // directions equally spaced in z and angle around it
+            let n = 600;
+            let mut total = 0.0;
+            for i in 0..n {
+                for j in 0..n {
+                    let z = 1.0 - 2.0 * (i as f32 + 0.5) / n as f32;
+                    let phi = 2.0 * std::f32::consts::PI * (j as f32 + 0.5) / n as f32;
+                    let r = f32::sqrt(1.0 - z * z);
+                    let direction = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                    total += material.eval(&r_in, &rec, direction, &mut rng).unwrap().1;
+                }
+            }
+            let integral = total * 4.0 * std::f32::consts::PI / (n * n) as f32;
+            assert!(integral <= 1.02, "{}", integral);
+        }
+
+        assert!(Metal::gold(0.0)
+            .eval(&r_in, &rec, r_in.direction, &mut rng)
+            .is_none());
+    }
+
+    #[test]
+    fn test_anisotropic_follows_tangent() {
+        let mut rng = rand::thread_rng();
+        // smooth along u and rough across it, so reflections spread out
+        // across dpdu whichever way the surface's u runs
+        let metal = Rc::new(Metal::anisotropic(
+            Color::new(1.0, 1.0, 1.0),
+            Color::new(3.0, 3.0, 3.0),
+            0.1,
+            0.7,
+        ));
+
+        for dpdu in [Vec3::new(0.0, 0.0, 2.0), Vec3::new(1.0, 0.0, 0.0)] {
+            let (mut rec, r_in) = test_hit(metal.clone(), 1.0, true);
+            rec.dpdu = dpdu;
+            rec.dpdv = Vec3::new(0.0, 1.0, 0.0).cross(dpdu);
+
+            let (along, across) = (dpdu.unit_vector(), rec.dpdv.unit_vector());
+            let (mut spread_along, mut spread_across) = (0.0, 0.0);
+            for _ in 0..2000 {
+                if let Some((_, scattered)) = metal.scatter(&r_in, &rec, &mut rng) {
+                    let d = scattered.direction.unit_vector();
+                    spread_along += f32::abs(d * along);
+                    spread_across += f32::abs(d * across);
+                }
+            }
+            assert!(spread_across > 3.0 * spread_along);
+        }
+    }
+
+    #[test]
+    fn test_henyey_greenstein_mean_cosine() {
+        // the mean cosine of the HG distribution is g
+        for g in [-0.7, 0.0, 0.3, 0.9] {
+            let phase = HenyeyGreenstein {
+                albedo: Color::new(1.0, 1.0, 1.0),
+                g,
+            };
+
+            let n = 100_000;
+            let mut sum = 0.0;
+            for i in 0..n {
+                let xi = (i as f32 + 0.5) / n as f32;
+                sum += phase.sample_cos_theta(xi);
+            }
+
+            assert!(f32::abs(sum / n as f32 - g) < 1e-2);
+        }
+    }
+}
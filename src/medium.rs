@@ -0,0 +1,351 @@
+use rand::{Rng, RngCore};
+
+use crate::spectrum::{Dispersion, SampledWavelengths};
+use crate::vec3::Color;
+
+/// What's inside a closed dielectric surface.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Interior {
+    pub index_of_refraction: f32,
+    /// Absorption coefficient per unit distance traveled inside, per
+    /// channel.
+    pub absorption: Color,
+    /// Scattering coefficient per unit distance, per channel. Zero for
+    /// glass, light scatters around inside translucent materials.
+    pub scattering: Color,
+    /// Where dielectrics overlap (liquid in a glass is modelled slightly
+    /// larger than the inside of the glass) the one with the higher priority
+    /// is the one that's really there. Surfaces of the lower priority one
+    /// inside it are ignored.
+    pub priority: u32,
+    /// How the index of refraction varies with wavelength, in spectral mode.
+    pub dispersion: Option<Dispersion>,
+}
+
+impl Interior {
+    /// Index of refraction at the hero wavelength, if we have one.
+    pub fn ior(&self, wavelengths: Option<&SampledWavelengths>) -> f32 {
+        match (self.dispersion, wavelengths) {
+            (Some(dispersion), Some(wavelengths)) => dispersion.ior(wavelengths.hero()),
+            _ => self.index_of_refraction,
+        }
+    }
+}
+
+/// The dielectrics a path is currently inside, outermost first. Outside of
+/// everything is air.
+///
+/// Media are told apart by their `Interior`, not by the object or material
+/// they came from, so a path can leave through a different surface of the
+/// same glass than it entered by, like the other operand of a CSG shape.
+#[derive(Clone, Default)]
+pub struct MediumStack {
+    entries: Vec<Interior>,
+}
+
+impl MediumStack {
+    pub fn new() -> MediumStack {
+        MediumStack {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The medium the path is in, ignoring `skip`. With overlapping media
+    /// that's the highest priority one, and the most recently entered among
+    /// equals.
+    fn current(&self, skip: Option<&Interior>) -> Option<&Interior> {
+        let mut skipped = false;
+        let mut current: Option<&Interior> = None;
+
+        for interior in self.entries.iter().rev() {
+            if !skipped && Some(interior) == skip {
+                skipped = true;
+                continue;
+            }
+
+            if current.is_none_or(|c| interior.priority > c.priority) {
+                current = Some(interior);
+            }
+        }
+
+        current
+    }
+
+    /// Fraction of light left after traveling `distance` through the current
+    /// medium, by the Beer-Lambert law. In spectral mode this is per
+    /// wavelength rather than per RGB channel.
+    pub fn transmittance(&self, distance: f32, wavelengths: Option<&SampledWavelengths>) -> Color {
+        match self.current(None) {
+            Some(interior) => {
+                let absorption = match wavelengths {
+                    Some(wavelengths) => wavelengths.rgb_values(interior.absorption),
+                    None => interior.absorption,
+                };
+                Color::new(
+                    f32::exp(-absorption.x * distance),
+                    f32::exp(-absorption.y * distance),
+                    f32::exp(-absorption.z * distance),
+                )
+            }
+            None => Color::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Sample how far the path goes through the current medium before it
+    /// scatters, if it does before reaching a surface `distance` away.
+    /// Returns the distance to the scattering event, if there is one, and
+    /// the path's weight for getting there.
+    ///
+    /// Distances are sampled with the extinction of a channel picked at
+    /// random, weighted by the average over channels of the chances of
+    /// sampling what we did, so colored media don't get noisy channels.
+    pub fn sample_distance(
+        &self,
+        distance: f32,
+        wavelengths: Option<&SampledWavelengths>,
+        rng: &mut dyn RngCore,
+    ) -> (Option<f32>, Color) {
+        let interior = match self.current(None) {
+            Some(interior) if !interior.scattering.near_zero() => interior,
+            _ => return (None, self.transmittance(distance, wavelengths)),
+        };
+
+        let (absorption, scattering) = match wavelengths {
+            Some(wavelengths) => (
+                wavelengths.rgb_values(interior.absorption),
+                wavelengths.rgb_values(interior.scattering),
+            ),
+            None => (interior.absorption, interior.scattering),
+        };
+        let extinction = absorption + scattering;
+        let transmittance = |t: f32| {
+            Color::new(
+                f32::exp(-extinction.x * t),
+                f32::exp(-extinction.y * t),
+                f32::exp(-extinction.z * t),
+            )
+        };
+
+        let sigma = [extinction.x, extinction.y, extinction.z][rng.gen_range(0..3)];
+        let t = -f32::ln(1.0 - rng.gen::<f32>()) / sigma;
+
+        if t < distance {
+            let tr = transmittance(t);
+            let pdf = (extinction.x * tr.x + extinction.y * tr.y + extinction.z * tr.z) / 3.0;
+            let weight = Color::new(
+                scattering.x * tr.x,
+                scattering.y * tr.y,
+                scattering.z * tr.z,
+            ) / pdf;
+            (Some(t), weight)
+        } else {
+            let tr = transmittance(distance);
+            let pdf = (tr.x + tr.y + tr.z) / 3.0;
+            (None, tr / pdf)
+        }
+    }
+
+    /// Relative index of refraction (far side over near side) for a path
+    /// crossing the surface of `interior` into or out of it. Returns None if
+    /// the surface is hidden inside a higher priority medium and the path
+    /// should carry straight on.
+    pub fn interface(
+        &self,
+        interior: &Interior,
+        entering: bool,
+        wavelengths: Option<&SampledWavelengths>,
+    ) -> Option<f32> {
+        let other = if entering {
+            self.current(None)
+        } else {
+            self.current(Some(interior))
+        };
+
+        if other.is_some_and(|other| other.priority > interior.priority) {
+            return None;
+        }
+        let other_ior = other.map_or(1.0, |other| other.ior(wavelengths));
+
+        if entering {
+            Some(interior.ior(wavelengths) / other_ior)
+        } else {
+            Some(other_ior / interior.ior(wavelengths))
+        }
+    }
+
+    /// Update the stack for a path passing through the surface of
+    /// `interior`.
+    pub fn cross(&mut self, interior: Interior, entering: bool) {
+        if entering {
+            self.entries.push(interior);
+        } else if let Some(i) = self.entries.iter().rposition(|e| *e == interior) {
+            self.entries.remove(i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::material::{Dialetric, Material};
+
+    fn interior(mat: &Rc<dyn Material>) -> Interior {
+        mat.interior().unwrap()
+    }
+
+    #[test]
+    fn test_transmittance() {
+        let glass: Rc<dyn Material> =
+            Rc::new(Dialetric::colored(1.5, Color::new(0.5, 1.0, 0.25), 2.0));
+        let mut media = MediumStack::new();
+        assert_eq!(media.transmittance(2.0, None), Color::new(1.0, 1.0, 1.0));
+
+        media.cross(interior(&glass), true);
+        let t = media.transmittance(2.0, None);
+        assert!((t - Color::new(0.5, 1.0, 0.25)).length() < 1e-5);
+        let t = media.transmittance(4.0, None);
+        assert!((t - Color::new(0.25, 1.0, 0.0625)).length() < 1e-5);
+
+        media.cross(interior(&glass), false);
+        assert!(media.is_empty());
+    }
+
+    #[test]
+    fn test_sample_distance() {
+        let mut rng = rand::thread_rng();
+        // without scattering it's just the transmittance
+        let glass: Rc<dyn Material> =
+            Rc::new(Dialetric::colored(1.5, Color::new(0.5, 1.0, 0.25), 2.0));
+        let mut media = MediumStack::new();
+        media.cross(interior(&glass), true);
+        let (t, weight) = media.sample_distance(2.0, None, &mut rng);
+        assert!(t.is_none());
+        assert!((weight - Color::new(0.5, 1.0, 0.25)).length() < 1e-5);
+
+        // With scattering the weights average out to the chance of getting
+        // through, and of scattering somewhere along the way.
+        let mut media = MediumStack::new();
+        let mut cloudy = interior(&glass);
+        cloudy.absorption = Color::new(0.5, 0.0, 1.0);
+        cloudy.scattering = Color::new(0.5, 2.0, 1.0);
+        media.entries.push(cloudy);
+
+        let n = 200_000;
+        let mut through = Color::new(0.0, 0.0, 0.0);
+        let mut scattered = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            match media.sample_distance(1.0, None, &mut rng) {
+                (None, weight) => through += weight,
+                (Some(t), weight) => {
+                    assert!(t < 1.0);
+                    scattered += weight;
+                }
+            }
+        }
+        through = through / n as f32;
+        scattered = scattered / n as f32;
+
+        let extinction = [1.0, 2.0, 2.0];
+        let albedo = [0.5, 1.0, 0.5];
+        let (through, scattered) = (
+            [through.x, through.y, through.z],
+            [scattered.x, scattered.y, scattered.z],
+        );
+        for i in 0..3 {
+            let tr = f32::exp(-extinction[i]);
+            assert!(f32::abs(through[i] - tr) < 0.01, "{:?}", through);
+            assert!(
+                f32::abs(scattered[i] - albedo[i] * (1.0 - tr)) < 0.01,
+                "{:?}",
+                scattered
+            );
+        }
+    }
+
+    #[test]
+    fn test_dispersive_interface() {
+        let glass: Rc<dyn Material> = Rc::new(Dialetric::dispersive(Dispersion::bk7()));
+        let media = MediumStack::new();
+
+        let eta = media.interface(&interior(&glass), true, None).unwrap();
+        assert!(f32::abs(eta - 1.5168) < 1e-3);
+
+        let blue = SampledWavelengths {
+            lambda: [450.0, 550.0, 650.0],
+            secondary_terminated: false,
+        };
+        let red = SampledWavelengths {
+            lambda: [650.0, 450.0, 550.0],
+            secondary_terminated: false,
+        };
+        let eta_blue = media
+            .interface(&interior(&glass), true, Some(&blue))
+            .unwrap();
+        let eta_red = media
+            .interface(&interior(&glass), true, Some(&red))
+            .unwrap();
+        assert!(eta_blue > eta_red);
+    }
+
+    #[test]
+    fn test_leaving_through_another_surface() {
+        // the two sides of a lens, each with its own copy of the glass
+        let front: Rc<dyn Material> = Rc::new(Dialetric::new(1.5));
+        let back: Rc<dyn Material> = Rc::new(Dialetric::new(1.5));
+        let mut media = MediumStack::new();
+
+        media.cross(interior(&front), true);
+        let eta = media.interface(&interior(&back), false, None).unwrap();
+        assert_eq!(eta, 1.0 / 1.5);
+        media.cross(interior(&back), false);
+        assert!(media.is_empty());
+    }
+
+    #[test]
+    fn test_nested_dielectrics() {
+        // water (1.33) in a glass (1.5), the glass taking priority where they
+        // overlap
+        let mut glass = Dialetric::new(1.5);
+        glass.priority = 2;
+        let glass: Rc<dyn Material> = Rc::new(glass);
+        let mut water = Dialetric::new(1.33);
+        water.priority = 1;
+        let water: Rc<dyn Material> = Rc::new(water);
+
+        let mut media = MediumStack::new();
+
+        // air into glass
+        let eta = media.interface(&interior(&glass), true, None).unwrap();
+        assert_eq!(eta, 1.5);
+        media.cross(interior(&glass), true);
+
+        // the water surface inside the glass isn't really there
+        assert!(media.interface(&interior(&water), true, None).is_none());
+        media.cross(interior(&water), true);
+
+        // glass into water
+        let eta = media.interface(&interior(&glass), false, None).unwrap();
+        assert_eq!(eta, 1.33 / 1.5);
+        media.cross(interior(&glass), false);
+
+        // water back into glass on the far side
+        let eta = media.interface(&interior(&glass), true, None).unwrap();
+        assert_eq!(eta, 1.5 / 1.33);
+        media.cross(interior(&glass), true);
+
+        // leaving the water inside the glass is ignored again, then out
+        // into the air
+        assert!(media.interface(&interior(&water), false, None).is_none());
+        media.cross(interior(&water), false);
+        let eta = media.interface(&interior(&glass), false, None).unwrap();
+        assert_eq!(eta, 1.0 / 1.5);
+        media.cross(interior(&glass), false);
+        assert!(media.is_empty());
+    }
+}
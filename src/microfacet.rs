@@ -0,0 +1,161 @@
+use std::f32::consts::PI;
+
+use crate::vec3::{Color, Vec3};
+
+/// Trowbridge-Reitz (GGX) microfacet distribution with the Smith
+/// shadowing-masking function. Directions are in the local shading frame
+/// with the normal along +z, and alpha_x/alpha_y control the roughness along
+/// the tangent and bitangent.
+#[derive(Copy, Clone, Debug)]
+pub struct Ggx {
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+}
+
+impl Ggx {
+    /// Roughness is perceptually linear, alpha is its square. Alpha is kept
+    /// away from zero since the distribution becomes a delta there.
+    pub fn from_roughness(roughness_x: f32, roughness_y: f32) -> Ggx {
+        Ggx {
+            alpha_x: f32::max(roughness_x * roughness_x, 1e-4),
+            alpha_y: f32::max(roughness_y * roughness_y, 1e-4),
+        }
+    }
+
+    /// Below this the surface is treated as perfectly smooth.
+    pub fn is_smooth(&self) -> bool {
+        f32::max(self.alpha_x, self.alpha_y) < 1e-3
+    }
+
+    /// Density of microfacet normals, per unit projected area.
+    pub fn d(&self, wm: Vec3) -> f32 {
+        let x = wm.x / self.alpha_x;
+        let y = wm.y / self.alpha_y;
+        let e = x * x + y * y + wm.z * wm.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    fn lambda(&self, w: Vec3) -> f32 {
+        if w.z == 0.0 {
+            return f32::INFINITY;
+        }
+
+        let a2 = (self.alpha_x * self.alpha_x * w.x * w.x
+            + self.alpha_y * self.alpha_y * w.y * w.y)
+            / (w.z * w.z);
+        (f32::sqrt(1.0 + a2) - 1.0) / 2.0
+    }
+
+    /// Masking of a single direction.
+    pub fn g1(&self, w: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Height correlated masking and shadowing of a pair of directions.
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Sample a microfacet normal from the distribution of normals visible
+    /// from `wo`, following Heitz 2018. u1 and u2 are uniform in [0, 1).
+    pub fn sample_visible_normal(&self, wo: Vec3, u1: f32, u2: f32) -> Vec3 {
+        // flip wo into the upper hemisphere, the distribution is symmetric
+        let wo = if wo.z < 0.0 { -wo } else { wo };
+
+        // stretch to the hemisphere configuration
+        let vh = Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).unit_vector();
+
+        let len_sq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len_sq > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / f32::sqrt(len_sq)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+
+        // uniform point on the projected disk, warped toward the visible half
+        let r = f32::sqrt(u1);
+        let phi = 2.0 * PI * u2;
+        let p1 = r * f32::cos(phi);
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * f32::sqrt(f32::max(0.0, 1.0 - p1 * p1)) + s * r * f32::sin(phi);
+
+        let nh = t1 * p1 + t2 * p2 + vh * f32::sqrt(f32::max(0.0, 1.0 - p1 * p1 - p2 * p2));
+
+        // and unstretch
+        Vec3::new(
+            self.alpha_x * nh.x,
+            self.alpha_y * nh.y,
+            f32::max(1e-6, nh.z),
+        )
+        .unit_vector()
+    }
+}
+
+/// Exact Fresnel reflectance of a conductor with complex index of
+/// refraction eta + ik, for a single channel.
+pub fn fresnel_conductor(cos_theta_i: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_theta_i * cos_theta_i;
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = f32::sqrt(f32::max(0.0, t0 * t0 + 4.0 * eta2 * k2));
+    let t1 = a2_plus_b2 + cos2;
+    let a = f32::sqrt(f32::max(0.0, 0.5 * (a2_plus_b2 + t0)));
+    let t2 = 2.0 * cos_theta_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rp + rs)
+}
+
+/// Fresnel reflectance of a conductor for each color channel.
+pub fn fresnel_conductor_rgb(cos_theta_i: f32, eta: Color, k: Color) -> Color {
+    Color::new(
+        fresnel_conductor(cos_theta_i, eta.x, k.x),
+        fresnel_conductor(cos_theta_i, eta.y, k.y),
+        fresnel_conductor(cos_theta_i, eta.z, k.z),
+    )
+}
+
+/// Reflect `w` about `n`, with both pointing away from the surface.
+pub fn reflect(w: Vec3, n: Vec3) -> Vec3 {
+    n * (2.0 * (w * n)) - w
+}
+
+/// Exact Fresnel reflectance of a dielectric interface for unpolarized
+/// light. `eta` is the ratio of the index of refraction on the far side of
+/// the interface over the near side.
+pub fn fresnel_dielectric(cos_theta_i: f32, eta: f32) -> f32 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    // total internal reflection
+    if sin2_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = f32::sqrt(1.0 - sin2_theta_t);
+
+    let r_parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
+}
+
+/// Refract `w` through a surface with normal `n`, both pointing away from
+/// the surface on the same side. `eta` is as for `fresnel_dielectric`.
+/// Returns None on total internal reflection.
+pub fn refract(w: Vec3, n: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_theta_i = w * n;
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = f32::sqrt(1.0 - sin2_theta_t);
+
+    Some(-w / eta + n * (cos_theta_i / eta - cos_theta_t))
+}
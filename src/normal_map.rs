@@ -0,0 +1,266 @@
+use std::rc::Rc;
+
+use rand::RngCore;
+
+use crate::material::Material;
+use crate::medium::Interior;
+use crate::onb::Onb;
+use crate::rayhit::{HitRecord, Ray};
+use crate::texture::Texture;
+use crate::vec3::{Color, Vec3};
+
+/// How the shading normal is changed from the geometric one.
+pub enum Perturbation {
+    /// Tangent space normals, x along dp/du, y along dp/dv and z out of the
+    /// surface, with each component mapped from [0, 1] in the texture to
+    /// [-1, 1].
+    NormalMap(Rc<dyn Texture>),
+    /// The surface moved out along the normal by the height, in scene units
+    /// after multiplying by `scale`.
+    BumpMap { height: Rc<dyn Texture>, scale: f32 },
+}
+
+/// Fine surface detail on top of another material, by changing the normal
+/// it's shaded with.
+///
+/// Shading normals that differ from the geometry can let light through
+/// where it shouldn't: reflections that go below the real surface, or the
+/// back of the shading normal facing the camera. The normal falls back to
+/// the geometric one when it faces away, and scattered rays that are on
+/// different sides of the two normals are dropped.
+pub struct NormalMapped {
+    pub base: Rc<dyn Material>,
+    pub perturbation: Perturbation,
+}
+
+/// Step in u and v for the finite differences of bump maps.
+const BUMP_DELTA: f32 = 5e-4;
+
+impl NormalMapped {
+    pub fn normal_map(base: Rc<dyn Material>, map: impl Texture + 'static) -> NormalMapped {
+        NormalMapped {
+            base,
+            perturbation: Perturbation::NormalMap(Rc::new(map)),
+        }
+    }
+
+    pub fn bump_map(
+        base: Rc<dyn Material>,
+        height: impl Texture + 'static,
+        scale: f32,
+    ) -> NormalMapped {
+        NormalMapped {
+            base,
+            perturbation: Perturbation::BumpMap {
+                height: Rc::new(height),
+                scale,
+            },
+        }
+    }
+
+    /// The perturbed outward normal.
+    fn outward_normal(&self, rec: &HitRecord) -> Vec3 {
+        let n = if rec.front_face {
+            rec.normal
+        } else {
+            -rec.normal
+        };
+
+        // a unit tangent frame, made up if the surface doesn't have one
+        let (dpdu, dpdv) = if rec.dpdu.near_zero() || rec.dpdv.near_zero() {
+            let uvw = Onb::build_from_w(n);
+            (uvw.u, uvw.v)
+        } else {
+            (rec.dpdu, rec.dpdv)
+        };
+
+        match &self.perturbation {
+            Perturbation::NormalMap(map) => {
+                let t = (dpdu - n * (dpdu * n)).unit_vector();
+                let mut b = n.cross(t);
+                if b * dpdv < 0.0 {
+                    b = -b;
+                }
+
+                let c = map.value(rec.u, rec.v, rec.p) * 2.0 - Vec3::new(1.0, 1.0, 1.0);
+                (t * c.x + b * c.y + n * c.z).unit_vector()
+            }
+            Perturbation::BumpMap { height, scale } => {
+                let h = |u, v, p| height.value(u, v, p).x * scale;
+                let h0 = h(rec.u, rec.v, rec.p);
+                let dhdu =
+                    (h(rec.u + BUMP_DELTA, rec.v, rec.p + dpdu * BUMP_DELTA) - h0) / BUMP_DELTA;
+                let dhdv =
+                    (h(rec.u, rec.v + BUMP_DELTA, rec.p + dpdv * BUMP_DELTA) - h0) / BUMP_DELTA;
+
+                let bumped = (dpdu + n * dhdu).cross(dpdv + n * dhdv).unit_vector();
+                if bumped * n < 0.0 {
+                    -bumped
+                } else {
+                    bumped
+                }
+            }
+        }
+    }
+
+    /// The hit with the shading normal in place of the geometric one.
+    fn shading(&self, r_in: &Ray, rec: &HitRecord) -> HitRecord {
+        let outward = self.outward_normal(rec);
+        let normal = if rec.front_face { outward } else { -outward };
+
+        let mut shading = rec.clone();
+        if normal * r_in.direction < 0.0 {
+            shading.normal = normal;
+        }
+        shading
+    }
+
+    /// Drop scattered rays that would leak through the geometry.
+    fn check(
+        rec: &HitRecord,
+        shading: &HitRecord,
+        scattered: Option<(Color, Ray)>,
+    ) -> Option<(Color, Ray)> {
+        let (attenuation, ray) = scattered?;
+        let geometric = ray.direction * rec.normal;
+        let shaded = ray.direction * shading.normal;
+        if geometric * shaded <= 0.0 {
+            return None;
+        }
+
+        Some((attenuation, ray))
+    }
+}
+
+impl Material for NormalMapped {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        let shading = self.shading(r_in, rec);
+        NormalMapped::check(rec, &shading, self.base.scatter(r_in, &shading, rng))
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.base.emitted(rec)
+    }
+
+    fn interior(&self) -> Option<Interior> {
+        self.base.interior()
+    }
+
+    fn scatter_interface(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        eta: f32,
+        rng: &mut dyn RngCore,
+    ) -> Option<(Color, Ray)> {
+        let shading = self.shading(r_in, rec);
+        NormalMapped::check(
+            rec,
+            &shading,
+            self.base.scatter_interface(r_in, &shading, eta, rng),
+        )
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.base.is_emissive()
+    }
+
+    fn eval(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        direction: Vec3,
+        rng: &mut dyn RngCore,
+    ) -> Option<(Color, f32)> {
+        let shading = self.shading(r_in, rec);
+        let (f, pdf) = self.base.eval(r_in, &shading, direction, rng)?;
+        // the light would leak through the geometry, as in `check`
+        if (direction * rec.normal) * (direction * shading.normal) <= 0.0 {
+            return Some((Color::new(0.0, 0.0, 0.0), pdf));
+        }
+
+        Some((f, pdf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::rayhit::test_hit;
+    use crate::vec3::Point3;
+
+    fn hit(mat: Rc<dyn Material>) -> HitRecord {
+        let (mut rec, _) = test_hit(mat, 1.0, true);
+        rec.dpdu = Vec3::new(2.0, 0.0, 0.0);
+        rec.dpdv = Vec3::new(0.0, 0.0, -2.0);
+        rec
+    }
+
+    #[test]
+    fn test_normal_map() {
+        let white: Rc<dyn Material> = Rc::new(Lambertian {
+            albedo: Color::new(1.0, 1.0, 1.0),
+        });
+        let r_in = Ray {
+            origin: Point3::new(0.0, 1.0, 0.0),
+            direction: Vec3::new(0.0, -1.0, 0.0),
+        };
+
+        // flat, leaning toward u, leaning toward v, and facing into the
+        // surface which falls back to the geometric normal
+        for (c, expected) in [
+            (Color::new(0.5, 0.5, 1.0), Vec3::new(0.0, 1.0, 0.0)),
+            (
+                Color::new(1.0, 0.5, 1.0),
+                Vec3::new(1.0, 1.0, 0.0).unit_vector(),
+            ),
+            (
+                Color::new(0.5, 1.0, 1.0),
+                Vec3::new(0.0, 1.0, -1.0).unit_vector(),
+            ),
+            (Color::new(0.5, 0.5, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+        ] {
+            let mat = NormalMapped::normal_map(white.clone(), c);
+            let shading = mat.shading(&r_in, &hit(white.clone()));
+            assert!((shading.normal - expected).length() < 1e-5, "{:?}", c);
+        }
+    }
+
+    #[test]
+    fn test_bump_map() {
+        let mut rng = rand::thread_rng();
+        struct Ramp;
+        impl Texture for Ramp {
+            fn value(&self, u: f32, _v: f32, _p: Point3) -> Color {
+                Color::new(u, u, u)
+            }
+        }
+
+        let white: Rc<dyn Material> = Rc::new(Lambertian {
+            albedo: Color::new(1.0, 1.0, 1.0),
+        });
+        let r_in = Ray {
+            origin: Point3::new(0.0, 1.0, 0.0),
+            direction: Vec3::new(0.0, -1.0, 0.0),
+        };
+
+        // rising by 2 over the 2 units of u is a 45 degree slope facing -x
+        let mat = NormalMapped::bump_map(white.clone(), Ramp, 2.0);
+        let shading = mat.shading(&r_in, &hit(white.clone()));
+        let expected = Vec3::new(-1.0, 1.0, 0.0).unit_vector();
+        assert!(
+            (shading.normal - expected).length() < 1e-2,
+            "{:?}",
+            shading.normal
+        );
+
+        // no light gets below the surface
+        let hit = hit(white);
+        for _ in 0..1000 {
+            if let Some((_, scattered)) = mat.scatter(&r_in, &hit, &mut rng) {
+                assert!(scattered.direction.y > 0.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::vec3::Vec3;
+
+/// Orthonormal basis built around a single direction. Used to turn
+/// directions sampled around the z axis into world space.
+#[derive(Copy, Clone, Debug)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn build_from_w(n: Vec3) -> Onb {
+        let w = n.unit_vector();
+        // pick whichever axis is least aligned with w so the cross product
+        // doesn't degenerate.
+        let a = if f32::abs(w.x) > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = w.cross(a).unit_vector();
+        let u = w.cross(v);
+
+        Onb { u, v, w }
+    }
+
+    /// A basis around `n` with u along `tangent`, so that whatever is
+    /// measured along u and v follows the surface. The tangent only needs to
+    /// be roughly perpendicular to `n`. Falls back to `build_from_w` when
+    /// there's no tangent to go by.
+    pub fn build_from_w_and_u(n: Vec3, tangent: Vec3) -> Onb {
+        let w = n.unit_vector();
+        let t = tangent - w * (tangent * w);
+        if t.length_squared() < 1e-12 {
+            return Onb::build_from_w(w);
+        }
+
+        let u = t.unit_vector();
+        let v = w.cross(u);
+        Onb { u, v, w }
+    }
+
+    /// Local (u, v, w) coordinates to world space.
+    pub fn local(&self, a: f32, b: f32, c: f32) -> Vec3 {
+        self.u * a + self.v * b + self.w * c
+    }
+
+    /// World space vector to local (u, v, w) coordinates.
+    pub fn to_local(self, a: Vec3) -> Vec3 {
+        Vec3::new(a * self.u, a * self.v, a * self.w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_onb_from_tangent() {
+        let n = Vec3::new(0.0, 0.0, 2.0);
+        let uvw = Onb::build_from_w_and_u(n, Vec3::new(1.0, 0.0, 0.5));
+        assert_eq!(uvw.u, Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(uvw.v, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(uvw.w, Vec3::new(0.0, 0.0, 1.0));
+
+        // no tangent, or one along the normal, gives some frame anyway
+        for tangent in [Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 3.0)] {
+            let uvw = Onb::build_from_w_and_u(n, tangent);
+            assert!(f32::abs(uvw.u.length() - 1.0) < 1e-6);
+            assert!(f32::abs(uvw.u * uvw.w) < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,541 @@
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use rand::{Rng, RngCore};
+
+use crate::material::{Material, Metal, RoughDielectric};
+use crate::microfacet::{self, Ggx};
+use crate::rayhit::{HitRecord, Ray};
+use crate::texture::Texture;
+use crate::vec3::{Color, Vec3};
+
+/// Index of refraction of the clearcoat layer.
+const CLEARCOAT_IOR: f32 = 1.5;
+
+/// One material with artist friendly parameters in the style of the Disney
+/// principled BRDF, covering everything from plastic to metal to glass. Every
+/// parameter is a texture, plain numbers and colors work as constant ones.
+/// The scalar parameters other than `index_of_refraction` are in [0, 1].
+pub struct Principled {
+    pub base_color: Rc<dyn Texture>,
+    /// Blends from a dielectric to a conductor whose reflectance at normal
+    /// incidence is the base color.
+    pub metallic: Rc<dyn Texture>,
+    pub roughness: Rc<dyn Texture>,
+    /// Stretches the highlights of the metal and specular layers along the
+    /// surface's tangent, for brushed metal and the like.
+    pub anisotropic: Rc<dyn Texture>,
+    /// Strength of the dielectric specular reflection. 0.5 is a reflectance
+    /// of 4% at normal incidence, about right for most non-metals.
+    pub specular: Rc<dyn Texture>,
+    /// Extra reflection at grazing angles, for cloth.
+    pub sheen: Rc<dyn Texture>,
+    /// Blends the sheen from white to the hue of the base color.
+    pub sheen_tint: Rc<dyn Texture>,
+    /// Strength of a clear glossy layer on top, like varnish or car paint.
+    pub clearcoat: Rc<dyn Texture>,
+    pub clearcoat_roughness: Rc<dyn Texture>,
+    /// Blends the dielectric from opaque to glass, tinted by the base color.
+    pub transmission: Rc<dyn Texture>,
+    /// Used for the transmission.
+    pub index_of_refraction: Rc<dyn Texture>,
+}
+
+/// The parameters looked up at a particular hit.
+struct Parameters {
+    base_color: Color,
+    metallic: f32,
+    roughness: f32,
+    anisotropic: f32,
+    specular: f32,
+    sheen: f32,
+    sheen_tint: f32,
+    clearcoat: f32,
+    clearcoat_roughness: f32,
+    transmission: f32,
+    index_of_refraction: f32,
+}
+
+impl Principled {
+    /// A rough plastic of the given color. The other parameters can be
+    /// changed with the builder methods below.
+    pub fn new(base_color: impl Texture + 'static) -> Principled {
+        Principled {
+            base_color: Rc::new(base_color),
+            metallic: Rc::new(0.0),
+            roughness: Rc::new(0.5),
+            anisotropic: Rc::new(0.0),
+            specular: Rc::new(0.5),
+            sheen: Rc::new(0.0),
+            sheen_tint: Rc::new(0.5),
+            clearcoat: Rc::new(0.0),
+            clearcoat_roughness: Rc::new(0.03),
+            transmission: Rc::new(0.0),
+            index_of_refraction: Rc::new(1.5),
+        }
+    }
+
+    pub fn metallic(mut self, metallic: impl Texture + 'static) -> Principled {
+        self.metallic = Rc::new(metallic);
+        self
+    }
+
+    pub fn roughness(mut self, roughness: impl Texture + 'static) -> Principled {
+        self.roughness = Rc::new(roughness);
+        self
+    }
+
+    pub fn anisotropic(mut self, anisotropic: impl Texture + 'static) -> Principled {
+        self.anisotropic = Rc::new(anisotropic);
+        self
+    }
+
+    pub fn specular(mut self, specular: impl Texture + 'static) -> Principled {
+        self.specular = Rc::new(specular);
+        self
+    }
+
+    pub fn sheen(
+        mut self,
+        sheen: impl Texture + 'static,
+        sheen_tint: impl Texture + 'static,
+    ) -> Principled {
+        self.sheen = Rc::new(sheen);
+        self.sheen_tint = Rc::new(sheen_tint);
+        self
+    }
+
+    pub fn clearcoat(
+        mut self,
+        clearcoat: impl Texture + 'static,
+        clearcoat_roughness: impl Texture + 'static,
+    ) -> Principled {
+        self.clearcoat = Rc::new(clearcoat);
+        self.clearcoat_roughness = Rc::new(clearcoat_roughness);
+        self
+    }
+
+    pub fn transmission(
+        mut self,
+        transmission: impl Texture + 'static,
+        index_of_refraction: impl Texture + 'static,
+    ) -> Principled {
+        self.transmission = Rc::new(transmission);
+        self.index_of_refraction = Rc::new(index_of_refraction);
+        self
+    }
+
+    fn parameters(&self, rec: &HitRecord) -> Parameters {
+        let color = |t: &Rc<dyn Texture>| t.value(rec.u, rec.v, rec.p);
+        let scalar = |t: &Rc<dyn Texture>| color(t).x.clamp(0.0, 1.0);
+
+        Parameters {
+            base_color: color(&self.base_color),
+            metallic: scalar(&self.metallic),
+            roughness: scalar(&self.roughness),
+            anisotropic: scalar(&self.anisotropic),
+            specular: scalar(&self.specular),
+            sheen: scalar(&self.sheen),
+            sheen_tint: scalar(&self.sheen_tint),
+            clearcoat: scalar(&self.clearcoat),
+            clearcoat_roughness: scalar(&self.clearcoat_roughness),
+            transmission: scalar(&self.transmission),
+            index_of_refraction: f32::max(color(&self.index_of_refraction).x, 1.0),
+        }
+    }
+}
+
+impl Parameters {
+    /// The distribution of the metal and specular layers, rougher along the
+    /// tangent and smoother across it as the anisotropy goes up.
+    fn distribution(&self) -> Ggx {
+        let aspect = f32::sqrt(1.0 - 0.9 * self.anisotropic);
+        let alpha = self.roughness * self.roughness;
+        Ggx {
+            alpha_x: f32::max(alpha / aspect, 1e-4),
+            alpha_y: f32::max(alpha * aspect, 1e-4),
+        }
+    }
+}
+
+impl Parameters {
+    fn metal(&self) -> Metal {
+        Metal {
+            distribution: self.distribution(),
+            ..Metal::new(self.base_color, self.roughness)
+        }
+    }
+
+    fn glass(&self) -> RoughDielectric {
+        RoughDielectric::new(self.index_of_refraction, self.roughness)
+    }
+
+    /// The square root of the base color, the tint at each crossing of the
+    /// glass, so going in and back out again gives the base color.
+    fn transmission_tint(&self) -> Color {
+        let c = self.base_color;
+        Color::new(f32::sqrt(c.x), f32::sqrt(c.y), f32::sqrt(c.z))
+    }
+}
+
+fn mul(a: Color, b: Color) -> Color {
+    Color::new(a.x * b.x, a.y * b.y, a.z * b.z)
+}
+
+fn schlick_weight(cos_theta: f32) -> f32 {
+    (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+fn luminance(c: Color) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+/// Sample a reflection off a dielectric layer, in the local shading frame.
+/// The Fresnel term has already been used to choose this lobe, which leaves
+/// a colorless weight.
+fn sample_glossy(distribution: Ggx, wo: Vec3, u1: f32, u2: f32) -> Option<(Color, Vec3)> {
+    if distribution.is_smooth() {
+        return Some((Color::new(1.0, 1.0, 1.0), Vec3::new(-wo.x, -wo.y, wo.z)));
+    }
+
+    let wm = distribution.sample_visible_normal(wo, u1, u2);
+    let wi = microfacet::reflect(wo, wm);
+    if wi.z <= 0.0 {
+        return None;
+    }
+
+    let weight = distribution.g(wo, wi) / distribution.g1(wo);
+    Some((Color::new(weight, weight, weight), wi))
+}
+
+/// Sample the Disney diffuse lobe, with its retroreflection at grazing
+/// angles on rough surfaces, plus the sheen.
+fn sample_diffuse(params: &Parameters, wo: Vec3, rng: &mut dyn RngCore) -> (Color, Vec3) {
+    let mut wi = Vec3::new(0.0, 0.0, 1.0) + Vec3::random_unit_vector(rng);
+    if wi.near_zero() {
+        wi = Vec3::new(0.0, 0.0, 1.0);
+    }
+    let wi = wi.unit_vector();
+
+    (diffuse_weight(params, wo, wi), wi)
+}
+
+/// The diffuse lobe's BSDF times the cosine over its cosine weighted pdf.
+fn diffuse_weight(params: &Parameters, wo: Vec3, wi: Vec3) -> Color {
+    let cos_d = wi * (wo + wi).unit_vector();
+    let fd90 = 0.5 + 2.0 * params.roughness * cos_d * cos_d;
+    let diffuse =
+        (1.0 + (fd90 - 1.0) * schlick_weight(wi.z)) * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z));
+
+    let lum = luminance(params.base_color);
+    let tint = if lum > 0.0 {
+        params.base_color / lum
+    } else {
+        Color::new(1.0, 1.0, 1.0)
+    };
+    let sheen_color =
+        Color::new(1.0, 1.0, 1.0) * (1.0 - params.sheen_tint) + tint * params.sheen_tint;
+
+    // cosine sampling leaves f * pi, and unlike the diffuse the sheen isn't
+    // divided by pi to begin with
+    params.base_color * diffuse + sheen_color * (params.sheen * schlick_weight(cos_d) * PI)
+}
+
+/// The BSDF times the cosine, and the pdf, of a reflection sampled by
+/// `sample_glossy`, in the local shading frame.
+fn eval_glossy(distribution: Ggx, wo: Vec3, wi: Vec3) -> (f32, f32) {
+    if wi.z <= 0.0 {
+        return (0.0, 0.0);
+    }
+    let wm = (wo + wi).unit_vector();
+    let d = distribution.d(wm);
+    (
+        d * distribution.g(wo, wi) / (4.0 * wo.z),
+        distribution.g1(wo) * d / (4.0 * wo.z),
+    )
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        let params = self.parameters(rec);
+        let uvw = rec.shading_frame();
+        let wo = uvw.to_local(-r_in.direction.unit_vector());
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        // The lobes are layered, outermost first: the clearcoat, then metal
+        // or glass, then a specular layer over the diffuse. Each is picked
+        // with the probability of light interacting with it, so those
+        // factors cancel out of the weights.
+        let coat = params.clearcoat * microfacet::fresnel_dielectric(wo.z, CLEARCOAT_IOR);
+        if rec.front_face && rng.gen::<f32>() < coat {
+            let distribution =
+                Ggx::from_roughness(params.clearcoat_roughness, params.clearcoat_roughness);
+            let (attenuation, wi) =
+                sample_glossy(distribution, wo, rng.gen::<f32>(), rng.gen::<f32>())?;
+            let scattered = Ray {
+                origin: rec.p,
+                direction: uvw.local(wi.x, wi.y, wi.z),
+            };
+            return Some((attenuation, scattered));
+        }
+
+        if rng.gen::<f32>() < params.metallic {
+            return params.metal().scatter(r_in, rec, rng);
+        }
+
+        if rng.gen::<f32>() < params.transmission {
+            let (mut attenuation, scattered) = params.glass().scatter(r_in, rec, rng)?;
+            if scattered.direction * rec.normal < 0.0 {
+                attenuation = mul(attenuation, params.transmission_tint());
+            }
+            return Some((attenuation, scattered));
+        }
+
+        let f0 = 0.08 * params.specular;
+        let specular = f0 + (1.0 - f0) * schlick_weight(wo.z);
+        let (attenuation, wi) = if rng.gen::<f32>() < specular {
+            sample_glossy(
+                params.distribution(),
+                wo,
+                rng.gen::<f32>(),
+                rng.gen::<f32>(),
+            )?
+        } else {
+            sample_diffuse(&params, wo, rng)
+        };
+
+        let scattered = Ray {
+            origin: rec.p,
+            direction: uvw.local(wi.x, wi.y, wi.z),
+        };
+        Some((attenuation, scattered))
+    }
+
+    fn eval(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        direction: Vec3,
+        rng: &mut dyn RngCore,
+    ) -> Option<(Color, f32)> {
+        let params = self.parameters(rec);
+        let uvw = rec.shading_frame();
+        let wo = uvw.to_local(-r_in.direction.unit_vector());
+        let wi = uvw.to_local(direction.unit_vector());
+        if wo.z <= 0.0 {
+            return Some((Color::new(0.0, 0.0, 0.0), 0.0));
+        }
+
+        // each lobe weighted by the chance of `scatter` picking it, any
+        // smooth lobe that could be picked makes the whole thing a delta
+        let coat = if rec.front_face {
+            params.clearcoat * microfacet::fresnel_dielectric(wo.z, CLEARCOAT_IOR)
+        } else {
+            0.0
+        };
+        let metal = (1.0 - coat) * params.metallic;
+        let glass = (1.0 - coat - metal) * params.transmission;
+        let f0 = 0.08 * params.specular;
+        let specular = f0 + (1.0 - f0) * schlick_weight(wo.z);
+        let base = 1.0 - coat - metal - glass;
+
+        let mut f = Color::new(0.0, 0.0, 0.0);
+        let mut pdf = 0.0;
+
+        if coat > 0.0 {
+            let distribution =
+                Ggx::from_roughness(params.clearcoat_roughness, params.clearcoat_roughness);
+            if distribution.is_smooth() {
+                return None;
+            }
+            let (coat_f, coat_pdf) = eval_glossy(distribution, wo, wi);
+            f += Color::new(coat_f, coat_f, coat_f) * coat;
+            pdf += coat_pdf * coat;
+        }
+
+        if metal > 0.0 {
+            let (metal_f, metal_pdf) = params.metal().eval(r_in, rec, direction, rng)?;
+            f += metal_f * metal;
+            pdf += metal_pdf * metal;
+        }
+
+        if glass > 0.0 {
+            let (mut glass_f, glass_pdf) = params.glass().eval(r_in, rec, direction, rng)?;
+            if wi.z < 0.0 {
+                glass_f = mul(glass_f, params.transmission_tint());
+            }
+            f += glass_f * glass;
+            pdf += glass_pdf * glass;
+        }
+
+        if base > 0.0 {
+            let distribution = params.distribution();
+            if distribution.is_smooth() {
+                return None;
+            }
+            if wi.z > 0.0 {
+                let (glossy_f, glossy_pdf) = eval_glossy(distribution, wo, wi);
+                f += Color::new(glossy_f, glossy_f, glossy_f) * (base * specular);
+                pdf += glossy_pdf * base * specular;
+
+                // cosine weighted
+                let diffuse_pdf = wi.z / PI;
+                f += diffuse_weight(&params, wo, wi) * (diffuse_pdf * base * (1.0 - specular));
+                pdf += diffuse_pdf * base * (1.0 - specular);
+            }
+        }
+
+        Some((f, pdf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::rayhit::test_hit;
+
+    /// Average weight and fraction of rays transmitted below the surface,
+    /// for light arriving at `cos_theta` to the normal.
+    fn scatter_statistics(mat: Principled, cos_theta: f32) -> (Color, f32) {
+        let mut rng = rand::thread_rng();
+        let mat: Rc<dyn Material> = Rc::new(mat);
+        let (rec, r_in) = test_hit(mat.clone(), cos_theta, true);
+
+        let n = 100_000;
+        let mut total = Color::new(0.0, 0.0, 0.0);
+        let mut transmitted = 0;
+        for _ in 0..n {
+            if let Some((attenuation, scattered)) = mat.scatter(&r_in, &rec, &mut rng) {
+                total += attenuation;
+                if scattered.direction.y < 0.0 {
+                    transmitted += 1;
+                }
+            }
+        }
+
+        (total / n as f32, transmitted as f32 / n as f32)
+    }
+
+    #[test]
+    fn test_principled_white_plastic_conserves_energy() {
+        for cos_theta in [1.0, 0.7, 0.4] {
+            let (albedo, transmitted) = scatter_statistics(Principled::new(1.0), cos_theta);
+            assert_eq!(transmitted, 0.0);
+            assert!(
+                albedo.x > 0.9 && albedo.x < 1.1,
+                "{} {:?}",
+                cos_theta,
+                albedo
+            );
+        }
+    }
+
+    #[test]
+    fn test_principled_lobes() {
+        // fully metallic is the metal underneath
+        let base = Color::new(0.9, 0.6, 0.2);
+        let (albedo, _) =
+            scatter_statistics(Principled::new(base).metallic(1.0).roughness(0.0), 1.0);
+        assert!((albedo - base).length() < 5e-3, "{:?}", albedo);
+
+        // smooth clear glass loses nothing, and mostly transmits head on
+        let (albedo, transmitted) = scatter_statistics(
+            Principled::new(1.0).roughness(0.0).transmission(1.0, 1.5),
+            1.0,
+        );
+        assert!((albedo - Color::new(1.0, 1.0, 1.0)).length() < 1e-3);
+        assert!(f32::abs(transmitted - 0.96) < 0.01, "{}", transmitted);
+
+        // the clearcoat only adds reflection
+        let (plain, _) = scatter_statistics(Principled::new(0.5), 1.0);
+        let (coated, _) = scatter_statistics(Principled::new(0.5).clearcoat(1.0, 0.0), 1.0);
+        assert!(coated.x > plain.x);
+    }
+
+    #[test]
+    fn test_principled_eval() {
+        let mut rng = StdRng::seed_from_u64(40);
+        let materials = [
+            Principled::new(Color::new(0.8, 0.4, 0.2)),
+            Principled::new(Color::new(0.3, 0.3, 0.8)).sheen(1.0, 0.5),
+            Principled::new(0.9).metallic(1.0).anisotropic(0.5),
+            Principled::new(0.9).roughness(0.4).transmission(1.0, 1.5),
+            Principled::new(0.5).metallic(0.5).clearcoat(1.0, 0.3),
+        ];
+        for material in materials {
+            let mat: Rc<dyn Material> = Rc::new(material);
+            let (rec, r_in) = test_hit(mat.clone(), 0.8, true);
+
+            // over the directions scatter picks, the BSDF over the pdf
+            // averages out to the same as the weights
+            let n = 100_000;
+            let mut albedo = Color::new(0.0, 0.0, 0.0);
+            let mut f = Color::new(0.0, 0.0, 0.0);
+            for _ in 0..n {
+                if let Some((attenuation, scattered)) = mat.scatter(&r_in, &rec, &mut rng) {
+                    let (sample_f, pdf) = mat
+                        .eval(&r_in, &rec, scattered.direction, &mut rng)
+                        .unwrap();
+                    assert!(pdf > 0.0);
+                    albedo += attenuation;
+                    f += sample_f / pdf;
+                }
+            }
+            assert!(
+                (f - albedo).length() < 0.01 * n as f32,
+                "{:?} {:?}",
+                f,
+                albedo
+            );
+
+            // and the pdf covers the sphere no more than once
+            let mut pdf = 0.0;
+            for _ in 0..n {
+                pdf += mat
+                    .eval(&r_in, &rec, Vec3::random_unit_vector(&mut rng), &mut rng)
+                    .unwrap()
+                    .1;
+            }
+            let integral = pdf * 4.0 * PI / n as f32;
+            assert!(integral <= 1.05, "{}", integral);
+        }
+
+        // smooth lobes can't be evaluated
+        let mat: Rc<dyn Material> = Rc::new(Principled::new(0.5).roughness(0.0));
+        let (rec, r_in) = test_hit(mat.clone(), 0.8, true);
+        assert!(mat.eval(&r_in, &rec, rec.normal, &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_principled_anisotropy_follows_tangent() {
+        let mut rng = rand::thread_rng();
+        // the highlight stretches out along dpdu, whichever way it runs
+        let mat: Rc<dyn Material> = Rc::new(Principled::new(1.0).metallic(1.0).anisotropic(1.0));
+        let r_in = Ray {
+            origin: Vec3::new(0.0, 1.0, 0.0),
+            direction: Vec3::new(0.0, -1.0, 0.0),
+        };
+
+        for dpdu in [Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 3.0)] {
+            let (mut rec, _) = test_hit(mat.clone(), 1.0, true);
+            rec.dpdu = dpdu;
+            rec.dpdv = dpdu.cross(Vec3::new(0.0, 1.0, 0.0));
+
+            let (along, across) = (dpdu.unit_vector(), rec.dpdv.unit_vector());
+            let (mut spread_along, mut spread_across) = (0.0, 0.0);
+            for _ in 0..2000 {
+                if let Some((_, scattered)) = mat.scatter(&r_in, &rec, &mut rng) {
+                    let d = scattered.direction.unit_vector();
+                    spread_along += f32::abs(d * along);
+                    spread_across += f32::abs(d * across);
+                }
+            }
+            assert!(spread_along > 2.0 * spread_across);
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use crate::aabb::Aabb;
+use crate::light::{self, Light, LightSample};
+use crate::light_bvh::LightBounds;
+use crate::onb::Onb;
+use crate::vec3::{Color, Point3, Vec3};
+
+/// Brightness of a color, for comparing the power of lights.
+fn average(c: Color) -> f32 {
+    (c.x + c.y + c.z) / 3.0
+}
+
+/// Light given off equally in every direction from a single point. The
+/// light arriving falls off with the square of the distance.
+#[derive(Copy, Clone, Debug)]
+pub struct PointLight {
+    pub position: Point3,
+    /// Light per unit solid angle.
+    pub intensity: Color,
+}
+
+impl Light for PointLight {
+    fn sample(&self, origin: Point3, _u1: f32, _u2: f32) -> Option<LightSample> {
+        let to_light = self.position - origin;
+        let distance = to_light.length();
+
+        Some(LightSample {
+            direction: to_light / distance,
+            pdf: 1.0,
+            delta: Some((distance, self.intensity / (distance * distance))),
+        })
+    }
+
+    fn pdf(&self, _origin: Point3, _direction: Vec3) -> f32 {
+        0.0
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: Aabb::new(self.position, self.position),
+            power: 4.0 * std::f32::consts::PI * average(self.intensity),
+            axis: Vec3::new(0.0, 0.0, 1.0),
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+        })
+    }
+}
+
+/// A point light that only shines within a cone. The light is at full
+/// strength out to `inner_angle` from the axis, then fades smoothly to
+/// nothing at `outer_angle`.
+#[derive(Copy, Clone, Debug)]
+pub struct SpotLight {
+    pub position: Point3,
+    /// Unit vector along the axis of the cone, away from the light.
+    pub axis: Vec3,
+    /// Light per unit solid angle, along the axis.
+    pub intensity: Color,
+    cos_inner: f32,
+    cos_outer: f32,
+}
+
+impl SpotLight {
+    /// A spotlight at `position` pointed at `target`. The angles are in
+    /// degrees from the axis.
+    pub fn new(
+        position: Point3,
+        target: Point3,
+        intensity: Color,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> SpotLight {
+        let outer_angle = f32::max(outer_angle, inner_angle);
+        SpotLight {
+            position,
+            axis: (target - position).unit_vector(),
+            intensity,
+            cos_inner: f32::cos(inner_angle.to_radians()),
+            cos_outer: f32::cos(outer_angle.to_radians()),
+        }
+    }
+
+    /// How much of the intensity goes out along `w`, away from the light.
+    fn falloff(&self, w: Vec3) -> f32 {
+        let cos_theta = w * self.axis;
+        if cos_theta >= self.cos_inner {
+            return 1.0;
+        }
+        if cos_theta <= self.cos_outer {
+            return 0.0;
+        }
+
+        // smoothstep between the two cones
+        let t = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, origin: Point3, _u1: f32, _u2: f32) -> Option<LightSample> {
+        let to_light = self.position - origin;
+        let distance = to_light.length();
+        let direction = to_light / distance;
+        let falloff = self.falloff(-direction);
+        if falloff == 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            pdf: 1.0,
+            delta: Some((distance, self.intensity * (falloff / (distance * distance)))),
+        })
+    }
+
+    fn pdf(&self, _origin: Point3, _direction: Vec3) -> f32 {
+        0.0
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        // Everything inside the inner cone, plus however far the falloff
+        // goes beyond it. The power is overestimated as if the light went
+        // every which way, like pbrt does.
+        let theta_e = f32::acos(self.cos_outer) - f32::acos(self.cos_inner);
+        Some(LightBounds {
+            bounds: Aabb::new(self.position, self.position),
+            power: 4.0 * std::f32::consts::PI * average(self.intensity),
+            axis: self.axis,
+            cos_theta_o: self.cos_inner,
+            cos_theta_e: f32::cos(theta_e),
+        })
+    }
+}
+
+/// Light from very far away, like the sun, arriving from the same direction
+/// everywhere. A disc with some angular size gives soft shadows, one with
+/// none gives hard ones.
+#[derive(Copy, Clone, Debug)]
+pub struct DirectionalLight {
+    /// Unit vector toward the light.
+    pub direction: Vec3,
+    /// Light arriving on a surface facing the light.
+    pub irradiance: Color,
+    /// Sine squared of the disc's angular radius.
+    sin2_theta_max: f32,
+}
+
+impl DirectionalLight {
+    /// A light shining from `direction`, with the angular diameter of its
+    /// disc in degrees. The sun is about half a degree across.
+    pub fn new(direction: Vec3, irradiance: Color, angular_diameter: f32) -> DirectionalLight {
+        let sin_theta_max = f32::sin((angular_diameter / 2.0).to_radians());
+        DirectionalLight {
+            direction: direction.unit_vector(),
+            irradiance,
+            sin2_theta_max: sin_theta_max * sin_theta_max,
+        }
+    }
+
+    /// Whether the disc has no size, so all the light comes from one
+    /// direction.
+    fn is_delta(&self) -> bool {
+        self.sin2_theta_max == 0.0
+    }
+
+    /// Whether `w` points at the disc. Compares how far off the axis it is
+    /// rather than cosines, which lose too much precision for small discs.
+    fn in_disc(&self, w: Vec3) -> bool {
+        let w = w.unit_vector();
+        w * self.direction > 0.0 && w.cross(self.direction).length_squared() <= self.sin2_theta_max
+    }
+
+    /// Radiance over the disc, which adds up to the irradiance.
+    fn radiance(&self) -> Color {
+        self.irradiance * light::cone_pdf(self.sin2_theta_max)
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _origin: Point3, u1: f32, u2: f32) -> Option<LightSample> {
+        if self.is_delta() {
+            return Some(LightSample {
+                direction: self.direction,
+                pdf: 1.0,
+                delta: Some((f32::INFINITY, self.irradiance)),
+            });
+        }
+
+        let uvw = Onb::build_from_w(self.direction);
+        let w = light::sample_cone(self.sin2_theta_max, u1, u2);
+        Some(LightSample {
+            direction: uvw.local(w.x, w.y, w.z),
+            pdf: light::cone_pdf(self.sin2_theta_max),
+            delta: None,
+        })
+    }
+
+    fn pdf(&self, _origin: Point3, direction: Vec3) -> f32 {
+        if self.is_delta() || !self.in_disc(direction) {
+            return 0.0;
+        }
+
+        light::cone_pdf(self.sin2_theta_max)
+    }
+
+    fn escaped(&self, direction: Vec3) -> Color {
+        if self.is_delta() || !self.in_disc(direction) {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        self.radiance()
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_point_light() {
+        let light = PointLight {
+            position: Point3::new(0.0, 2.0, 0.0),
+            intensity: Color::new(4.0, 4.0, 4.0),
+        };
+        let sample = light.sample(Point3::new(0.0, 0.0, 0.0), 0.5, 0.5).unwrap();
+        assert_eq!(sample.direction, Vec3::new(0.0, 1.0, 0.0));
+
+        // inverse square falloff
+        let (distance, emitted) = sample.delta.unwrap();
+        assert_eq!(distance, 2.0);
+        assert_eq!(emitted, Color::new(1.0, 1.0, 1.0));
+        assert_eq!(light.pdf(Point3::new(0.0, 0.0, 0.0), sample.direction), 0.0);
+    }
+
+    #[test]
+    fn test_spot_light() {
+        let light = SpotLight::new(
+            Point3::new(0.0, 1.0, 0.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            30.0,
+            45.0,
+        );
+        let at = |x: f32| {
+            light
+                .sample(Point3::new(x, 0.0, 0.0), 0.5, 0.5)
+                .map(|sample| sample.delta.unwrap().1.x * (1.0 + x * x))
+        };
+
+        // full strength inside the inner cone, fading out to the outer one
+        assert_eq!(at(0.0), Some(1.0));
+        assert!(f32::abs(at(0.5).unwrap() - 1.0) < 1e-6);
+        let fading = at(0.8).unwrap();
+        assert!(fading > 0.0 && fading < 1.0);
+        assert!(at(0.9).unwrap() < fading);
+        assert!(at(1.1).is_none());
+    }
+
+    #[test]
+    fn test_directional_light() {
+        let direction = Vec3::new(1.0, 1.0, 0.0).unit_vector();
+        let origin = Point3::new(0.0, 0.0, 0.0);
+
+        let hard = DirectionalLight::new(direction, Color::new(2.0, 2.0, 2.0), 0.0);
+        let sample = hard.sample(origin, 0.5, 0.5).unwrap();
+        assert_eq!(sample.direction, hard.direction);
+        assert_eq!(
+            sample.delta,
+            Some((f32::INFINITY, Color::new(2.0, 2.0, 2.0)))
+        );
+        assert_eq!(hard.escaped(direction), Color::new(0.0, 0.0, 0.0));
+
+        // the radiance over a soft light's disc adds up to the irradiance
+        let soft = DirectionalLight::new(direction, Color::new(2.0, 2.0, 2.0), 0.5);
+        let n = 100;
+        let mut irradiance = 0.0;
+        for i in 0..n {
+            let sample = soft
+                .sample(origin, (i as f32 + 0.5) / n as f32, 0.3)
+                .unwrap();
+            assert!(sample.delta.is_none());
+            assert_eq!(sample.pdf, soft.pdf(origin, sample.direction));
+            irradiance += soft.escaped(sample.direction).x / sample.pdf;
+        }
+        assert!(f32::abs(irradiance / n as f32 - 2.0) < 1e-2);
+
+        let away = Vec3::new(0.0, 1.0, 0.0);
+        assert_eq!(soft.pdf(origin, away), 0.0);
+        assert_eq!(soft.escaped(away), Color::new(0.0, 0.0, 0.0));
+    }
+}
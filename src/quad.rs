@@ -0,0 +1,219 @@
+use std::rc::Rc;
+
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::hittable::Hittable;
+use crate::light::{self, Light, LightSample};
+use crate::light_bvh::LightBounds;
+use crate::material::Material;
+use crate::rayhit::{HitRecord, Ray};
+use crate::vec3::{Point3, Vec3};
+
+/// Flat parallelogram with one corner at `corner` and sides `u` and `v`.
+/// The front face is the side `u` x `v` points to.
+#[derive(Clone)]
+pub struct Quad {
+    pub corner: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub mat: Rc<dyn Material>,
+    normal: Vec3,
+    area: f32,
+}
+
+impl Quad {
+    pub fn new(corner: Point3, u: Vec3, v: Vec3, material: Rc<dyn Material>) -> Quad {
+        let n = u.cross(v);
+        Quad {
+            corner,
+            u,
+            v,
+            mat: material,
+            normal: n.unit_vector(),
+            area: n.length(),
+        }
+    }
+
+    /// Where the ray crosses the quad, as the ray parameter and the
+    /// coordinates along u and v.
+    fn intersect(&self, ray: &Ray) -> Option<(f32, f32, f32)> {
+        let denom = self.normal * ray.direction;
+        // parallel to the plane
+        if f32::abs(denom) < 1e-8 {
+            return None;
+        }
+
+        let t = ((self.corner - ray.origin) * self.normal) / denom;
+        let d = ray.at(t) - self.corner;
+
+        // coordinates along the sides, which needn't be perpendicular
+        let n = self.u.cross(self.v);
+        let w = n / (n * n);
+        let a = w * d.cross(self.v);
+        let b = w * self.u.cross(d);
+        if !(0.0..=1.0).contains(&a) || !(0.0..=1.0).contains(&b) {
+            return None;
+        }
+
+        Some((t, a, b))
+    }
+
+    /// The pdf per unit solid angle of picking a point uniformly over the
+    /// area, seen along `direction` at `distance`.
+    fn area_pdf(&self, direction: Vec3, distance: f32) -> f32 {
+        let cos_theta = f32::abs(direction.unit_vector() * self.normal);
+        if cos_theta < 1e-6 {
+            return 0.0;
+        }
+
+        distance * distance / (cos_theta * self.area)
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32, _rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let (t, u, v) = self.intersect(&ray)?;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let mut rec = HitRecord {
+            p: ray.at(t),
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            u,
+            v,
+            dpdu: self.u,
+            dpdv: self.v,
+            mat: self.mat.clone(),
+            t,
+            front_face: false,
+        };
+        rec.set_face_normal(&ray, self.normal);
+
+        Some(rec)
+    }
+
+    fn light(&self) -> Option<Rc<dyn Light>> {
+        if self.mat.is_emissive() {
+            Some(Rc::new(self.clone()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Quads are sampled uniformly over their area.
+impl Light for Quad {
+    fn sample(&self, origin: Point3, u1: f32, u2: f32) -> Option<LightSample> {
+        let p = self.corner + self.u * u1 + self.v * u2;
+        let to_light = p - origin;
+        let distance = to_light.length();
+        let pdf = self.area_pdf(to_light, distance);
+        if pdf == 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction: to_light / distance,
+            pdf,
+            delta: None,
+        })
+    }
+
+    fn pdf(&self, origin: Point3, direction: Vec3) -> f32 {
+        let ray = Ray { origin, direction };
+        match self.intersect(&ray) {
+            Some((t, _, _)) if t > 0.0 => self.area_pdf(direction, t * direction.length()),
+            _ => 0.0,
+        }
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let center = self.corner + (self.u + self.v) / 2.0;
+        let radiance = light::surface_radiance(&self.mat, center, self.normal);
+        let mut bounds = Aabb::new(self.corner, self.corner);
+        for p in [
+            self.corner + self.u,
+            self.corner + self.v,
+            self.corner + self.u + self.v,
+        ] {
+            bounds = bounds.union(&Aabb::new(p, p));
+        }
+
+        // only the front face gives off light
+        Some(LightBounds {
+            bounds,
+            power: std::f32::consts::PI * self.area * radiance,
+            axis: self.normal,
+            cos_theta_o: 1.0,
+            cos_theta_e: 0.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::DiffuseLight;
+    use crate::vec3::Color;
+
+    fn lamp() -> Quad {
+        Quad::new(
+            Point3::new(-1.0, 2.0, -1.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Rc::new(DiffuseLight {
+                emit: Color::new(1.0, 1.0, 1.0),
+            }),
+        )
+    }
+
+    #[test]
+    fn test_quad_hit() {
+        let mut rng = rand::thread_rng();
+        let quad = lamp();
+        let ray = Ray {
+            origin: Point3::new(0.5, 0.0, 0.0),
+            direction: Vec3::new(0.0, 1.0, 0.0),
+        };
+        let rec = quad.hit(ray, 0.0, f32::INFINITY, &mut rng).unwrap();
+        assert_eq!(rec.t, 2.0);
+        assert_eq!((rec.u, rec.v), (0.75, 0.5));
+        // u x v points down, toward the ray
+        assert!(rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(0.0, -1.0, 0.0));
+
+        let miss = Ray {
+            origin: Point3::new(1.5, 0.0, 0.0),
+            direction: Vec3::new(0.0, 1.0, 0.0),
+        };
+        assert!(quad.hit(miss, 0.0, f32::INFINITY, &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_quad_light() {
+        let mut rng = rand::thread_rng();
+        let quad = lamp();
+        let light = quad.light().unwrap();
+        let origin = Point3::new(0.3, 0.0, 0.2);
+
+        for i in 0..10 {
+            for j in 0..10 {
+                let (u1, u2) = (i as f32 / 10.0 + 0.05, j as f32 / 10.0 + 0.05);
+                let sample = light.sample(origin, u1, u2).unwrap();
+                let ray = Ray {
+                    origin,
+                    direction: sample.direction,
+                };
+                assert!(quad.hit(ray, 0.0, f32::INFINITY, &mut rng).is_some());
+                assert!(f32::abs(sample.pdf / light.pdf(origin, sample.direction) - 1.0) < 1e-4);
+            }
+        }
+
+        // straight up from the middle, 2 units away from a 4 unit quad
+        let pdf = light.pdf(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(f32::abs(pdf - 1.0) < 1e-6);
+        assert_eq!(light.pdf(origin, Vec3::new(0.0, -1.0, 0.0)), 0.0);
+    }
+}
@@ -2,6 +2,7 @@ use std::rc::Rc;
 
 use crate::vec3::{Vec3, Point3};
 use crate::material::Material;
+use crate::onb::Onb;
 
 #[derive(Copy, Clone, Debug)]
 pub struct Ray {
@@ -19,12 +20,25 @@ impl Ray {
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
+    /// Surface coordinates for texturing, both in [0, 1].
+    pub u: f32,
+    pub v: f32,
+    /// How the point moves with u and v, along the surface. Zero where the
+    /// surface has no parameterization.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub mat: Rc<dyn Material>,
     pub t: f32,
     pub front_face: bool,
 }
 
 impl HitRecord {
+    /// The local frame materials shade in, around the normal with u along
+    /// dpdu, so anisotropic roughness follows the surface's parameterization.
+    pub fn shading_frame(&self) -> Onb {
+        Onb::build_from_w_and_u(self.normal, self.dpdu)
+    }
+
     pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: Vec3) {
         // dot product of ray and outward normal tells us if we are hitting
         // the inside or ouside of the surface.
@@ -35,4 +49,27 @@ impl HitRecord {
             -outward_normal
         };
     }
-}
\ No newline at end of file
+}
+
+/// A hit at the origin on a surface of `mat` facing up y, and a ray arriving
+/// at it from above at `cos_theta` to the normal, for testing materials.
+#[cfg(test)]
+pub fn test_hit(mat: Rc<dyn Material>, cos_theta: f32, front_face: bool) -> (HitRecord, Ray) {
+    let rec = HitRecord {
+        p: Point3::new(0.0, 0.0, 0.0),
+        normal: Vec3::new(0.0, 1.0, 0.0),
+        u: 0.5,
+        v: 0.5,
+        dpdu: Vec3::new(0.0, 0.0, 0.0),
+        dpdv: Vec3::new(0.0, 0.0, 0.0),
+        mat,
+        t: 1.0,
+        front_face,
+    };
+    let sin_theta = f32::sqrt(1.0 - cos_theta * cos_theta);
+    let r_in = Ray {
+        origin: Vec3::new(-sin_theta, cos_theta, 0.0),
+        direction: Vec3::new(sin_theta, -cos_theta, 0.0),
+    };
+    (rec, r_in)
+}
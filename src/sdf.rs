@@ -0,0 +1,446 @@
+use std::rc::Rc;
+
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::hittable::Hittable;
+use crate::material::Material;
+use crate::rayhit::{HitRecord, Ray};
+use crate::vec3::{Point3, Vec3};
+
+/// A signed distance field, negative inside the shape. Distances may be
+/// underestimates (sphere tracing just takes more steps) but never
+/// overestimates.
+pub trait Sdf {
+    fn distance(&self, p: Point3) -> f32;
+
+    /// A box the surface is guaranteed to be inside of.
+    fn bounds(&self) -> Aabb;
+}
+
+fn abs(v: Vec3) -> Vec3 {
+    Vec3::new(f32::abs(v.x), f32::abs(v.y), f32::abs(v.z))
+}
+
+fn max(v: Vec3, m: f32) -> Vec3 {
+    Vec3::new(f32::max(v.x, m), f32::max(v.y, m), f32::max(v.z, m))
+}
+
+fn surrounding_box(a: Aabb, b: Aabb) -> Aabb {
+    Aabb::new(
+        Point3::new(
+            f32::min(a.min.x, b.min.x),
+            f32::min(a.min.y, b.min.y),
+            f32::min(a.min.z, b.min.z),
+        ),
+        Point3::new(
+            f32::max(a.max.x, b.max.x),
+            f32::max(a.max.y, b.max.y),
+            f32::max(a.max.z, b.max.z),
+        ),
+    )
+}
+
+pub struct SdfSphere {
+    pub center: Point3,
+    pub radius: f32,
+}
+
+impl Sdf for SdfSphere {
+    fn distance(&self, p: Point3) -> f32 {
+        (p - self.center).length() - self.radius
+    }
+
+    fn bounds(&self) -> Aabb {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        Aabb::new(self.center - r, self.center + r)
+    }
+}
+
+/// Box centered at the origin with its edges rounded off by `radius`.
+pub struct RoundBox {
+    pub half_extents: Vec3,
+    pub radius: f32,
+}
+
+impl Sdf for RoundBox {
+    fn distance(&self, p: Point3) -> f32 {
+        let q = abs(p) - self.half_extents;
+        max(q, 0.0).length() + f32::min(f32::max(q.x, f32::max(q.y, q.z)), 0.0) - self.radius
+    }
+
+    fn bounds(&self) -> Aabb {
+        let r = self.half_extents + Vec3::new(self.radius, self.radius, self.radius);
+        Aabb::new(-r, r)
+    }
+}
+
+/// Torus centered at the origin, lying in the xz plane.
+pub struct Torus {
+    pub major_radius: f32,
+    pub minor_radius: f32,
+}
+
+impl Sdf for Torus {
+    fn distance(&self, p: Point3) -> f32 {
+        let ring = f32::sqrt(p.x * p.x + p.z * p.z) - self.major_radius;
+        f32::sqrt(ring * ring + p.y * p.y) - self.minor_radius
+    }
+
+    fn bounds(&self) -> Aabb {
+        let r = self.major_radius + self.minor_radius;
+        let r = Vec3::new(r, self.minor_radius, r);
+        Aabb::new(-r, r)
+    }
+}
+
+/// Triangular prism centered at the origin with its length along z. The
+/// triangle is equilateral, pointing up, with its corners `size` from the
+/// center along y.
+pub struct TriPrism {
+    pub size: f32,
+    pub half_length: f32,
+}
+
+impl Sdf for TriPrism {
+    fn distance(&self, p: Point3) -> f32 {
+        let q = abs(p);
+        f32::max(
+            q.z - self.half_length,
+            f32::max(q.x * 0.866_025 + p.y * 0.5, -p.y) - self.size * 0.5,
+        )
+    }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::new(
+            Point3::new(-0.866_025 * self.size, -0.5 * self.size, -self.half_length),
+            Point3::new(0.866_025 * self.size, self.size, self.half_length),
+        )
+    }
+}
+
+/// The power 8 Mandelbulb fractal, centered at the origin and roughly of
+/// radius 1.2.
+pub struct Mandelbulb {
+    pub power: f32,
+    pub iterations: u32,
+}
+
+impl Sdf for Mandelbulb {
+    fn distance(&self, p: Point3) -> f32 {
+        // distance estimate from the running derivative of the iteration
+        let mut z = p;
+        let mut dr = 1.0;
+        let mut r = z.length();
+
+        for _ in 0..self.iterations {
+            if r > 2.0 || r == 0.0 {
+                break;
+            }
+
+            let theta = f32::acos(z.z / r) * self.power;
+            let phi = f32::atan2(z.y, z.x) * self.power;
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+
+            let zr = r.powf(self.power);
+            z = Vec3::new(
+                theta.sin() * phi.cos(),
+                phi.sin() * theta.sin(),
+                theta.cos(),
+            ) * zr
+                + p;
+            r = z.length();
+        }
+
+        if r == 0.0 {
+            return 0.0;
+        }
+        0.5 * r.ln() * r / dr
+    }
+
+    fn bounds(&self) -> Aabb {
+        let r = Vec3::new(1.25, 1.25, 1.25);
+        Aabb::new(-r, r)
+    }
+}
+
+pub struct Translate {
+    pub sdf: Box<dyn Sdf>,
+    pub offset: Vec3,
+}
+
+impl Sdf for Translate {
+    fn distance(&self, p: Point3) -> f32 {
+        self.sdf.distance(p - self.offset)
+    }
+
+    fn bounds(&self) -> Aabb {
+        let b = self.sdf.bounds();
+        Aabb::new(b.min + self.offset, b.max + self.offset)
+    }
+}
+
+/// Union of two shapes with the seam blended over a distance of about `k`.
+pub struct SmoothUnion {
+    pub a: Box<dyn Sdf>,
+    pub b: Box<dyn Sdf>,
+    pub k: f32,
+}
+
+impl Sdf for SmoothUnion {
+    fn distance(&self, p: Point3) -> f32 {
+        let d1 = self.a.distance(p);
+        let d2 = self.b.distance(p);
+        let h = (0.5 + 0.5 * (d2 - d1) / self.k).clamp(0.0, 1.0);
+
+        d2 * (1.0 - h) + d1 * h - self.k * h * (1.0 - h)
+    }
+
+    fn bounds(&self) -> Aabb {
+        // the blend pushes the surface out by at most k / 4
+        let b = surrounding_box(self.a.bounds(), self.b.bounds());
+        let k = Vec3::new(self.k, self.k, self.k) / 4.0;
+        Aabb::new(b.min - k, b.max + k)
+    }
+}
+
+/// Twists a shape around the y axis by `rate` radians per unit of height.
+pub struct Twist {
+    pub sdf: Box<dyn Sdf>,
+    pub rate: f32,
+}
+
+impl Twist {
+    /// Largest distance from the y axis of the untwisted shape.
+    fn radius(&self) -> f32 {
+        let b = self.sdf.bounds();
+        let x = f32::max(f32::abs(b.min.x), f32::abs(b.max.x));
+        let z = f32::max(f32::abs(b.min.z), f32::abs(b.max.z));
+        f32::sqrt(x * x + z * z)
+    }
+}
+
+impl Sdf for Twist {
+    fn distance(&self, p: Point3) -> f32 {
+        let angle = self.rate * p.y;
+        let (s, c) = angle.sin_cos();
+        let q = Point3::new(c * p.x - s * p.z, p.y, s * p.x + c * p.z);
+
+        // Twisting stretches space, so the child's distance can overestimate.
+        // Dividing by the Lipschitz bound of the twist over the shape keeps it
+        // safe.
+        let stretch = self.rate * self.radius();
+        self.sdf.distance(q) / f32::sqrt(1.0 + stretch * stretch)
+    }
+
+    fn bounds(&self) -> Aabb {
+        let b = self.sdf.bounds();
+        let r = self.radius();
+        Aabb::new(Point3::new(-r, b.min.y, -r), Point3::new(r, b.max.y, r))
+    }
+}
+
+/// Repeats a shape on a grid with the given spacing, `count` copies out in
+/// each direction from the original (so 2 * count + 1 copies per axis).
+pub struct Repeat {
+    pub sdf: Box<dyn Sdf>,
+    pub spacing: Vec3,
+    pub count: [i32; 3],
+}
+
+impl Sdf for Repeat {
+    fn distance(&self, p: Point3) -> f32 {
+        let cell = |p: f32, spacing: f32, count: i32| {
+            let n = count as f32;
+            p - spacing * (p / spacing).round().clamp(-n, n)
+        };
+
+        self.sdf.distance(Point3::new(
+            cell(p.x, self.spacing.x, self.count[0]),
+            cell(p.y, self.spacing.y, self.count[1]),
+            cell(p.z, self.spacing.z, self.count[2]),
+        ))
+    }
+
+    fn bounds(&self) -> Aabb {
+        let b = self.sdf.bounds();
+        let extent = Vec3::new(
+            self.spacing.x * self.count[0] as f32,
+            self.spacing.y * self.count[1] as f32,
+            self.spacing.z * self.count[2] as f32,
+        );
+        Aabb::new(b.min - extent, b.max + extent)
+    }
+}
+
+const MAX_STEPS: u32 = 256;
+const HIT_EPSILON: f32 = 0.0001;
+
+/// A surface defined by a signed distance field, rendered by sphere tracing.
+pub struct SdfObject {
+    sdf: Box<dyn Sdf>,
+    bounds: Aabb,
+    mat: Rc<dyn Material>,
+}
+
+impl SdfObject {
+    pub fn new(sdf: impl Sdf + 'static, material: Rc<dyn Material>) -> SdfObject {
+        let bounds = sdf.bounds();
+        SdfObject {
+            sdf: Box::new(sdf),
+            bounds,
+            mat: material,
+        }
+    }
+
+    /// Outward normal from the gradient of the distance field, using the
+    /// tetrahedral central difference.
+    fn normal(&self, p: Point3) -> Vec3 {
+        let h = HIT_EPSILON;
+        let k1 = Vec3::new(1.0, -1.0, -1.0);
+        let k2 = Vec3::new(-1.0, -1.0, 1.0);
+        let k3 = Vec3::new(-1.0, 1.0, -1.0);
+        let k4 = Vec3::new(1.0, 1.0, 1.0);
+
+        (k1 * self.sdf.distance(p + k1 * h)
+            + k2 * self.sdf.distance(p + k2 * h)
+            + k3 * self.sdf.distance(p + k3 * h)
+            + k4 * self.sdf.distance(p + k4 * h))
+        .unit_vector()
+    }
+}
+
+impl Hittable for SdfObject {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32, _rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let (t_enter, t_exit) = self.bounds.hit(&ray, t_min, t_max)?;
+
+        let ray_length = ray.direction.length();
+        let mut t = t_enter;
+
+        // Rays that start inside (refraction) march to the exit, so track
+        // which side we started on and march on the absolute distance.
+        let inside = self.sdf.distance(ray.at(t)) < 0.0;
+        for _ in 0..MAX_STEPS {
+            let mut d = self.sdf.distance(ray.at(t));
+            if inside {
+                d = -d;
+            }
+
+            if d < HIT_EPSILON {
+                let p = ray.at(t);
+                let mut rec = HitRecord {
+                    p,
+                    normal: Vec3::new(0.0, 0.0, 0.0),
+                    u: 0.0,
+                    v: 0.0,
+                    dpdu: Vec3::new(0.0, 0.0, 0.0),
+                    dpdv: Vec3::new(0.0, 0.0, 0.0),
+                    mat: self.mat.clone(),
+                    t,
+                    front_face: false,
+                };
+                rec.set_face_normal(&ray, self.normal(p));
+                return Some(rec);
+            }
+
+            t += d / ray_length;
+            if t > t_exit {
+                return None;
+            }
+        }
+
+        None
+    }
+
+    /// Conservative bounds of the surface.
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::vec3::Color;
+
+    fn material() -> Rc<dyn Material> {
+        Rc::new(Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        })
+    }
+
+    #[test]
+    fn test_sphere_trace_matches_sphere() {
+        let mut rng = rand::thread_rng();
+        let object = SdfObject::new(
+            SdfSphere {
+                center: Point3::new(0.0, 0.0, 0.0),
+                radius: 1.0,
+            },
+            material(),
+        );
+        let ray = Ray {
+            origin: Point3::new(0.0, 0.0, -5.0),
+            direction: Vec3::new(0.0, 0.0, 2.0),
+        };
+
+        let rec = object.hit(ray, 0.0, f32::INFINITY, &mut rng).unwrap();
+        assert!(f32::abs(rec.t - 2.0) < 1e-3);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-2);
+        assert!(rec.front_face);
+
+        // and from the inside out
+        let rec = object
+            .hit(ray, rec.t + 0.01, f32::INFINITY, &mut rng)
+            .unwrap();
+        assert!(f32::abs(rec.t - 3.0) < 1e-3);
+        assert!(!rec.front_face);
+    }
+
+    #[test]
+    fn test_bounds_are_conservative() {
+        let sdf = Twist {
+            sdf: Box::new(SmoothUnion {
+                a: Box::new(RoundBox {
+                    half_extents: Vec3::new(0.5, 1.0, 0.3),
+                    radius: 0.1,
+                }),
+                b: Box::new(Repeat {
+                    sdf: Box::new(SdfSphere {
+                        center: Point3::new(0.0, 0.0, 0.0),
+                        radius: 0.2,
+                    }),
+                    spacing: Vec3::new(0.5, 0.5, 0.5),
+                    count: [1, 2, 1],
+                }),
+                k: 0.3,
+            }),
+            rate: 1.0,
+        };
+        let b = sdf.bounds();
+
+        // everything outside the bounds must be outside the shape
+        let n = 20;
+        for i in 0..=n {
+            for j in 0..=n {
+                for k in 0..=n {
+                    let p = Point3::new(
+                        -3.0 + 6.0 * i as f32 / n as f32,
+                        -3.0 + 6.0 * j as f32 / n as f32,
+                        -3.0 + 6.0 * k as f32 / n as f32,
+                    );
+                    let outside = p.x < b.min.x
+                        || p.y < b.min.y
+                        || p.z < b.min.z
+                        || p.x > b.max.x
+                        || p.y > b.max.y
+                        || p.z > b.max.z;
+                    if outside {
+                        assert!(sdf.distance(p) > 0.0);
+                    }
+                }
+            }
+        }
+    }
+}
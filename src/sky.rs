@@ -0,0 +1,256 @@
+use std::f32::consts::PI;
+
+use crate::environment::DirectionDistribution;
+use crate::light::{Light, LightSample};
+use crate::light_bvh::LightBounds;
+use crate::punctual::DirectionalLight;
+use crate::spectrum;
+use crate::vec3::{Color, Point3, Vec3};
+
+/// The sun's angular diameter in degrees.
+const SUN_ANGULAR_DIAMETER: f32 = 0.53;
+
+/// Sky luminance in kcd/m^2 is multiplied by this to give radiance in the
+/// renderer's units, so a clear midday sky comes out around a half.
+const LUMINANCE_SCALE: f32 = 0.05;
+
+/// How much of the sky the ground below the horizon reflects.
+const GROUND_ALBEDO: f32 = 0.2;
+
+/// Coefficients of the Perez sky luminance distribution for one of Y, x
+/// and y.
+#[derive(Copy, Clone, Debug)]
+struct Perez {
+    a: f32,
+    b: f32,
+    c: f32,
+    d: f32,
+    e: f32,
+}
+
+impl Perez {
+    /// The distribution at angle theta from the zenith and gamma from the
+    /// sun.
+    fn f(&self, cos_theta: f32, gamma: f32) -> f32 {
+        // the horizon would divide by zero
+        let cos_theta = f32::max(cos_theta, 0.01);
+        let cos_gamma = f32::cos(gamma);
+        (1.0 + self.a * f32::exp(self.b / cos_theta))
+            * (1.0 + self.c * f32::exp(self.d * gamma) + self.e * cos_gamma * cos_gamma)
+    }
+}
+
+/// The clear sky model from Preetham, Shirley and Smits, "A Practical
+/// Analytic Model for Daylight", 1999. Turbidity is the haziness of the
+/// air, 2 for a very clear day up to about 10 for a hazy one.
+#[derive(Copy, Clone, Debug)]
+pub struct Sky {
+    /// Unit vector toward the sun.
+    pub sun_direction: Vec3,
+    pub turbidity: f32,
+    /// Zenith luminance and chromaticity.
+    zenith: (f32, f32, f32),
+    perez: [Perez; 3],
+}
+
+impl Sky {
+    /// A sky with the sun `elevation` degrees above the horizon. The
+    /// azimuth is in degrees from +x toward +z.
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32) -> Sky {
+        // the model breaks down below the horizon and for very clear air
+        let elevation = elevation.clamp(0.0, 90.0).to_radians();
+        let azimuth = azimuth.to_radians();
+        let t = turbidity.clamp(1.7, 20.0);
+        let sun_direction = Vec3::new(
+            f32::cos(elevation) * f32::cos(azimuth),
+            f32::sin(elevation),
+            f32::cos(elevation) * f32::sin(azimuth),
+        );
+
+        let theta_s = PI / 2.0 - elevation;
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * f32::tan(chi) - 0.2155 * t + 2.4192;
+        let (t2, t3) = (theta_s * theta_s, theta_s * theta_s * theta_s);
+        let zenith_x = t * t * (0.00166 * t3 - 0.00375 * t2 + 0.00209 * theta_s)
+            + t * (-0.02903 * t3 + 0.06377 * t2 - 0.03202 * theta_s + 0.00394)
+            + (0.11693 * t3 - 0.21196 * t2 + 0.06052 * theta_s + 0.25886);
+        let zenith_y = t * t * (0.00275 * t3 - 0.00610 * t2 + 0.00317 * theta_s)
+            + t * (-0.04214 * t3 + 0.08970 * t2 - 0.04153 * theta_s + 0.00516)
+            + (0.15346 * t3 - 0.26756 * t2 + 0.06670 * theta_s + 0.26688);
+
+        let perez = [
+            Perez {
+                a: 0.1787 * t - 1.4630,
+                b: -0.3554 * t + 0.4275,
+                c: -0.0227 * t + 5.3251,
+                d: 0.1206 * t - 2.5771,
+                e: -0.0670 * t + 0.3703,
+            },
+            Perez {
+                a: -0.0193 * t - 0.2592,
+                b: -0.0665 * t + 0.0008,
+                c: -0.0004 * t + 0.2125,
+                d: -0.0641 * t - 0.8989,
+                e: -0.0033 * t + 0.0452,
+            },
+            Perez {
+                a: -0.0167 * t - 0.2608,
+                b: -0.0950 * t + 0.0092,
+                c: -0.0079 * t + 0.2102,
+                d: -0.0441 * t - 1.6537,
+                e: -0.0109 * t + 0.0529,
+            },
+        ];
+
+        Sky {
+            sun_direction,
+            turbidity: t,
+            zenith: (zenith_luminance, zenith_x, zenith_y),
+            perez,
+        }
+    }
+
+    /// Light arriving from the sky along `direction`, not counting the
+    /// sun's disc. Below the horizon the ground gives back a little of the
+    /// sky above it.
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        let w = direction.unit_vector();
+        if w.y < 0.0 {
+            return self.radiance(Vec3::new(w.x, -w.y, w.z)) * GROUND_ALBEDO;
+        }
+
+        let cos_theta_s = self.sun_direction.y;
+        let theta_s = f32::acos(cos_theta_s);
+        let gamma = f32::acos((w * self.sun_direction).clamp(-1.0, 1.0));
+        let relative = |perez: &Perez| perez.f(w.y, gamma) / perez.f(1.0, theta_s);
+
+        let luminance = self.zenith.0 * relative(&self.perez[0]) * LUMINANCE_SCALE;
+        let x = self.zenith.1 * relative(&self.perez[1]);
+        let y = self.zenith.2 * relative(&self.perez[2]);
+
+        let xyz = Vec3::new(x * luminance / y, luminance, (1.0 - x - y) * luminance / y);
+        let rgb = spectrum::xyz_to_linear_srgb(xyz);
+        Color::new(
+            f32::max(rgb.x, 0.0),
+            f32::max(rgb.y, 0.0),
+            f32::max(rgb.z, 0.0),
+        )
+    }
+
+    /// Light from the sun on a surface facing it, after passing through
+    /// the atmosphere. Follows the paper's appendix for Rayleigh and haze
+    /// scattering at a wavelength for each channel, leaving out ozone and
+    /// water vapour.
+    pub fn sun_irradiance(&self) -> Color {
+        let elevation = f32::asin(self.sun_direction.y).to_degrees();
+        let theta_s = 90.0 - elevation;
+        // relative optical path length through the air
+        let air_mass =
+            1.0 / (f32::cos(theta_s.to_radians()) + 0.15 * f32::powf(93.885 - theta_s, -1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+
+        let transmittance = |lambda_um: f32| {
+            let rayleigh = f32::exp(-0.008735 * f32::powf(lambda_um, -4.08) * air_mass);
+            let haze = f32::exp(-beta * f32::powf(lambda_um, -1.3) * air_mass);
+            rayleigh * haze
+        };
+
+        // sunlight above the atmosphere, in the same units as the sky
+        let outside = Color::new(6.5, 6.8, 6.6);
+        Color::new(
+            outside.x * transmittance(0.68),
+            outside.y * transmittance(0.55),
+            outside.z * transmittance(0.44),
+        )
+    }
+
+    /// The sun as a light, a disc of the sun's size.
+    pub fn sun(&self) -> DirectionalLight {
+        DirectionalLight::new(
+            self.sun_direction,
+            self.sun_irradiance(),
+            SUN_ANGULAR_DIAMETER,
+        )
+    }
+
+    /// The sky as a light, with directions picked in proportion to its
+    /// brightness.
+    pub fn light(self) -> SkyLight {
+        let distribution = DirectionDistribution::new(256, 128, |u, t| {
+            let c = self.radiance(DirectionDistribution::direction(u, t));
+            (c.x + c.y + c.z) / 3.0
+        });
+        SkyLight {
+            sky: self,
+            distribution,
+        }
+    }
+}
+
+/// Light from a `Sky`, sampled through a coarse picture of it.
+pub struct SkyLight {
+    sky: Sky,
+    distribution: DirectionDistribution,
+}
+
+impl Light for SkyLight {
+    fn sample(&self, _origin: Point3, u1: f32, u2: f32) -> Option<LightSample> {
+        self.distribution.sample(u1, u2)
+    }
+
+    fn pdf(&self, _origin: Point3, direction: Vec3) -> f32 {
+        self.distribution.pdf(direction)
+    }
+
+    fn escaped(&self, direction: Vec3) -> Color {
+        self.sky.radiance(direction)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sky_radiance() {
+        let sky = Sky::new(30.0, 0.0, 3.0);
+        let up = sky.radiance(Vec3::new(0.0, 1.0, 0.0));
+        assert!(up.y > 0.1 && up.y < 10.0);
+        // blue overhead
+        assert!(up.z > up.x);
+
+        // brighter around the sun than away from it
+        let near_sun = sky.radiance(Vec3::new(1.0, 0.7, 0.1));
+        let away = sky.radiance(Vec3::new(-1.0, 0.7, 0.1));
+        assert!(near_sun.y > away.y);
+
+        let down = sky.radiance(Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!(down, up * GROUND_ALBEDO);
+    }
+
+    #[test]
+    fn test_sun_irradiance() {
+        // the sun reddens and dims toward the horizon and in haze
+        let noon = Sky::new(80.0, 0.0, 3.0).sun_irradiance();
+        let evening = Sky::new(5.0, 0.0, 3.0).sun_irradiance();
+        let hazy = Sky::new(80.0, 0.0, 8.0).sun_irradiance();
+        assert!(evening.y < noon.y && hazy.y < noon.y);
+        assert!(evening.x / evening.z > noon.x / noon.z);
+        assert!(noon.z > 0.0);
+    }
+
+    #[test]
+    fn test_sky_light() {
+        let light = Sky::new(30.0, 0.0, 3.0).light();
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        for i in 0..10 {
+            let sample = light.sample(origin, (i as f32 + 0.5) / 10.0, 0.37).unwrap();
+            let pdf = light.pdf(origin, sample.direction);
+            assert!(f32::abs(sample.pdf / pdf - 1.0) < 1e-3);
+        }
+    }
+}
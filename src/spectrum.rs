@@ -0,0 +1,306 @@
+use std::sync::OnceLock;
+
+use crate::vec3::{Color, Vec3};
+
+/// Visible range we sample wavelengths from, in nanometers.
+pub const LAMBDA_MIN: f32 = 380.0;
+pub const LAMBDA_MAX: f32 = 780.0;
+
+/// The wavelengths carried by one path in spectral mode. The first is the
+/// hero wavelength, sampled uniformly, the others are spread evenly across
+/// the range from it. Radiance along the path is a Vec3 holding one value per
+/// wavelength instead of RGB.
+#[derive(Copy, Clone, Debug)]
+pub struct SampledWavelengths {
+    pub lambda: [f32; 3],
+    /// Set once only the hero wavelength is left, see `terminate_secondary`.
+    pub secondary_terminated: bool,
+}
+
+impl SampledWavelengths {
+    /// `u` is uniform in [0, 1).
+    pub fn sample(u: f32) -> SampledWavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut lambda = [0.0; 3];
+        for (i, l) in lambda.iter_mut().enumerate() {
+            let offset = (u + i as f32 / 3.0).fract();
+            *l = LAMBDA_MIN + offset * range;
+        }
+
+        SampledWavelengths {
+            lambda,
+            secondary_terminated: false,
+        }
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    /// After an event that depends on wavelength (like dispersion) only the
+    /// hero wavelength can carry on. Returns the wavelengths for the rest of
+    /// the path and the weight for radiance from further along it. The hero
+    /// stands in for all three, so it's weighted by three, but only the
+    /// first time.
+    pub fn terminate_secondary(self) -> (SampledWavelengths, Vec3) {
+        let weight = if self.secondary_terminated {
+            Vec3::new(1.0, 0.0, 0.0)
+        } else {
+            Vec3::new(3.0, 0.0, 0.0)
+        };
+        let wavelengths = SampledWavelengths {
+            secondary_terminated: true,
+            ..self
+        };
+
+        (wavelengths, weight)
+    }
+
+    /// Values of an RGB reflectance (or light color) at each wavelength.
+    pub fn rgb_values(&self, rgb: Color) -> Vec3 {
+        Vec3::new(
+            rgb_to_spectrum(rgb, self.lambda[0]),
+            rgb_to_spectrum(rgb, self.lambda[1]),
+            rgb_to_spectrum(rgb, self.lambda[2]),
+        )
+    }
+
+    /// Convert radiance at the sampled wavelengths to linear sRGB, through
+    /// CIE XYZ. This is the Monte Carlo estimate of the integral of the
+    /// radiance against the color matching functions.
+    pub fn to_rgb(self, radiance: Vec3) -> Color {
+        let pdf = 1.0 / (LAMBDA_MAX - LAMBDA_MIN);
+        let values = [radiance.x, radiance.y, radiance.z];
+
+        let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+        for (l, v) in self.lambda.iter().zip(values) {
+            xyz += cie_xyz(*l) * (v / pdf);
+        }
+        xyz = xyz / 3.0;
+
+        let rgb = xyz_to_linear_srgb(xyz);
+        let white = white_point();
+        Color::new(rgb.x / white.x, rgb.y / white.y, rgb.z / white.z)
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Upsample an RGB color to a spectrum and evaluate it at `lambda`. Each
+/// channel is spread over its part of the spectrum with smooth transitions
+/// between them. The three basis functions add up to one everywhere, so
+/// white is a flat spectrum and reflectances stay in [0, 1]. Saturated colors
+/// come back a little desaturated.
+pub fn rgb_to_spectrum(rgb: Color, lambda: f32) -> f32 {
+    let red = smoothstep(570.0, 610.0, lambda);
+    let blue = 1.0 - smoothstep(480.0, 510.0, lambda);
+    let green = 1.0 - red - blue;
+
+    rgb.x * red + rgb.y * green + rgb.z * blue
+}
+
+/// A lobe of the fitted color matching functions, a Gaussian with different
+/// widths on either side of the peak.
+fn lobe(lambda: f32, mu: f32, sigma_below: f32, sigma_above: f32) -> f32 {
+    let sigma = if lambda < mu {
+        sigma_below
+    } else {
+        sigma_above
+    };
+    let t = (lambda - mu) / sigma;
+    f32::exp(-0.5 * t * t)
+}
+
+/// CIE 1931 standard observer color matching functions, using the multi
+/// lobe fit from Wyman, Sloan and Shirley 2013.
+pub fn cie_xyz(lambda: f32) -> Vec3 {
+    let x = 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8);
+
+    Vec3::new(x, y, z)
+}
+
+pub fn xyz_to_linear_srgb(xyz: Vec3) -> Color {
+    Color::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+}
+
+/// RGB of a flat, equal energy spectrum. Dividing by this white balances
+/// the film so that white surfaces under white light stay white.
+fn white_point() -> Color {
+    static WHITE: OnceLock<Color> = OnceLock::new();
+
+    *WHITE.get_or_init(|| {
+        let steps = 1000;
+        let d_lambda = (LAMBDA_MAX - LAMBDA_MIN) / steps as f32;
+        let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..steps {
+            let lambda = LAMBDA_MIN + (i as f32 + 0.5) * d_lambda;
+            xyz += cie_xyz(lambda) * d_lambda;
+        }
+
+        xyz_to_linear_srgb(xyz)
+    })
+}
+
+/// Linear sRGB of a reflectance spectrum under equal energy light. A flat
+/// spectrum comes out the same in every channel. Colors outside of sRGB
+/// are clipped.
+pub fn reflectance_to_rgb(reflectance: impl Fn(f32) -> f32) -> Color {
+    let steps = 32;
+    let d_lambda = (LAMBDA_MAX - LAMBDA_MIN) / steps as f32;
+    let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+    for i in 0..steps {
+        let lambda = LAMBDA_MIN + (i as f32 + 0.5) * d_lambda;
+        xyz += cie_xyz(lambda) * (reflectance(lambda) * d_lambda);
+    }
+
+    let rgb = xyz_to_linear_srgb(xyz);
+    let white = white_point();
+    Color::new(
+        f32::max(rgb.x / white.x, 0.0),
+        f32::max(rgb.y / white.y, 0.0),
+        f32::max(rgb.z / white.z, 0.0),
+    )
+}
+
+/// Wavelength dependent index of refraction. Wavelengths are in nanometers
+/// here, but the coefficients use micrometers as is conventional.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Dispersion {
+    /// n = a + b / lambda^2
+    Cauchy { a: f32, b: f32 },
+    /// n^2 = 1 + sum of b_i lambda^2 / (lambda^2 - c_i)
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    /// Schott BK7, common optical crown glass.
+    pub fn bk7() -> Dispersion {
+        Dispersion::Sellmeier {
+            b: [1.039_612, 0.231_792_34, 1.010_469_4],
+            c: [0.006_000_699, 0.020_017_914, 103.560_65],
+        }
+    }
+
+    /// Schott SF11, a dense flint glass with strong dispersion.
+    pub fn sf11() -> Dispersion {
+        Dispersion::Sellmeier {
+            b: [1.737_596_9, 0.313_747_35, 1.898_781],
+            c: [0.013_188_707, 0.062_306_814, 155.236_3],
+        }
+    }
+
+    pub fn ior(&self, lambda_nm: f32) -> f32 {
+        let l = lambda_nm / 1000.0;
+        let l2 = l * l;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let n2 = 1.0 + b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum::<f32>();
+                f32::sqrt(n2)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_terminate_secondary_once() {
+        let wavelengths = SampledWavelengths::sample(0.3);
+        let (terminated, weight) = wavelengths.terminate_secondary();
+        assert_eq!(weight, Vec3::new(3.0, 0.0, 0.0));
+        assert_eq!(terminated.hero(), wavelengths.hero());
+
+        // a second dispersive event mustn't count the hero three times again
+        let (_, weight) = terminated.terminate_secondary();
+        assert_eq!(weight, Vec3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_white_round_trip() {
+        // a flat spectrum comes out white
+        let n = 3000;
+        let mut rgb = Color::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+            let wavelengths = SampledWavelengths::sample((i as f32 + 0.5) / n as f32);
+            let radiance = wavelengths.rgb_values(Color::new(1.0, 1.0, 1.0));
+            rgb += wavelengths.to_rgb(radiance);
+        }
+        rgb = rgb / n as f32;
+
+        assert!(
+            (rgb - Color::new(1.0, 1.0, 1.0)).length() < 1e-2,
+            "{:?}",
+            rgb
+        );
+    }
+
+    #[test]
+    fn test_primaries_keep_their_hue() {
+        let n = 3000;
+        for (i, primary) in [
+            Color::new(1.0, 0.0, 0.0),
+            Color::new(0.0, 1.0, 0.0),
+            Color::new(0.0, 0.0, 1.0),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let mut rgb = Color::new(0.0, 0.0, 0.0);
+            for j in 0..n {
+                let wavelengths = SampledWavelengths::sample((j as f32 + 0.5) / n as f32);
+                rgb += wavelengths.to_rgb(wavelengths.rgb_values(*primary));
+            }
+            rgb = rgb / n as f32;
+
+            let channels = [rgb.x, rgb.y, rgb.z];
+            for (k, c) in channels.iter().enumerate() {
+                if k != i {
+                    assert!(channels[i] > *c, "{:?} {:?}", primary, rgb);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_reflectance_to_rgb() {
+        let grey = reflectance_to_rgb(|_| 0.5);
+        assert!((grey - Color::new(0.5, 0.5, 0.5)).length() < 0.02, "{:?}", grey);
+
+        let red = reflectance_to_rgb(|lambda| if lambda > 600.0 { 1.0 } else { 0.0 });
+        assert!(red.x > 0.5 && red.y < 0.1 && red.z < 0.1, "{:?}", red);
+    }
+
+    #[test]
+    fn test_wavelengths_in_range() {
+        for u in [0.0, 0.3, 0.999] {
+            let wavelengths = SampledWavelengths::sample(u);
+            for l in wavelengths.lambda {
+                assert!((LAMBDA_MIN..LAMBDA_MAX).contains(&l));
+            }
+        }
+    }
+
+    #[test]
+    fn test_dispersion() {
+        // BK7 is 1.5168 at the sodium d line and higher toward blue
+        let bk7 = Dispersion::bk7();
+        assert!(f32::abs(bk7.ior(587.6) - 1.5168) < 1e-3);
+        assert!(bk7.ior(450.0) > bk7.ior(650.0));
+
+        let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.01 };
+        assert!(f32::abs(cauchy.ior(500.0) - 1.54) < 1e-5);
+    }
+}
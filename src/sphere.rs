@@ -1,10 +1,17 @@
-use crate::material::Material;
-use crate::vec3::{Vec3, Point3};
+use rand::RngCore;
+
+use crate::aabb::Aabb;
 use crate::hittable::Hittable;
-use crate::rayhit::{Ray, HitRecord};
+use crate::light::{self, Light, LightSample};
+use crate::light_bvh::LightBounds;
+use crate::material::Material;
+use crate::onb::Onb;
+use crate::rayhit::{HitRecord, Ray};
+use crate::vec3::{Point3, Vec3};
 
 use std::rc::Rc;
 
+#[derive(Clone)]
 pub struct Sphere {
     pub center: Point3,
     pub radius: f32,
@@ -19,10 +26,51 @@ impl Sphere {
             mat: material,
         }
     }
+
+    /// Texture coordinates of a point on the unit sphere. u goes around the
+    /// y axis starting from -x, v from the south pole to the north.
+    fn uv(p: Point3) -> (f32, f32) {
+        let theta = f32::acos(-p.y.clamp(-1.0, 1.0));
+        let phi = f32::atan2(-p.z, p.x) + std::f32::consts::PI;
+
+        (
+            phi / (2.0 * std::f32::consts::PI),
+            theta / std::f32::consts::PI,
+        )
+    }
+
+    /// Derivatives of the point on the surface with respect to u and v, for
+    /// the point `p` on the unit sphere. Zero at the poles, where they're
+    /// undefined.
+    fn tangents(&self, p: Point3) -> (Vec3, Vec3) {
+        let sin_theta = f32::sqrt(p.x * p.x + p.z * p.z);
+        if sin_theta < 1e-6 {
+            return (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        }
+
+        let pi = std::f32::consts::PI;
+        let dpdu = Vec3::new(p.z, 0.0, -p.x) * (2.0 * pi * self.radius);
+        let dpdv = Vec3::new(-p.x * p.y / sin_theta, sin_theta, -p.y * p.z / sin_theta)
+            * (pi * self.radius);
+
+        (dpdu, dpdv)
+    }
+
+    /// Sine squared of the half angle of the cone the sphere fills, seen
+    /// from `origin`. None from inside the sphere.
+    fn sin2_theta_max(&self, origin: Point3) -> Option<f32> {
+        let distance_squared = (self.center - origin).length_squared();
+        let sin2_theta_max = self.radius * self.radius / distance_squared;
+        if sin2_theta_max >= 1.0 {
+            return None;
+        }
+
+        Some(sin2_theta_max)
+    }
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32, _rng: &mut dyn RngCore) -> Option<HitRecord> {
         let oc: Vec3 = ray.origin - self.center;
         let a = ray.direction.length_squared();
         let half_b = oc * ray.direction;
@@ -45,11 +93,17 @@ impl Hittable for Sphere {
         }
 
         let at_ray = ray.at(root);
+        let (u, v) = Sphere::uv((at_ray - self.center) / self.radius);
+        let (dpdu, dpdv) = self.tangents((at_ray - self.center) / self.radius);
         let mut rec = HitRecord {
             t: root,
             p: at_ray,
             mat: self.mat.clone(),
             normal: (at_ray - self.center) / self.radius,
+            u,
+            v,
+            dpdu,
+            dpdv,
             front_face: false,
         };
 
@@ -59,4 +113,118 @@ impl Hittable for Sphere {
 
         Some(rec)
     }
-}
\ No newline at end of file
+
+    fn light(&self) -> Option<Rc<dyn Light>> {
+        if self.mat.is_emissive() {
+            Some(Rc::new(self.clone()))
+        } else {
+            None
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+}
+
+/// Spheres are sampled uniformly over the cone of directions they cover.
+/// Only the visible cap can be reached, so none of the samples are wasted
+/// on the far side.
+impl Light for Sphere {
+    fn sample(&self, origin: Point3, u1: f32, u2: f32) -> Option<LightSample> {
+        let sin2_theta_max = self.sin2_theta_max(origin)?;
+        let uvw = Onb::build_from_w(self.center - origin);
+        let w = light::sample_cone(sin2_theta_max, u1, u2);
+
+        Some(LightSample {
+            direction: uvw.local(w.x, w.y, w.z),
+            pdf: light::cone_pdf(sin2_theta_max),
+            delta: None,
+        })
+    }
+
+    fn pdf(&self, origin: Point3, direction: Vec3) -> f32 {
+        let sin2_theta_max = match self.sin2_theta_max(origin) {
+            Some(sin2_theta_max) => sin2_theta_max,
+            None => return 0.0,
+        };
+
+        // Whether the direction is in the cone, by how close the ray passes
+        // to the center. Comparing cosines isn't precise enough for small
+        // cones.
+        let direction = direction.unit_vector();
+        let to_center = self.center - origin;
+        let miss_squared = to_center.cross(direction).length_squared();
+        if to_center * direction <= 0.0 || miss_squared > self.radius * self.radius {
+            return 0.0;
+        }
+
+        light::cone_pdf(sin2_theta_max)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let top = self.center + Vec3::new(0.0, self.radius, 0.0);
+        let radiance = light::surface_radiance(&self.mat, top, Vec3::new(0.0, 1.0, 0.0));
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        let area = 4.0 * std::f32::consts::PI * self.radius * self.radius;
+
+        // shines every which way, out to the horizon of each point
+        Some(LightBounds {
+            bounds: Aabb::new(self.center - r, self.center + r),
+            power: std::f32::consts::PI * area * radiance,
+            axis: Vec3::new(0.0, 0.0, 1.0),
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::DiffuseLight;
+    use crate::vec3::Color;
+
+    #[test]
+    fn test_sphere_light() {
+        let mut rng = rand::thread_rng();
+        let lamp = Sphere::new(
+            Point3::new(0.0, 3.0, 0.0),
+            1.0,
+            Rc::new(DiffuseLight {
+                emit: Color::new(1.0, 1.0, 1.0),
+            }),
+        );
+        let light = lamp.light().unwrap();
+        let origin = Point3::new(0.0, 0.0, 0.0);
+
+        // every sample hits the sphere, with the pdf it says it has
+        for i in 0..100 {
+            let sample = light.sample(origin, i as f32 / 100.0, 0.61).unwrap();
+            let ray = Ray {
+                origin,
+                direction: sample.direction,
+            };
+            assert!(lamp.hit(ray, 0.0, f32::INFINITY, &mut rng).is_some());
+            assert_eq!(sample.pdf, light.pdf(origin, sample.direction));
+        }
+
+        // the cone covers 2 pi (1 - cos theta max) steradians
+        let solid_angle = 2.0 * std::f32::consts::PI * (1.0 - f32::sqrt(8.0) / 3.0);
+        let pdf = light.pdf(origin, Vec3::new(0.0, 1.0, 0.0));
+        assert!(f32::abs(pdf * solid_angle - 1.0) < 1e-3);
+
+        assert_eq!(light.pdf(origin, Vec3::new(0.0, -1.0, 0.0)), 0.0);
+        assert!(light.sample(Point3::new(0.0, 3.0, 0.0), 0.5, 0.5).is_none());
+
+        let lambertian = Sphere::new(
+            origin,
+            1.0,
+            Rc::new(crate::material::Lambertian {
+                albedo: Color::new(0.5, 0.5, 0.5),
+            }),
+        );
+        assert!(lambertian.light().is_none());
+    }
+}
@@ -0,0 +1,338 @@
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::vec3::{Color, Point3};
+
+/// A color that varies over a surface, looked up by texture coordinates or
+/// by position. Scalar parameters use the first channel.
+pub trait Texture {
+    fn value(&self, u: f32, v: f32, p: Point3) -> Color;
+}
+
+/// A plain color is a constant texture.
+impl Texture for Color {
+    fn value(&self, _u: f32, _v: f32, _p: Point3) -> Color {
+        *self
+    }
+}
+
+/// And so is a plain number, in every channel.
+impl Texture for f32 {
+    fn value(&self, _u: f32, _v: f32, _p: Point3) -> Color {
+        Color::new(*self, *self, *self)
+    }
+}
+
+/// Checkerboard in texture space with `columns` squares across u and `rows`
+/// across v.
+pub struct Checker {
+    pub columns: u32,
+    pub rows: u32,
+    pub even: Rc<dyn Texture>,
+    pub odd: Rc<dyn Texture>,
+}
+
+impl Checker {
+    pub fn new(
+        columns: u32,
+        rows: u32,
+        even: impl Texture + 'static,
+        odd: impl Texture + 'static,
+    ) -> Checker {
+        Checker {
+            columns,
+            rows,
+            even: Rc::new(even),
+            odd: Rc::new(odd),
+        }
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f32, v: f32, p: Point3) -> Color {
+        let column = (u * self.columns as f32).floor() as i32;
+        let row = (v * self.rows as f32).floor() as i32;
+
+        if (column + row) % 2 == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+/// A texture from an image, repeating outside [0, 1] and filtered
+/// bilinearly. v runs from the bottom of the image to the top.
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    /// Rows from the top.
+    pixels: Vec<Color>,
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> ImageTexture {
+        assert_eq!(pixels.len(), width * height);
+        ImageTexture {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Load a PNG. Color images are usually sRGB encoded, pass `srgb` to
+    /// convert them to linear. Data like normal maps should be used as is.
+    pub fn load(path: &Path, srgb: bool) -> Result<ImageTexture, Error> {
+        let invalid = |e: png::DecodingError| Error::new(ErrorKind::InvalidData, e);
+
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        // palettes and low bit depths to 8 bits per channel
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(invalid)?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(invalid)?;
+
+        let channels = info.color_type.samples();
+        let decode = |b: u8| {
+            let c = b as f32 / 255.0;
+            if !srgb {
+                c
+            } else if c <= 0.04045 {
+                c / 12.92
+            } else {
+                f32::powf((c + 0.055) / 1.055, 2.4)
+            }
+        };
+
+        let pixels = buf[..info.buffer_size()]
+            .chunks(channels)
+            .map(|px| match channels {
+                // grey, with or without alpha
+                1 | 2 => Color::new(decode(px[0]), decode(px[0]), decode(px[0])),
+                _ => Color::new(decode(px[0]), decode(px[1]), decode(px[2])),
+            })
+            .collect();
+
+        Ok(ImageTexture::new(
+            info.width as usize,
+            info.height as usize,
+            pixels,
+        ))
+    }
+
+    /// Load a Radiance .hdr image, which holds linear light with no upper
+    /// limit.
+    pub fn load_hdr(path: &Path) -> Result<ImageTexture, Error> {
+        decode_hdr(&fs::read(path)?)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The texel in column x and row y from the top, wrapping around.
+    pub fn texel(&self, x: i64, y: i64) -> Color {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _p: Point3) -> Color {
+        // texel centers are at half integer coordinates
+        let x = u * self.width as f32 - 0.5;
+        let y = (1.0 - v) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(x0, y0) * (1.0 - fx) + self.texel(x0 + 1, y0) * fx;
+        let bottom = self.texel(x0, y0 + 1) * (1.0 - fx) + self.texel(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+fn invalid_hdr(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("bad .hdr image: {}", msg))
+}
+
+/// Decode a Radiance .hdr image: a text header, a line giving the size, then
+/// rows of RGBE pixels, each row either flat or run length encoded a channel
+/// at a time. Only the usual top to bottom, left to right layout is read.
+fn decode_hdr(data: &[u8]) -> Result<ImageTexture, Error> {
+    let mut pos = 0;
+    let mut next_line = || -> Result<String, Error> {
+        let end = data[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| invalid_hdr("header ends early"))?;
+        let line = String::from_utf8_lossy(&data[pos..pos + end]).into_owned();
+        pos += end + 1;
+        Ok(line)
+    };
+
+    if !next_line()?.starts_with("#?") {
+        return Err(invalid_hdr("missing #? signature"));
+    }
+    // the header runs until a blank line
+    loop {
+        let line = next_line()?;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid_hdr(&line));
+        }
+    }
+
+    let size = next_line()?;
+    let (height, width) = match size.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", h, "+X", w] => match (h.parse::<usize>(), w.parse::<usize>()) {
+            (Ok(h), Ok(w)) if h > 0 && w > 0 => (h, w),
+            _ => return Err(invalid_hdr(&size)),
+        },
+        _ => return Err(invalid_hdr(&size)),
+    };
+
+    // The smallest a row can be is runs of 127 in each channel, so a file
+    // too short for the size it claims is caught before allocating for it.
+    let min_row = usize::min(width.saturating_mul(4), 4 + 8 * width.div_ceil(127));
+    let fits = width.checked_mul(height).is_some()
+        && min_row
+            .checked_mul(height)
+            .is_some_and(|n| n <= data.len() - pos);
+    if !fits {
+        return Err(invalid_hdr(&format!("{} doesn't fit in the file", size)));
+    }
+
+    let mut bytes = data[pos..].iter().copied();
+    let mut next = || bytes.next().ok_or_else(|| invalid_hdr("pixels end early"));
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut row = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        let start = [next()?, next()?, next()?, next()?];
+        let rle = (8..0x8000).contains(&width)
+            && start[0] == 2
+            && start[1] == 2
+            && start[2] < 0x80
+            && ((start[2] as usize) << 8 | start[3] as usize) == width;
+
+        if rle {
+            for channel in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = next()? as usize;
+                    let (count, run) = if count > 128 {
+                        (count - 128, Some(next()?))
+                    } else {
+                        (count, None)
+                    };
+                    if count == 0 || x + count > width {
+                        return Err(invalid_hdr("bad run length"));
+                    }
+                    for px in &mut row[x..x + count] {
+                        px[channel] = match run {
+                            Some(b) => b,
+                            None => next()?,
+                        };
+                    }
+                    x += count;
+                }
+            }
+        } else {
+            row[0] = start;
+            for px in &mut row[1..] {
+                *px = [next()?, next()?, next()?, next()?];
+            }
+        }
+
+        pixels.extend(row.iter().map(|&[r, g, b, e]| {
+            if e == 0 {
+                return Color::new(0.0, 0.0, 0.0);
+            }
+            // the mantissas are fractions of 256
+            let scale = f32::powi(2.0, e as i32 - 136);
+            Color::new(r as f32 * scale, g as f32 * scale, b as f32 * scale)
+        }));
+    }
+
+    Ok(ImageTexture::new(width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checker() {
+        let checker = Checker::new(4, 2, 1.0, Color::new(0.0, 0.5, 1.0));
+        let p = Point3::new(0.0, 0.0, 0.0);
+
+        assert_eq!(checker.value(0.1, 0.1, p), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(checker.value(0.3, 0.1, p), Color::new(0.0, 0.5, 1.0));
+        assert_eq!(checker.value(0.3, 0.6, p), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(checker.value(0.9, 0.1, p), Color::new(0.0, 0.5, 1.0));
+    }
+
+    #[test]
+    fn test_image_texture() {
+        // black on the left, white on the right, top row red
+        let black = Color::new(0.0, 0.0, 0.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let red = Color::new(1.0, 0.0, 0.0);
+        let image = ImageTexture::new(2, 2, vec![red, red, black, white]);
+        let p = Point3::new(0.0, 0.0, 0.0);
+
+        // texel centers
+        assert_eq!(image.value(0.25, 0.25, p), black);
+        assert_eq!(image.value(0.75, 0.25, p), white);
+        assert_eq!(image.value(0.25, 0.75, p), red);
+
+        // halfway between, and wrapping around
+        assert!((image.value(0.5, 0.25, p) - white * 0.5).length() < 1e-6);
+        assert!((image.value(1.25, -0.75, p) - black).length() < 1e-6);
+        assert!((image.value(0.0, 0.25, p) - white * 0.5).length() < 1e-6);
+    }
+
+    #[test]
+    fn test_decode_hdr() {
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n";
+
+        // a flat row of 1s and 2s, then a run length encoded row of 0.5 and
+        // black
+        let mut data = header.to_vec();
+        for x in 0..8 {
+            data.extend(if x < 4 {
+                [128, 128, 128, 129]
+            } else {
+                [128, 128, 128, 130]
+            });
+        }
+        data.extend([2, 2, 0, 8]);
+        for _channel in 0..4 {
+            // a run of six, then two as they are
+            data.extend([128 + 6, 128, 2, 0, 0]);
+        }
+
+        let image = decode_hdr(&data).unwrap();
+        assert_eq!((image.width(), image.height()), (8, 2));
+        assert_eq!(image.texel(0, 0), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(image.texel(7, 0), Color::new(2.0, 2.0, 2.0));
+        assert_eq!(image.texel(3, 1), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(image.texel(7, 1), Color::new(0.0, 0.0, 0.0));
+
+        assert!(decode_hdr(b"#?RADIANCE\n\n-Y 2 +X 8\n").is_err());
+        assert!(decode_hdr(b"P3\n").is_err());
+        // far bigger than the file, or than memory
+        assert!(decode_hdr(b"#?RADIANCE\n\n-Y 99999999 +X 99999999\n\x02\x02").is_err());
+        let huge = format!("#?RADIANCE\n\n-Y {} +X {}\n", usize::MAX, usize::MAX);
+        assert!(decode_hdr(huge.as_bytes()).is_err());
+    }
+}
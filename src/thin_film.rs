@@ -0,0 +1,507 @@
+use std::f32::consts::PI;
+use std::ops::{Add, Div, Mul, Sub};
+
+use rand::{Rng, RngCore};
+
+use crate::material::Material;
+use crate::medium::Interior;
+use crate::microfacet::{self, Ggx};
+use crate::rayhit::{HitRecord, Ray};
+use crate::spectrum;
+use crate::vec3::{Color, Vec3};
+
+/// Just enough complex arithmetic for Fresnel amplitudes. Complex indices
+/// of refraction describe conductors, complex cosines total internal
+/// reflection.
+#[derive(Copy, Clone, Debug)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    fn new(re: f32, im: f32) -> Complex {
+        Complex { re, im }
+    }
+
+    fn real(re: f32) -> Complex {
+        Complex { re, im: 0.0 }
+    }
+
+    fn norm_sqr(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    /// Principal square root, with a non-negative real part.
+    fn sqrt(self) -> Complex {
+        let r = f32::sqrt(self.norm_sqr());
+        let re = f32::sqrt(f32::max(0.0, (r + self.re) / 2.0));
+        let im = f32::sqrt(f32::max(0.0, (r - self.re) / 2.0));
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    /// e^(i self)
+    fn exp_i(self) -> Complex {
+        let scale = f32::exp(-self.im);
+        Complex::new(scale * f32::cos(self.re), scale * f32::sin(self.re))
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+    fn add(self, o: Complex) -> Complex {
+        Complex::new(self.re + o.re, self.im + o.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub(self, o: Complex) -> Complex {
+        Complex::new(self.re - o.re, self.im - o.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul(self, o: Complex) -> Complex {
+        Complex::new(
+            self.re * o.re - self.im * o.im,
+            self.re * o.im + self.im * o.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+    fn div(self, o: Complex) -> Complex {
+        let d = o.norm_sqr();
+        Complex::new(
+            (self.re * o.re + self.im * o.im) / d,
+            (self.im * o.re - self.re * o.im) / d,
+        )
+    }
+}
+
+/// A thin transparent layer on top of a surface, like oil on water, a soap
+/// film, or the oxide on heated metal. Light reflected off its top and
+/// bottom interferes, so the reflectance depends on wavelength and angle.
+#[derive(Copy, Clone, Debug)]
+pub struct ThinFilm {
+    /// In nanometers, interesting colors come from a few hundred.
+    pub thickness: f32,
+    pub index_of_refraction: f32,
+}
+
+impl ThinFilm {
+    /// Reflectance at `lambda` nanometers of the film on a substrate with
+    /// complex index of refraction `substrate`, for light arriving from a
+    /// medium of index `outside` at `cos_theta` to the normal. Unpolarized,
+    /// using the Airy summation over all the reflections inside the film.
+    fn reflectance(&self, cos_theta: f32, lambda: f32, outside: f32, substrate: Complex) -> f32 {
+        let n1 = Complex::real(outside);
+        let n2 = Complex::real(self.index_of_refraction);
+        let n3 = substrate;
+
+        // Snell's law, n sin(theta) is the same in every layer
+        let cos1 = Complex::real(cos_theta.clamp(0.0, 1.0));
+        let sin1 = outside * f32::sqrt(f32::max(0.0, 1.0 - cos_theta * cos_theta));
+        let cos_in = |n: Complex| {
+            let s = Complex::real(sin1) / n;
+            (Complex::real(1.0) - s * s).sqrt()
+        };
+        let cos2 = cos_in(n2);
+        let cos3 = cos_in(n3);
+
+        // phase difference between successive reflections
+        let phase = Complex::real(4.0 * PI * self.thickness / lambda) * n2 * cos2;
+        let shift = phase.exp_i();
+
+        let airy = |r12: Complex, r23: Complex| {
+            let r = (r12 + r23 * shift) / (Complex::real(1.0) + r12 * r23 * shift);
+            r.norm_sqr()
+        };
+        let rs = |na: Complex, ca: Complex, nb: Complex, cb: Complex| {
+            (na * ca - nb * cb) / (na * ca + nb * cb)
+        };
+        let rp = |na: Complex, ca: Complex, nb: Complex, cb: Complex| {
+            (nb * ca - na * cb) / (nb * ca + na * cb)
+        };
+
+        let s = airy(rs(n1, cos1, n2, cos2), rs(n2, cos2, n3, cos3));
+        let p = airy(rp(n1, cos1, n2, cos2), rp(n2, cos2, n3, cos3));
+        ((s + p) / 2.0).clamp(0.0, 1.0)
+    }
+
+    /// The reflectance as an RGB color.
+    fn reflectance_rgb(
+        &self,
+        cos_theta: f32,
+        outside: f32,
+        substrate: impl Fn(f32) -> Complex,
+    ) -> Color {
+        let rgb = spectrum::reflectance_to_rgb(|lambda| {
+            self.reflectance(cos_theta, lambda, outside, substrate(lambda))
+        });
+        Color::new(
+            rgb.x.clamp(0.0, 1.0),
+            rgb.y.clamp(0.0, 1.0),
+            rgb.z.clamp(0.0, 1.0),
+        )
+    }
+}
+
+/// Glass with a thin film on its outside, or with an index of refraction
+/// of one, a soap bubble. The film is thin enough that refracted light
+/// carries on in the same direction as without it. The outside of the film
+/// is taken to be air.
+#[derive(Copy, Clone, Debug)]
+pub struct IridescentDielectric {
+    pub index_of_refraction: f32,
+    pub film: ThinFilm,
+}
+
+impl IridescentDielectric {
+    pub fn new(index_of_refraction: f32, film: ThinFilm) -> IridescentDielectric {
+        IridescentDielectric {
+            index_of_refraction,
+            film,
+        }
+    }
+}
+
+impl Material for IridescentDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        let eta = if rec.front_face {
+            self.index_of_refraction
+        } else {
+            1.0 / self.index_of_refraction
+        };
+
+        self.scatter_interface(r_in, rec, eta, rng)
+    }
+
+    fn interior(&self) -> Option<Interior> {
+        Some(Interior {
+            index_of_refraction: self.index_of_refraction,
+            absorption: Color::new(0.0, 0.0, 0.0),
+            scattering: Color::new(0.0, 0.0, 0.0),
+            priority: 0,
+            dispersion: None,
+        })
+    }
+
+    fn scatter_interface(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        eta: f32,
+        rng: &mut dyn RngCore,
+    ) -> Option<(Color, Ray)> {
+        let uvw = rec.shading_frame();
+        let wo = uvw.to_local(-r_in.direction.unit_vector());
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        // indices relative to the side the light is on
+        let near = if rec.front_face {
+            1.0
+        } else {
+            self.index_of_refraction
+        };
+        let film = ThinFilm {
+            index_of_refraction: self.film.index_of_refraction / near,
+            // the phase depends on the film's absolute index
+            thickness: self.film.thickness * near,
+        };
+        let reflectance = film.reflectance_rgb(wo.z, 1.0, |_| Complex::real(eta));
+
+        // Reflect or refract in proportion to the average reflectance, and
+        // weight by the color of each.
+        let p = ((reflectance.x + reflectance.y + reflectance.z) / 3.0).clamp(0.0, 1.0);
+        let (attenuation, wi) = if rng.gen::<f32>() < p {
+            (reflectance / p, Vec3::new(-wo.x, -wo.y, wo.z))
+        } else {
+            let transmittance = Color::new(1.0, 1.0, 1.0) - reflectance;
+            let wi = microfacet::refract(wo, Vec3::new(0.0, 0.0, 1.0), eta)?;
+            (transmittance / (1.0 - p), wi)
+        };
+
+        let scattered = Ray {
+            origin: rec.p,
+            direction: uvw.local(wi.x, wi.y, wi.z),
+        };
+        Some((attenuation, scattered))
+    }
+}
+
+/// Rough metal with a thin film on top, like heat tinted steel or titanium.
+/// The metal's RGB index of refraction is interpolated across the spectrum.
+#[derive(Copy, Clone, Debug)]
+pub struct IridescentMetal {
+    pub eta: Color,
+    pub k: Color,
+    pub distribution: Ggx,
+    pub film: ThinFilm,
+}
+
+impl IridescentMetal {
+    pub fn new(eta: Color, k: Color, roughness: f32, film: ThinFilm) -> IridescentMetal {
+        IridescentMetal {
+            eta,
+            k,
+            distribution: Ggx::from_roughness(roughness, roughness),
+            film,
+        }
+    }
+
+    /// Complex index of refraction at `lambda`, from the RGB values placed
+    /// at the primaries' wavelengths.
+    fn substrate(&self, lambda: f32) -> Complex {
+        let lerp = |c: Color| {
+            if lambda < 532.0 {
+                let t = ((lambda - 465.0) / (532.0 - 465.0)).clamp(0.0, 1.0);
+                c.z + (c.y - c.z) * t
+            } else {
+                let t = ((lambda - 532.0) / (630.0 - 532.0)).clamp(0.0, 1.0);
+                c.y + (c.x - c.y) * t
+            }
+        };
+
+        Complex::new(lerp(self.eta), lerp(self.k))
+    }
+}
+
+impl Material for IridescentMetal {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        let uvw = rec.shading_frame();
+        let wo = uvw.to_local(-r_in.direction.unit_vector());
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let (wm, weight) = if self.distribution.is_smooth() {
+            (Vec3::new(0.0, 0.0, 1.0), 1.0)
+        } else {
+            let wm =
+                self.distribution
+                    .sample_visible_normal(wo, rng.gen::<f32>(), rng.gen::<f32>());
+            let wi = microfacet::reflect(wo, wm);
+            if wi.z <= 0.0 {
+                return None;
+            }
+            (wm, self.distribution.g(wo, wi) / self.distribution.g1(wo))
+        };
+
+        let wi = microfacet::reflect(wo, wm);
+        let f = self
+            .film
+            .reflectance_rgb(wo * wm, 1.0, |lambda| self.substrate(lambda));
+
+        let scattered = Ray {
+            origin: rec.p,
+            direction: uvw.local(wi.x, wi.y, wi.z),
+        };
+        Some((f * weight, scattered))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::rayhit::test_hit;
+
+    /// Average weight of the rays scattered back above the surface and of
+    /// those going through it.
+    fn furnace(mat: impl Material + 'static, cos_theta: f32, front_face: bool) -> (Color, Color) {
+        let mut rng = StdRng::seed_from_u64(39);
+        let (rec, r_in) = test_hit(Rc::new(mat), cos_theta, front_face);
+
+        let n = 20_000;
+        let mut reflected = Color::new(0.0, 0.0, 0.0);
+        let mut transmitted = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            if let Some((attenuation, scattered)) = rec.mat.scatter(&r_in, &rec, &mut rng) {
+                if scattered.direction.y > 0.0 {
+                    reflected += attenuation;
+                } else {
+                    transmitted += attenuation;
+                }
+            }
+        }
+
+        (reflected / n as f32, transmitted / n as f32)
+    }
+
+    #[test]
+    fn test_film_without_thickness() {
+        // is just the substrate
+        let film = ThinFilm {
+            thickness: 0.0,
+            index_of_refraction: 1.33,
+        };
+        for cos_theta in [1.0, 0.7, 0.3] {
+            let r = film.reflectance(cos_theta, 550.0, 1.0, Complex::real(1.5));
+            let expected = microfacet::fresnel_dielectric(cos_theta, 1.5);
+            assert!(f32::abs(r - expected) < 1e-4, "{} {}", r, expected);
+        }
+
+        let r = film.reflectance(0.8, 550.0, 1.0, Complex::new(0.2, 3.0));
+        let expected = microfacet::fresnel_conductor(0.8, 0.2, 3.0);
+        assert!(f32::abs(r - expected) < 1e-4, "{} {}", r, expected);
+    }
+
+    #[test]
+    fn test_quarter_and_half_wave_films() {
+        // A quarter wave film with an index halfway (geometrically) between
+        // the two sides is an anti-reflection coating, head on at its
+        // design wavelength.
+        let lambda = 550.0;
+        let n = f32::sqrt(1.5);
+        let coating = ThinFilm {
+            thickness: lambda / (4.0 * n),
+            index_of_refraction: n,
+        };
+        assert!(coating.reflectance(1.0, lambda, 1.0, Complex::real(1.5)) < 1e-4);
+        // but not at others
+        assert!(coating.reflectance(1.0, 400.0, 1.0, Complex::real(1.5)) > 1e-3);
+
+        // a half wave film has no effect
+        let film = ThinFilm {
+            thickness: lambda / (2.0 * 1.33),
+            index_of_refraction: 1.33,
+        };
+        let r = film.reflectance(1.0, lambda, 1.0, Complex::real(1.5));
+        assert!(f32::abs(r - 0.04) < 1e-4, "{}", r);
+    }
+
+    #[test]
+    fn test_soap_film_colors() {
+        // A soap film in air. Thinner than a quarter of the shortest visible
+        // wavelength it reflects almost nothing (the black film just before
+        // a bubble pops), thicker it's colored.
+        let black = ThinFilm {
+            thickness: 5.0,
+            index_of_refraction: 1.33,
+        };
+        let r = black.reflectance_rgb(1.0, 1.0, |_| Complex::real(1.0));
+        assert!(r.x < 0.01 && r.y < 0.01 && r.z < 0.01, "{:?}", r);
+
+        // at 400nm green interferes destructively, leaving magenta
+        let magenta = ThinFilm {
+            thickness: 400.0,
+            index_of_refraction: 1.33,
+        };
+        let r = magenta.reflectance_rgb(1.0, 1.0, |_| Complex::real(1.0));
+        assert!(r.x > r.y && r.z > r.y, "{:?}", r);
+    }
+
+    #[test]
+    fn test_iridescent_dielectric_furnace() {
+        // The film doesn't absorb, so whatever it doesn't reflect goes
+        // through, and what's reflected is the film's color.
+        let film = ThinFilm {
+            thickness: 400.0,
+            index_of_refraction: 1.33,
+        };
+        for index_of_refraction in [1.0, 1.5] {
+            for cos_theta in [1.0, 0.6, 0.2] {
+                let mat = IridescentDielectric::new(index_of_refraction, film);
+                let (reflected, transmitted) = furnace(mat, cos_theta, true);
+
+                let total = reflected + transmitted;
+                assert!(
+                    (total - Color::new(1.0, 1.0, 1.0)).length() < 0.01,
+                    "{} {} {:?}",
+                    index_of_refraction,
+                    cos_theta,
+                    total
+                );
+
+                let expected =
+                    film.reflectance_rgb(cos_theta, 1.0, |_| Complex::real(index_of_refraction));
+                assert!(
+                    (reflected - expected).length() < 0.01,
+                    "{} {} {:?} {:?}",
+                    index_of_refraction,
+                    cos_theta,
+                    reflected,
+                    expected
+                );
+            }
+        }
+
+        // A soap bubble is magenta head on
+        let (reflected, _) = furnace(IridescentDielectric::new(1.0, film), 1.0, true);
+        assert!(reflected.x > reflected.y && reflected.z > reflected.y);
+
+        // From inside the glass some light is trapped by total internal
+        // reflection, but none is made up.
+        for cos_theta in [1.0, 0.6, 0.2] {
+            let (reflected, transmitted) =
+                furnace(IridescentDielectric::new(1.5, film), cos_theta, false);
+            let total = reflected + transmitted;
+            assert!(
+                total.x < 1.01 && total.y < 1.01 && total.z < 1.01,
+                "{:?}",
+                total
+            );
+        }
+    }
+
+    #[test]
+    fn test_iridescent_metal_furnace() {
+        // A film that doesn't absorb on a perfect mirror reflects
+        // everything, so it loses no more to masking by the microfacets than
+        // the bare mirror does.
+        let mirror = |roughness, thickness| {
+            IridescentMetal::new(
+                Color::new(0.0, 0.0, 0.0),
+                Color::new(1e4, 1e4, 1e4),
+                roughness,
+                ThinFilm {
+                    thickness,
+                    index_of_refraction: 1.8,
+                },
+            )
+        };
+        for roughness in [0.0, 0.3, 0.8] {
+            for cos_theta in [1.0, 0.6, 0.2] {
+                let (reflected, transmitted) = furnace(mirror(roughness, 300.0), cos_theta, true);
+                let (bare, _) = furnace(mirror(roughness, 0.0), cos_theta, true);
+                assert_eq!(transmitted, Color::new(0.0, 0.0, 0.0));
+                assert!(
+                    (reflected - bare).length() < 0.01,
+                    "{} {} {:?} {:?}",
+                    roughness,
+                    cos_theta,
+                    reflected,
+                    bare
+                );
+                assert!(reflected.x <= 1.001 && reflected.y <= 1.001 && reflected.z <= 1.001);
+            }
+        }
+
+        // On real metal the film colors the reflection
+        let titanium = IridescentMetal::new(
+            Color::new(2.7, 2.5, 2.2),
+            Color::new(3.8, 3.4, 3.0),
+            0.2,
+            ThinFilm {
+                thickness: 250.0,
+                index_of_refraction: 2.4,
+            },
+        );
+        let (tinted, _) = furnace(titanium, 0.8, true);
+        let mut bare = titanium;
+        bare.film.thickness = 0.0;
+        let (plain, _) = furnace(bare, 0.8, true);
+        assert!((tinted - plain).length() > 0.05, "{:?} {:?}", tinted, plain);
+        assert!(tinted.x <= 1.0 && tinted.y <= 1.0 && tinted.z <= 1.0);
+    }
+}
@@ -0,0 +1,202 @@
+use rand::RngCore;
+
+use crate::aabb::Aabb;
+use crate::hittable::Hittable;
+use crate::rayhit::{HitRecord, Ray};
+use crate::vec3::{Point3, Vec3};
+
+/// Scales, then rotates about the x, y and z axes in turn, then moves.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    /// Degrees about each axis.
+    pub rotation: Vec3,
+    /// The same in every direction, so normals only need rotating.
+    pub scale: f32,
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Vec3::new(0.0, 0.0, 0.0),
+            scale: 1.0,
+        }
+    }
+}
+
+/// Rotate `v` by `angle` degrees about the x, y or z axis.
+fn rotate_about(v: Vec3, axis: usize, angle: f32) -> Vec3 {
+    let (sin, cos) = f32::sin_cos(angle.to_radians());
+    match axis {
+        0 => Vec3::new(v.x, cos * v.y - sin * v.z, sin * v.y + cos * v.z),
+        1 => Vec3::new(cos * v.x + sin * v.z, v.y, -sin * v.x + cos * v.z),
+        _ => Vec3::new(cos * v.x - sin * v.y, sin * v.x + cos * v.y, v.z),
+    }
+}
+
+impl Transform {
+    fn rotate(&self, v: Vec3) -> Vec3 {
+        let v = rotate_about(v, 0, self.rotation.x);
+        let v = rotate_about(v, 1, self.rotation.y);
+        rotate_about(v, 2, self.rotation.z)
+    }
+
+    fn unrotate(&self, v: Vec3) -> Vec3 {
+        let v = rotate_about(v, 2, -self.rotation.z);
+        let v = rotate_about(v, 1, -self.rotation.y);
+        rotate_about(v, 0, -self.rotation.x)
+    }
+
+    /// A point in the object's space to the world's.
+    pub fn point(&self, p: Point3) -> Point3 {
+        self.rotate(p * self.scale) + self.translation
+    }
+
+    /// A direction in the object's space to the world's, scaled along with
+    /// the object.
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.rotate(v * self.scale)
+    }
+
+    pub fn inverse_point(&self, p: Point3) -> Point3 {
+        self.unrotate(p - self.translation) / self.scale
+    }
+
+    pub fn inverse_vector(&self, v: Vec3) -> Vec3 {
+        self.unrotate(v) / self.scale
+    }
+}
+
+/// An object moved, turned and resized, so that it can be animated without
+/// knowing how.
+pub struct Transformed<H: Hittable> {
+    pub object: H,
+    pub transform: Transform,
+}
+
+impl<H: Hittable> Transformed<H> {
+    pub fn new(object: H, transform: Transform) -> Transformed<H> {
+        Transformed { object, transform }
+    }
+
+    /// The ray in the object's space. The direction is scaled with the
+    /// origin, so distances along the ray are the same in both spaces.
+    fn to_local(&self, ray: Ray) -> Ray {
+        Ray {
+            origin: self.transform.inverse_point(ray.origin),
+            direction: self.transform.inverse_vector(ray.direction),
+        }
+    }
+
+    fn to_world(&self, mut rec: HitRecord) -> HitRecord {
+        rec.p = self.transform.point(rec.p);
+        rec.normal = self.transform.vector(rec.normal).unit_vector();
+        rec.dpdu = self.transform.vector(rec.dpdu);
+        rec.dpdv = self.transform.vector(rec.dpdv);
+        rec
+    }
+}
+
+impl<H: Hittable> Hittable for Transformed<H> {
+    fn hit(&self, ray: Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let rec = self.object.hit(self.to_local(ray), t_min, t_max, rng)?;
+        Some(self.to_world(rec))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // the box around the corners of the object's box, moved
+        let b = self.object.bounding_box()?;
+        let mut bounds: Option<Aabb> = None;
+        for x in [b.min.x, b.max.x] {
+            for y in [b.min.y, b.max.y] {
+                for z in [b.min.z, b.max.z] {
+                    let p = self.transform.point(Point3::new(x, y, z));
+                    let corner = Aabb::new(p, p);
+                    bounds = Some(bounds.map_or(corner, |bounds| bounds.union(&corner)));
+                }
+            }
+        }
+        bounds
+    }
+
+    fn intersections(&self, ray: Ray, rng: &mut dyn RngCore) -> Vec<HitRecord> {
+        self.object
+            .intersections(self.to_local(ray), rng)
+            .into_iter()
+            .map(|rec| self.to_world(rec))
+            .collect()
+    }
+
+    // Lights aren't passed on, the light's sampling wouldn't know it had
+    // moved.
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::vec3::Color;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-4
+    }
+
+    #[test]
+    fn test_transform() {
+        let transform = Transform {
+            translation: Vec3::new(1.0, 2.0, 3.0),
+            rotation: Vec3::new(30.0, 90.0, 10.0),
+            scale: 2.0,
+        };
+        let p = Point3::new(0.3, -0.2, 0.7);
+        assert!(close(transform.inverse_point(transform.point(p)), p));
+
+        // a quarter turn about y takes +x to -z
+        let turn = Transform {
+            rotation: Vec3::new(0.0, 90.0, 0.0),
+            ..Transform::default()
+        };
+        assert!(close(
+            turn.vector(Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 0.0, -1.0)
+        ));
+    }
+
+    #[test]
+    fn test_transformed_hit() {
+        let sphere = Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            Rc::new(Lambertian {
+                albedo: Color::new(0.5, 0.5, 0.5),
+            }),
+        );
+        let moved = Transformed::new(
+            sphere,
+            Transform {
+                translation: Vec3::new(0.0, 0.0, -5.0),
+                scale: 2.0,
+                ..Transform::default()
+            },
+        );
+
+        let rec = moved
+            .hit(
+                Ray {
+                    origin: Point3::new(0.0, 0.0, 0.0),
+                    direction: Vec3::new(0.0, 0.0, -1.0),
+                },
+                0.001,
+                f32::INFINITY,
+                &mut rand::thread_rng(),
+            )
+            .unwrap();
+        assert!(close(rec.p, Point3::new(0.0, 0.0, -3.0)));
+        assert!(f32::abs(rec.t - 3.0) < 1e-4);
+        assert!(close(rec.normal, Vec3::new(0.0, 0.0, 1.0)));
+    }
+}
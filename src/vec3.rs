@@ -3,7 +3,7 @@
 
 use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub};
 
-use rand::Rng;
+use rand::{Rng, RngCore};
 
 #[derive(Copy, Clone, Debug, Default)]
 pub struct Vec3 {
@@ -29,8 +29,7 @@ impl Vec3 {
         self / self.length()
     }
 
-    pub fn random(min: f32, max: f32) -> Vec3 {
-        let mut rng = rand::thread_rng();
+    pub fn random(rng: &mut dyn RngCore, min: f32, max: f32) -> Vec3 {
         Vec3 {
             x: rng.gen_range(min..max),
             y: rng.gen_range(min..max),
@@ -38,9 +37,9 @@ impl Vec3 {
         }
     }
 
-    pub fn random_in_unit_sphere() -> Vec3 {
+    pub fn random_in_unit_sphere(rng: &mut dyn RngCore) -> Vec3 {
         loop {
-            let p = Vec3::random(-1.0, 1.0);
+            let p = Vec3::random(rng, -1.0, 1.0);
             if p.length_squared() >= 1.0 {
                 continue;
             }
@@ -48,24 +47,13 @@ impl Vec3 {
         }
     }
 
-    pub fn random_unit_vector() -> Vec3 {
+    pub fn random_unit_vector(rng: &mut dyn RngCore) -> Vec3 {
         // Not sure about this. Should maybe just be some util function outside of the impl.
-        Self::random_in_unit_sphere().unit_vector()
+        Self::random_in_unit_sphere(rng).unit_vector()
     }
 
-    pub fn random_in_hemisphere(normal: Vec3) -> Vec3 {
-        let in_unit_sphere = Self::random_in_unit_sphere();
-        // in the same hemisphere as normal
-        if in_unit_sphere * normal > 0.0 {
-            in_unit_sphere
-        } else {
-            -in_unit_sphere
-        }
-    }
-
-    pub fn random_in_unit_disk() -> Vec3 {
+    pub fn random_in_unit_disk(rng: &mut dyn RngCore) -> Vec3 {
         loop {
-            let mut rng = rand::thread_rng();
             let p = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
             if p.length_squared() >= 1.0 {
                 continue;